@@ -0,0 +1,323 @@
+use argon2::{
+    password_hash::{PasswordHash, PasswordHasher, PasswordVerifier, SaltString},
+    Argon2,
+};
+use axum::{
+    extract::{FromRequestParts, OptionalFromRequestParts, OriginalUri},
+    http::{header, request::Parts, HeaderMap},
+};
+use hmac::{Hmac, Mac};
+use rand_core::{OsRng, RngCore};
+use sha2::{Digest, Sha256};
+use std::sync::{Arc, OnceLock};
+
+use log::{debug, warn};
+
+use super::constants;
+use super::data::Datasources;
+use super::entity::{Permission, User};
+use super::error::ApiError;
+
+// Name of the cookie holding the session of a logged in user
+pub const SESSION_COOKIE: &str = "session";
+// Start of the personal API tokens, so they are easy to spot in logs and leaked files
+pub const API_TOKEN_PREFIX: &str = "xmt_";
+// What personal API tokens can be given access to
+pub const TOKEN_SCOPES: &[&str] = &["posts", "series", "media", "admin"];
+// Permissions needed for the site management endpoints (under /api/admin)
+pub const ADMIN_PERMISSIONS: &[Permission] = &[Permission::ManageUsers, Permission::ManageSite];
+
+// Whether the user may use the site management endpoints, and give the admin scope to their tokens.
+// The only admin rule: AdminUser and the token validation both go through it.
+pub fn can_manage(ds: &Datasources, user: &User) -> bool {
+    ADMIN_PERMISSIONS.iter().all(|permission| ds.can(user, *permission))
+}
+
+// Scope a personal API token needs for the path. None for the paths tokens cannot be used on
+// (the token endpoints themselves: a token cannot make more).
+fn required_scope(path: &str) -> Option<&'static str> {
+    let under = |prefix: &str| path == prefix || path.starts_with(&format!("{}/", prefix));
+    if under("/api/posts") {
+        Some("posts")
+    } else if under("/api/series") {
+        Some("series")
+    } else if under("/api/media") {
+        Some("media")
+    } else if under("/api/admin") {
+        Some("admin")
+    } else {
+        None
+    }
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn from_hex(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| hex.get(i..i + 2).and_then(|b| u8::from_str_radix(b, 16).ok()))
+        .collect()
+}
+
+// Hex encoded SHA-256 digest
+pub fn sha256_hex(bytes: &[u8]) -> String {
+    to_hex(&Sha256::digest(bytes))
+}
+
+// Digest of an API token or a session token. Only this is stored in the database.
+pub fn hash_api_key(key: &str) -> String {
+    sha256_hex(key.as_bytes())
+}
+
+// Argon2id hash of a password, in the PHC string format (with its parameters and salt)
+pub fn hash_password(password: &str) -> Result<String, argon2::password_hash::Error> {
+    let salt = SaltString::generate(&mut OsRng);
+    Ok(Argon2::default().hash_password(password.as_bytes(), &salt)?.to_string())
+}
+
+/**
+ * Whether the password matches the hash. Without a hash (unknown user, or no password set)
+ * a dummy hash is checked anyway, so the answer takes as long and does not tell which it was.
+ */
+pub fn verify_password(password: &str, password_hash: Option<&str>) -> bool {
+    static DUMMY_HASH: OnceLock<String> = OnceLock::new();
+    let dummy = DUMMY_HASH.get_or_init(|| hash_password("dummy password").unwrap_or_default());
+    let matches = PasswordHash::new(password_hash.unwrap_or(dummy))
+        .map(|hash| Argon2::default().verify_password(password.as_bytes(), &hash).is_ok())
+        .unwrap_or(false);
+    matches && password_hash.is_some()
+}
+
+// Random secret, hex encoded (session tokens, signing keys)
+pub fn random_token() -> String {
+    let mut bytes = [0u8; 32];
+    OsRng.fill_bytes(&mut bytes);
+    to_hex(&bytes)
+}
+
+/**
+ * Key signing the session cookies, from the session_secret of the config. Without one, or with
+ * the placeholder of config.json.example that anyone could sign cookies with, a random key is used.
+ */
+pub fn session_key(secret: Option<&str>) -> Vec<u8> {
+    match secret.map(str::trim) {
+        Some(secret) if secret == constants::EXAMPLE_SESSION_SECRET => {
+            warn!("session_secret is the example one from config.json.example, using a random key instead: \
+                   sessions will end when the server restarts");
+        },
+        Some(secret) if !secret.is_empty() => return secret.as_bytes().to_vec(),
+        _ => warn!("No session_secret in the config, sessions will end when the server restarts"),
+    }
+    random_token().into_bytes()
+}
+
+fn mac(key: &[u8], token: &str) -> Hmac<Sha256> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("HMAC takes keys of any size");
+    mac.update(token.as_bytes());
+    mac
+}
+
+// Cookie value of a session: the token and its HMAC, so forged cookies are refused without a lookup
+pub fn sign_session(key: &[u8], token: &str) -> String {
+    format!("{}.{}", token, to_hex(&mac(key, token).finalize().into_bytes()))
+}
+
+// Token of a session cookie value, None when the signature does not match
+pub fn verify_session<'a>(key: &[u8], value: &'a str) -> Option<&'a str> {
+    let (token, signature) = value.split_once('.')?;
+    mac(key, token).verify_slice(&from_hex(signature)?).ok()?;
+    Some(token)
+}
+
+/**
+ * Set-Cookie header value for the session. An empty value with no max age removes the cookie.
+ * The cookie is not readable from scripts, and not sent along with requests from other sites.
+ */
+pub fn session_cookie(value: &str, max_age_seconds: u64, secure: bool) -> String {
+    format!("{}={}; HttpOnly; SameSite=Lax; Path=/; Max-Age={}{}",
+            SESSION_COOKIE, value, max_age_seconds, if secure { "; Secure" } else { "" })
+}
+
+fn bearer_token(parts: &Parts) -> Option<&str> {
+    parts.headers
+        .get(header::AUTHORIZATION)
+        .and_then(|hv| hv.to_str().ok())
+        .and_then(|s| s.strip_prefix("Bearer "))
+        .map(str::trim)
+        .filter(|s| !s.is_empty())
+}
+
+// Value of the session cookie of the request, if any
+pub fn session_cookie_value(headers: &HeaderMap) -> Option<&str> {
+    headers
+        .get_all(header::COOKIE)
+        .iter()
+        .filter_map(|hv| hv.to_str().ok())
+        .flat_map(|s| s.split(';'))
+        .filter_map(|c| c.trim().split_once('='))
+        .find(|(name, _)| *name == SESSION_COOKIE)
+        .map(|(_, value)| value)
+}
+
+fn datasources(parts: &Parts) -> Result<Arc<Datasources>, ApiError> {
+    parts.extensions
+        .get::<Arc<Datasources>>()
+        .cloned()
+        .ok_or_else(|| ApiError::Internal("Datasources extension missing".to_string()))
+}
+
+/**
+ * User of the API token or of the session cookie, None when there is neither (or they are not valid).
+ * A token without the scope of the requested path is refused.
+ */
+fn authenticate(ds: &Datasources, parts: &Parts) -> Result<Option<User>, ApiError> {
+    if let Some(token) = bearer_token(parts) {
+        let Some((user, scopes)) = ds.db().use_api_token(&hash_api_key(token))? else {
+            return Ok(None);
+        };
+        // Routers nested under a path see the rest of it in `parts.uri`
+        let path = parts.extensions.get::<OriginalUri>().map_or(parts.uri.path(), |uri| uri.path());
+        return match required_scope(path) {
+            Some(scope) if scopes.iter().any(|s| s == scope) => Ok(Some(user)),
+            _ => {
+                debug!("Token of {} cannot be used for {}", user.name, path);
+                Err(ApiError::Forbidden)
+            }
+        };
+    }
+    match session_cookie_value(&parts.headers).and_then(|value| verify_session(ds.session_key(), value)) {
+        Some(token) => Ok(ds.db().get_user_by_session(&hash_api_key(token))?),
+        None => Ok(None),
+    }
+}
+
+// Extractor for handlers restricted to logged in users.
+// The request must carry `Authorization: Bearer <token>` or the cookie of a session.
+// Use `Option<CurrentUser>` for handlers open to everyone.
+pub struct CurrentUser(pub User);
+
+impl<S: Send + Sync> FromRequestParts<S> for CurrentUser {
+    type Rejection = ApiError;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        let ds = datasources(parts)?;
+        authenticate(&ds, parts)?.map(CurrentUser).ok_or(ApiError::Unauthorized)
+    }
+}
+
+impl<S: Send + Sync> OptionalFromRequestParts<S> for CurrentUser {
+    type Rejection = ApiError;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Option<Self>, Self::Rejection> {
+        let ds = datasources(parts)?;
+        match authenticate(&ds, parts) {
+            Ok(user) => Ok(user.map(CurrentUser)),
+            // A token sent outside its scopes counts as no login where logging in is optional
+            Err(ApiError::Forbidden) => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+}
+
+// Extractor for the site management handlers, and their only gate: a CurrentUser (whose token has the
+// admin scope) with all the ADMIN_PERMISSIONS. Handlers open to other roles take a CurrentUser and check its permissions (Datasources::can).
+pub struct AdminUser(pub User);
+
+impl<S: Send + Sync> FromRequestParts<S> for AdminUser {
+    type Rejection = ApiError;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let CurrentUser(user) = <CurrentUser as FromRequestParts<S>>::from_request_parts(parts, state).await?;
+        let ds = datasources(parts)?;
+        if !can_manage(&ds, &user) {
+            debug!("User {} cannot manage the site", user.name);
+            return Err(ApiError::Forbidden);
+        }
+        Ok(AdminUser(user))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::entity::Role;
+
+    #[test]
+    fn test_hash_api_key() {
+        // echo -n secret | sha256sum
+        assert_eq!(hash_api_key("secret"),
+                   "2bb80d537b1da3e38bd30361aa855686bde0eacd7162fef6a25fe97bf527a25b");
+    }
+
+    #[test]
+    fn test_session_key() {
+        assert_eq!(session_key(Some("my long secret")), b"my long secret");
+        for secret in [None, Some(""), Some("  "), Some(constants::EXAMPLE_SESSION_SECRET)] {
+            let key = session_key(secret);
+            assert_eq!(key.len(), 64);
+            assert_ne!(key, session_key(secret));
+        }
+    }
+
+    #[test]
+    fn test_can_manage() {
+        let ds = Datasources::for_tests();
+        let user = |role| User { id: 2, name: "bob".to_string(), role };
+        for role in Role::ALL {
+            assert_eq!(can_manage(&ds, &user(*role)), *role == Role::Admin);
+        }
+    }
+
+    #[test]
+    fn test_required_scope() {
+        assert_eq!(required_scope("/api/posts"), Some("posts"));
+        assert_eq!(required_scope("/api/posts/3/revisions"), Some("posts"));
+        assert_eq!(required_scope("/api/media/3/variants"), Some("media"));
+        assert_eq!(required_scope("/api/admin/users"), Some("admin"));
+        assert_eq!(required_scope("/api/postscript"), None);
+        assert_eq!(required_scope("/api/tokens"), None);
+        assert_eq!(required_scope("/login"), None);
+        assert!(TOKEN_SCOPES.iter().all(|scope| ["/api/posts", "/api/series", "/api/media", "/api/admin"]
+            .iter()
+            .any(|path| required_scope(path) == Some(scope))));
+    }
+
+    #[test]
+    fn test_passwords() {
+        let hash = hash_password("correct horse").unwrap();
+        assert!(hash.starts_with("$argon2id$"));
+        assert_ne!(hash, hash_password("correct horse").unwrap());
+        assert!(verify_password("correct horse", Some(&hash)));
+        assert!(!verify_password("wrong horse", Some(&hash)));
+        assert!(!verify_password("dummy password", None));
+        assert!(!verify_password("correct horse", Some("not a hash")));
+    }
+
+    #[test]
+    fn test_signed_sessions() {
+        let token = random_token();
+        assert_eq!(token.len(), 64);
+        let value = sign_session(b"key", &token);
+        assert_eq!(verify_session(b"key", &value), Some(token.as_str()));
+        assert_eq!(verify_session(b"other key", &value), None);
+        let forged = format!("{}.{}", random_token(), value.split_once('.').unwrap().1);
+        assert_eq!(verify_session(b"key", &forged), None);
+        assert_eq!(verify_session(b"key", &token), None);
+        assert_eq!(verify_session(b"key", &format!("{}.zz", token)), None);
+    }
+
+    #[test]
+    fn test_session_cookie() {
+        assert_eq!(session_cookie("abc", 60, true), "session=abc; HttpOnly; SameSite=Lax; Path=/; Max-Age=60; Secure");
+        assert_eq!(session_cookie("", 0, false), "session=; HttpOnly; SameSite=Lax; Path=/; Max-Age=0");
+        let mut headers = HeaderMap::new();
+        assert_eq!(session_cookie_value(&headers), None);
+        headers.insert(header::COOKIE, "theme=dark; session=abc.def".parse().unwrap());
+        assert_eq!(session_cookie_value(&headers), Some("abc.def"));
+    }
+}
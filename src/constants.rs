@@ -2,6 +2,38 @@
 // Some constants
 
 pub const PUBLIC_FOLDER: &str = "./static/public";
+// Where the uploaded files are served
+pub const MEDIA_URL: &str = "/media";
+// Bytes of a media upload request that are not the file
+pub const MULTIPART_OVERHEAD: usize = 64 * 1024;
 pub const HTML_CONTENT_TYPE: &str = "text/html; charset=utf-8";
 pub const JSON_CONTENT_TYPE: &str = "application/json";
+pub const ATOM_CONTENT_TYPE: &str = "application/atom+xml; charset=utf-8";
+pub const RSS_CONTENT_TYPE: &str = "application/rss+xml; charset=utf-8";
+pub const XML_CONTENT_TYPE: &str = "application/xml; charset=utf-8";
+pub const CSS_CONTENT_TYPE: &str = "text/css; charset=utf-8";
+pub const TEXT_CONTENT_TYPE: &str = "text/plain; charset=utf-8";
+pub const JSON_FEED_CONTENT_TYPE: &str = "application/feed+json; charset=utf-8";
 pub const DEFAULT_CONFIG_FILE: &str = "./config.json";
+// Paths under /notes/ that are not post slugs
+pub const RESERVED_SLUGS: &[&str] = &["post", "preview", "tag", "tags", "search", "archive", "series"];
+// Maximum number of search results returned
+pub const SEARCH_LIMIT: i32 = 50;
+// Number of posts per page in the notes listings
+pub const NOTES_PAGE_SIZE: u32 = 20;
+// Number of headings from which a post shows its table of contents
+pub const TOC_MIN_HEADINGS: usize = 3;
+// Number of characters of the automatic excerpts of the notes
+pub const EXCERPT_LENGTH: usize = 200;
+// Number of related posts suggested below a post
+pub const RELATED_POSTS: i32 = 5;
+// Seconds between the checks for changed posts, whose related posts are then computed again
+pub const RELATED_REFRESH_SECONDS: u64 = 10;
+// Days a login lasts
+pub const SESSION_DAYS: u32 = 30;
+// session_secret of config.json.example, never used as the key
+pub const EXAMPLE_SESSION_SECRET: &str = "replace with a long random string";
+// Limits of the profiles shown on the author pages
+pub const PROFILE_NAME_LENGTH: usize = 100;
+pub const PROFILE_BIO_LENGTH: usize = 2000;
+pub const PROFILE_LINKS: usize = 10;
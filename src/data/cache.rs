@@ -0,0 +1,148 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use super::super::entity::{CacheStats, ContentVersion};
+
+struct Entry {
+    body: String,
+    // Value of the clock when the entry was last read
+    last_used: u64,
+}
+
+struct Inner {
+    // Version of the content the entries were rendered from
+    version: Option<ContentVersion>,
+    entries: HashMap<String, Entry>,
+    clock: u64,
+}
+
+/**
+ * Rendered pages, keyed by route. Every entry is dropped when the content version changes,
+ * and the least recently used one is evicted when the cache is full.
+ */
+pub struct RenderCache {
+    capacity: usize,
+    inner: Mutex<Inner>,
+    hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+impl RenderCache {
+    // A capacity of 0 disables the cache
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            inner: Mutex::new(Inner {
+                version: None,
+                entries: HashMap::new(),
+                clock: 0,
+            }),
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+        }
+    }
+
+    pub fn get(&self, key: &str, version: ContentVersion) -> Option<String> {
+        let mut inner = self.inner.lock().unwrap();
+        if inner.version != Some(version) {
+            inner.entries.clear();
+            inner.version = Some(version);
+        }
+        inner.clock += 1;
+        let clock = inner.clock;
+        match inner.entries.get_mut(key) {
+            Some(entry) => {
+                entry.last_used = clock;
+                self.hits.fetch_add(1, Ordering::Relaxed);
+                Some(entry.body.clone())
+            },
+            None => {
+                self.misses.fetch_add(1, Ordering::Relaxed);
+                None
+            }
+        }
+    }
+
+    /**
+     * Stores a page rendered from `version`. Pages rendered from content that changed since
+     * are not stored.
+     */
+    pub fn insert(&self, key: String, version: ContentVersion, body: String) {
+        if self.capacity == 0 {
+            return;
+        }
+        let mut inner = self.inner.lock().unwrap();
+        if inner.version != Some(version) {
+            return;
+        }
+        if inner.entries.len() >= self.capacity && !inner.entries.contains_key(&key) {
+            // A linear scan is fine for the few hundred pages of the site
+            let oldest = inner.entries.iter().min_by_key(|(_, e)| e.last_used).map(|(k, _)| k.clone());
+            if let Some(oldest) = oldest {
+                inner.entries.remove(&oldest);
+            }
+        }
+        inner.clock += 1;
+        let last_used = inner.clock;
+        inner.entries.insert(key, Entry { body, last_used });
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        CacheStats {
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+            entries: self.inner.lock().unwrap().entries.len(),
+            capacity: self.capacity,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn helper_version(writes: u64) -> ContentVersion {
+        ContentVersion { writes }
+    }
+
+    #[test]
+    fn test_hits_and_misses() {
+        let cache = RenderCache::new(10);
+        assert_eq!(cache.get("/notes", helper_version(0)), None);
+        cache.insert("/notes".to_string(), helper_version(0), "page".to_string());
+        assert_eq!(cache.get("/notes", helper_version(0)), Some("page".to_string()));
+        let stats = cache.stats();
+        assert_eq!((stats.hits, stats.misses, stats.entries), (1, 1, 1));
+    }
+
+    #[test]
+    fn test_invalidation() {
+        let cache = RenderCache::new(10);
+        cache.get("/notes", helper_version(0));
+        cache.insert("/notes".to_string(), helper_version(0), "old".to_string());
+        assert_eq!(cache.get("/notes", helper_version(1)), None);
+        assert_eq!(cache.stats().entries, 0);
+        // Rendered before the change
+        cache.insert("/notes".to_string(), helper_version(0), "old".to_string());
+        assert_eq!(cache.get("/notes", helper_version(1)), None);
+    }
+
+    #[test]
+    fn test_lru_eviction() {
+        let cache = RenderCache::new(2);
+        let version = helper_version(0);
+        cache.get("a", version);
+        cache.insert("a".to_string(), version, "A".to_string());
+        cache.insert("b".to_string(), version, "B".to_string());
+        cache.get("a", version);
+        cache.insert("c".to_string(), version, "C".to_string());
+        assert_eq!(cache.get("b", version), None);
+        assert_eq!(cache.get("a", version), Some("A".to_string()));
+        assert_eq!(cache.get("c", version), Some("C".to_string()));
+        let disabled = RenderCache::new(0);
+        disabled.get("a", version);
+        disabled.insert("a".to_string(), version, "A".to_string());
+        assert_eq!(disabled.get("a", version), None);
+    }
+}
@@ -1,10 +1,12 @@
 
 //use std::env;
 use std::fs;
+use std::path::Path;
 
 use super::super::constants;
+use super::super::highlight;
+use super::super::markdown;
 
-use log;
 use serde::{Serialize, Deserialize};
 use serde_json::Error;
 
@@ -20,9 +22,97 @@ pub struct Config {
     pub host: String,
     pub db_file: String,
     pub static_files: String,
+    // Paths listed as Disallow in robots.txt
+    #[serde(default = "default_robots_disallow")]
+    pub robots_disallow: Vec<String>,
+    // Color theme of the code blocks, served as /highlight.css
+    #[serde(default = "default_highlight_theme")]
+    pub highlight_theme: String,
+    // Markdown extensions enabled in the notes (tables, footnotes, admonitions...)
+    #[serde(default = "default_markdown_extensions")]
+    pub markdown_extensions: Vec<String>,
+    // HTML tags allowed in the notes of admins, in addition to the usual formatting
+    #[serde(default = "default_admin_html_tags")]
+    pub admin_html_tags: Vec<String>,
+    // Same for the other authors
+    #[serde(default)]
+    pub author_html_tags: Vec<String>,
+    // Number of rendered pages kept in memory (0 disables the cache)
+    #[serde(default = "default_render_cache_size")]
+    pub render_cache_size: usize,
+    // Directory of the uploaded files, served at /media
+    #[serde(default = "default_media_dir")]
+    pub media_dir: String,
+    // Largest upload accepted, in bytes
+    #[serde(default = "default_media_max_size")]
+    pub media_max_size: usize,
+    // MIME types of the files that can be uploaded
+    #[serde(default = "default_media_types")]
+    pub media_types: Vec<String>,
+    // Widths of the resized copies of the uploaded images
+    #[serde(default = "default_image_widths")]
+    pub image_widths: Vec<u32>,
+    // JPEG quality of the resized photos (1 to 100)
+    #[serde(default = "default_image_quality")]
+    pub image_quality: u8,
+    // Key signing the session cookies. A random one is made at startup when missing,
+    // which logs everyone out on each restart.
+    #[serde(default)]
+    pub session_secret: Option<String>,
+    // Send the session cookie over HTTPS only
+    #[serde(default = "default_secure_cookies")]
+    pub secure_cookies: bool,
+}
+
+fn default_secure_cookies() -> bool {
+    true
+}
+
+fn default_image_widths() -> Vec<u32> {
+    vec![480, 960, 1440]
+}
+
+fn default_image_quality() -> u8 {
+    80
+}
+
+fn default_media_dir() -> String {
+    "./media".to_string()
+}
+
+fn default_media_max_size() -> usize {
+    10 * 1024 * 1024
+}
+
+fn default_media_types() -> Vec<String> {
+    ["image/png", "image/jpeg", "image/gif", "image/webp"].iter().map(|t| t.to_string()).collect()
+}
+
+fn default_render_cache_size() -> usize {
+    500
+}
+
+fn default_admin_html_tags() -> Vec<String> {
+    vec!["iframe".to_string()]
+}
+
+fn default_markdown_extensions() -> Vec<String> {
+    markdown::DEFAULT_EXTENSIONS.iter().map(|e| e.to_string()).collect()
+}
+
+fn default_highlight_theme() -> String {
+    highlight::DEFAULT_THEME.to_string()
+}
+
+fn default_robots_disallow() -> Vec<String> {
+    ["/api/", "/notes/preview/"].iter().map(|p| p.to_string()).collect()
 }
 
 impl Config {
+    pub fn media_path(&self) -> &Path {
+        Path::new(&self.media_dir)
+    }
+
     pub fn load() -> Self {
         Self::from_file(constants::DEFAULT_CONFIG_FILE).expect("Unable to load file ./config.json")
     }
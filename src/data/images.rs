@@ -0,0 +1,520 @@
+// Smaller copies of the uploaded images, for the srcset of the notes, and removal of the
+// location that cameras and phones write in the metadata (EXIF and XMP) of photos.
+
+use std::io::Cursor;
+
+use image::codecs::jpeg::JpegEncoder;
+use image::codecs::webp::WebPEncoder;
+use image::imageops::FilterType;
+use image::{DynamicImage, ImageDecoder, ImageReader, ImageResult};
+
+// Tag of the IFD0 entry pointing to the GPS IFD
+const GPS_IFD_TAG: u16 = 0x8825;
+
+// A resized copy of an image
+pub struct Variant {
+    pub width: u32,
+    pub height: u32,
+    pub content: Vec<u8>,
+}
+
+pub struct Resized {
+    // Size of the original, once rotated as its EXIF orientation says
+    pub width: u32,
+    pub height: u32,
+    pub variants: Vec<Variant>,
+}
+
+/**
+ * Extension of the variants made for an image of that type, None when it gets none.
+ * Photos stay JPEG, other images become lossless WebP (which keeps the transparency).
+ * GIFs are left alone as they may be animated.
+ */
+pub fn variant_extension(mime_type: &str) -> Option<&'static str> {
+    match mime_type {
+        "image/jpeg" => Some("jpg"),
+        "image/png" | "image/webp" => Some("webp"),
+        _ => None,
+    }
+}
+
+fn encode(image: &DynamicImage, extension: &str, quality: u8) -> ImageResult<Vec<u8>> {
+    let mut content = Vec::new();
+    match extension {
+        "jpg" => image.to_rgb8().write_with_encoder(JpegEncoder::new_with_quality(&mut content, quality))?,
+        _ => image.to_rgba8().write_with_encoder(WebPEncoder::new_lossless(&mut content))?,
+    }
+    Ok(content)
+}
+
+/**
+ * Size of the image and its variants at each of the `widths` narrower than it, encoded as
+ * `extension` (see variant_extension). The variants have no metadata.
+ */
+pub fn resize(content: &[u8], widths: &[u32], extension: &str, quality: u8) -> ImageResult<Resized> {
+    let mut decoder = ImageReader::new(Cursor::new(content)).with_guessed_format()?.into_decoder()?;
+    let orientation = decoder.orientation()?;
+    let mut image = DynamicImage::from_decoder(decoder)?;
+    image.apply_orientation(orientation);
+    let (width, height) = (image.width(), image.height());
+    let mut variants = Vec::new();
+    let mut sorted_widths = widths.to_vec();
+    sorted_widths.sort_unstable();
+    sorted_widths.dedup();
+    for variant_width in sorted_widths.into_iter().filter(|w| *w > 0 && *w < width) {
+        let variant_height = ((height as u64 * variant_width as u64 + width as u64 / 2) / width as u64).max(1) as u32;
+        let resized = image.resize_exact(variant_width, variant_height, FilterType::Lanczos3);
+        variants.push(Variant {
+            width: variant_width,
+            height: variant_height,
+            content: encode(&resized, extension, quality)?,
+        });
+    }
+    Ok(Resized { width, height, variants })
+}
+
+// Reads and writes the integers of a TIFF structure (the EXIF data) in its byte order
+struct Tiff<'a> {
+    data: &'a mut [u8],
+    little_endian: bool,
+}
+
+impl Tiff<'_> {
+    fn u16_at(&self, offset: usize) -> Option<u16> {
+        let bytes: [u8; 2] = self.data.get(offset..offset + 2)?.try_into().ok()?;
+        Some(if self.little_endian { u16::from_le_bytes(bytes) } else { u16::from_be_bytes(bytes) })
+    }
+
+    fn u32_at(&self, offset: usize) -> Option<u32> {
+        let bytes: [u8; 4] = self.data.get(offset..offset + 4)?.try_into().ok()?;
+        Some(if self.little_endian { u32::from_le_bytes(bytes) } else { u32::from_be_bytes(bytes) })
+    }
+
+    fn zero(&mut self, offset: usize, len: usize) -> Option<()> {
+        self.data.get_mut(offset..offset.checked_add(len)?)?.fill(0);
+        Some(())
+    }
+
+    // Offset of the GPS IFD, from its entry in IFD0
+    fn gps_ifd(&self) -> Option<usize> {
+        let ifd0 = self.u32_at(4)? as usize;
+        let count = self.u16_at(ifd0)? as usize;
+        (0..count)
+            .map(|i| ifd0 + 2 + 12 * i)
+            .find(|entry| self.u16_at(*entry) == Some(GPS_IFD_TAG))
+            .and_then(|entry| self.u32_at(entry + 8))
+            .map(|offset| offset as usize)
+    }
+
+    // Zeroes the entries of the GPS IFD and the values they point to, leaving an empty IFD
+    fn clear_gps(&mut self) -> Option<bool> {
+        let ifd = self.gps_ifd()?;
+        let count = self.u16_at(ifd)? as usize;
+        if count == 0 {
+            return Some(false);
+        }
+        for entry in (0..count).map(|i| ifd + 2 + 12 * i) {
+            let value_type = self.u16_at(entry + 2)?;
+            let values = self.u32_at(entry + 4)? as usize;
+            let type_size = match value_type {
+                3 | 8 => 2,
+                4 | 9 | 11 => 4,
+                5 | 10 | 12 => 8,
+                _ => 1,
+            };
+            // Values of more than 4 bytes are stored elsewhere
+            let size = values.saturating_mul(type_size);
+            if size > 4 {
+                let offset = self.u32_at(entry + 8)? as usize;
+                self.zero(offset, size)?;
+            }
+            self.zero(entry, 12)?;
+        }
+        // No entries, and the first zeroed entry now reads as "no next IFD"
+        self.zero(ifd, 2)?;
+        Some(true)
+    }
+}
+
+// Zeroes the GPS IFD of EXIF data: TIFF, maybe after the "Exif\0\0" header of JPEG
+fn clear_exif(exif: &mut [u8]) -> bool {
+    let start = if exif.starts_with(b"Exif\0\0") { 6 } else { 0 };
+    let tiff = &mut exif[start..];
+    let little_endian = match tiff.get(..4) {
+        Some(b"II*\0") => true,
+        Some(b"MM\0*") => false,
+        _ => return false,
+    };
+    Tiff { data: tiff, little_endian }.clear_gps().unwrap_or(false)
+}
+
+// Packet left in place of XMP data with a location, padded with spaces as XMP allows
+const EMPTY_XMP: &[u8] = b"<?xpacket begin=\"\" id=\"W5M0MpCehiHzreSzNTczkc9d\"?>\
+<x:xmpmeta xmlns:x=\"adobe:ns:meta/\"/><?xpacket end=\"w\"?>";
+
+// Replaces XMP data having a location (any of the exif:GPS properties) with an empty packet
+fn clear_xmp(xmp: &mut [u8]) -> bool {
+    if !xmp.windows(3).any(|w| w == b"GPS") {
+        return false;
+    }
+    xmp.fill(b' ');
+    if let Some(start) = xmp.get_mut(..EMPTY_XMP.len()) {
+        start.copy_from_slice(EMPTY_XMP);
+    }
+    true
+}
+
+// XMP segments of JPEG: the packet, and the rest of the packets too large for one segment
+const JPEG_XMP: &[u8] = b"http://ns.adobe.com/xap/1.0/\0";
+const JPEG_EXTENDED_XMP: &[u8] = b"http://ns.adobe.com/xmp/extension/\0";
+
+fn strip_jpeg(jpeg: &mut [u8]) -> bool {
+    let mut stripped = false;
+    let mut offset = 2;
+    // Segments up to the start of the image data
+    while let Some(&[0xff, marker, high, low]) = jpeg.get(offset..offset + 4) {
+        let length = u16::from_be_bytes([high, low]) as usize;
+        if marker == 0xda || length < 2 {
+            break;
+        }
+        let end = (offset + 2 + length).min(jpeg.len());
+        let segment = &mut jpeg[offset + 4..end];
+        if marker == 0xe1 {
+            stripped |= if segment.starts_with(b"Exif\0\0") {
+                clear_exif(segment)
+            } else if segment.starts_with(JPEG_XMP) {
+                clear_xmp(&mut segment[JPEG_XMP.len()..])
+            } else if segment.starts_with(JPEG_EXTENDED_XMP) && segment.windows(3).any(|w| w == b"GPS") {
+                // Part of a packet: blanked, as an empty packet only fits at the start
+                segment[JPEG_EXTENDED_XMP.len()..].fill(b' ');
+                true
+            } else {
+                false
+            };
+        }
+        offset = end;
+    }
+    stripped
+}
+
+// CRC-32 of the PNG chunks, computed over their type and data
+fn crc32(bytes: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for byte in bytes {
+        crc ^= *byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 == 1 { (crc >> 1) ^ 0xedb8_8320 } else { crc >> 1 };
+        }
+    }
+    !crc
+}
+
+// Keyword of the iTXt chunk holding XMP, then its compression flag
+const PNG_XMP: &[u8] = b"XML:com.adobe.xmp\0";
+
+// Start of the text of an uncompressed iTXt chunk, after the language and the translated keyword
+fn itxt_text(data: &[u8]) -> Option<usize> {
+    let flags = data.get(PNG_XMP.len()..PNG_XMP.len() + 2)?;
+    if flags[0] != 0 {
+        return None;
+    }
+    let language = PNG_XMP.len() + 2;
+    let keyword = language + data[language..].iter().position(|b| *b == 0)? + 1;
+    Some(keyword + data[keyword..].iter().position(|b| *b == 0)? + 1)
+}
+
+fn strip_png(png: &mut Vec<u8>) -> bool {
+    let mut stripped = false;
+    // Chunks after the signature: length, type, data and CRC
+    let mut offset = 8;
+    while let Some(header) = png.get(offset..offset + 8) {
+        let length = u32::from_be_bytes([header[0], header[1], header[2], header[3]]) as usize;
+        let chunk_type = [header[4], header[5], header[6], header[7]];
+        let data_end = offset + 8 + length;
+        if &chunk_type == b"IEND" || data_end + 4 > png.len() {
+            break;
+        }
+        let data = &mut png[offset + 8..data_end];
+        let changed = match &chunk_type {
+            b"eXIf" => clear_exif(data),
+            b"iTXt" if data.starts_with(PNG_XMP) => match itxt_text(data) {
+                Some(text) => clear_xmp(&mut data[text..]),
+                None => {
+                    // Compressed, so the chunk goes
+                    png.drain(offset..data_end + 4);
+                    stripped = true;
+                    continue;
+                }
+            },
+            _ => false,
+        };
+        if changed {
+            let crc = crc32(&png[offset + 4..data_end]);
+            png[data_end..data_end + 4].copy_from_slice(&crc.to_be_bytes());
+            stripped = true;
+        }
+        offset = data_end + 4;
+    }
+    stripped
+}
+
+fn strip_webp(webp: &mut [u8]) -> bool {
+    let mut stripped = false;
+    // Chunks after the RIFF header: type, little endian size and data padded to an even size
+    let mut offset = 12;
+    while let Some(header) = webp.get(offset..offset + 8) {
+        let chunk_type = [header[0], header[1], header[2], header[3]];
+        let size = u32::from_le_bytes([header[4], header[5], header[6], header[7]]) as usize;
+        let end = offset + 8 + size;
+        if end > webp.len() {
+            break;
+        }
+        let data = &mut webp[offset + 8..end];
+        stripped |= match &chunk_type {
+            b"EXIF" => clear_exif(data),
+            b"XMP " => clear_xmp(data),
+            _ => false,
+        };
+        offset = end + size % 2;
+    }
+    stripped
+}
+
+// Bytes of a GIF color table, from the flags of the screen or image descriptor
+fn color_table_size(flags: u8) -> usize {
+    if flags & 0x80 == 0 { 0 } else { 3 << ((flags & 7) + 1) }
+}
+
+// End of the data sub-blocks starting at `offset`: each is a length and data, the last one is empty
+fn skip_sub_blocks(gif: &[u8], mut offset: usize) -> Option<usize> {
+    loop {
+        let length = *gif.get(offset)? as usize;
+        offset += 1 + length;
+        if length == 0 {
+            return Some(offset);
+        }
+    }
+}
+
+fn strip_gif(gif: &mut Vec<u8>) -> bool {
+    let mut stripped = false;
+    // Header and logical screen descriptor, then the global color table
+    let Some(&flags) = gif.get(10) else { return false };
+    let mut offset = 13 + color_table_size(flags);
+    loop {
+        match gif.get(offset..offset + 2) {
+            // Extension: label and sub-blocks. The XMP application extension goes.
+            Some(&[0x21, label]) => {
+                let Some(end) = skip_sub_blocks(gif, offset + 2).filter(|end| *end <= gif.len()) else { break };
+                if label == 0xff && gif[offset + 2..end].starts_with(b"\x0bXMP DataXMP") {
+                    gif.drain(offset..end);
+                    stripped = true;
+                } else {
+                    offset = end;
+                }
+            }
+            // Image: descriptor, local color table, LZW code size and sub-blocks
+            Some(&[0x2c, _]) => {
+                let Some(&flags) = gif.get(offset + 9) else { break };
+                match skip_sub_blocks(gif, offset + 10 + color_table_size(flags) + 1) {
+                    Some(end) => offset = end,
+                    None => break,
+                }
+            }
+            // Trailer
+            _ => break,
+        }
+    }
+    stripped
+}
+
+/**
+ * Remove the location from the metadata of an image: the GPS data of EXIF, and the XMP
+ * packets having GPS properties. The rest (like the orientation) is kept, as well as the
+ * size of the content where possible. Returns whether there was a location.
+ */
+pub fn strip_location(content: &mut Vec<u8>, mime_type: &str) -> bool {
+    match mime_type {
+        "image/jpeg" if content.starts_with(&[0xff, 0xd8]) => strip_jpeg(content),
+        "image/png" if content.starts_with(b"\x89PNG\r\n\x1a\n") => strip_png(content),
+        "image/webp" if content.starts_with(b"RIFF") && content.get(8..12) == Some(b"WEBP") => strip_webp(content),
+        "image/gif" if content.starts_with(b"GIF8") => strip_gif(content),
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // EXIF block (little endian TIFF) having a GPS latitude
+    fn helper_exif() -> Vec<u8> {
+        let mut tiff: Vec<u8> = b"II*\0".to_vec();
+        tiff.extend(8u32.to_le_bytes());
+        // IFD0 at 8: orientation, GPS pointer
+        tiff.extend(2u16.to_le_bytes());
+        tiff.extend([0x12, 0x01, 3, 0, 1, 0, 0, 0, 6, 0, 0, 0]);
+        tiff.extend([0x25, 0x88, 4, 0, 1, 0, 0, 0]);
+        tiff.extend(38u32.to_le_bytes());
+        tiff.extend(0u32.to_le_bytes());
+        // GPS IFD at 38: latitude (3 rationals at 56)
+        tiff.extend(1u16.to_le_bytes());
+        tiff.extend([2, 0, 5, 0, 3, 0, 0, 0]);
+        tiff.extend(56u32.to_le_bytes());
+        tiff.extend(0u32.to_le_bytes());
+        tiff.extend(LATITUDE);
+        tiff
+    }
+
+    const LATITUDE: [u8; 24] = [35, 0, 0, 0, 1, 0, 0, 0, 41, 0, 0, 0, 1, 0, 0, 0, 7, 0, 0, 0, 1, 0, 0, 0];
+    const XMP: &[u8] = b"<x:xmpmeta xmlns:x=\"adobe:ns:meta/\"><rdf:RDF><rdf:Description \
+        exif:GPSLatitude=\"35,41.7N\"/></rdf:RDF></x:xmpmeta>                                   ";
+
+    fn has_location(content: &[u8]) -> bool {
+        content.windows(LATITUDE.len()).any(|w| w == LATITUDE) || content.windows(3).any(|w| w == b"GPS")
+    }
+
+    // JPEG segments with an EXIF block and an XMP packet
+    fn helper_exif_jpeg() -> Vec<u8> {
+        let tiff = helper_exif();
+        let mut jpeg = vec![0xff, 0xd8, 0xff, 0xe1];
+        jpeg.extend(((tiff.len() + 8) as u16).to_be_bytes());
+        jpeg.extend(b"Exif\0\0");
+        jpeg.extend(tiff);
+        jpeg.extend([0xff, 0xe1]);
+        jpeg.extend(((JPEG_XMP.len() + XMP.len() + 2) as u16).to_be_bytes());
+        jpeg.extend(JPEG_XMP);
+        jpeg.extend(XMP);
+        jpeg.extend([0xff, 0xda, 0, 2, 1, 2, 3]);
+        jpeg
+    }
+
+    fn helper_image(format: image::ImageFormat) -> Vec<u8> {
+        let mut content = Vec::new();
+        DynamicImage::new_rgba8(4, 2).write_to(&mut Cursor::new(&mut content), format).unwrap();
+        content
+    }
+
+    fn helper_png_chunk(chunk_type: &[u8], data: &[u8]) -> Vec<u8> {
+        let mut chunk = (data.len() as u32).to_be_bytes().to_vec();
+        chunk.extend(chunk_type);
+        chunk.extend(data);
+        chunk.extend(crc32(&chunk[4..]).to_be_bytes());
+        chunk
+    }
+
+    #[test]
+    fn test_strip_location_jpeg() {
+        let mut jpeg = helper_exif_jpeg();
+        let length = jpeg.len();
+        let tiff_start = 4 + 2 + 6;
+        assert!(strip_location(&mut jpeg, "image/jpeg"));
+        assert_eq!(jpeg.len(), length);
+        // The latitude and the GPS entries are gone, the orientation stays
+        assert!(jpeg[tiff_start + 38..tiff_start + 80].iter().all(|b| *b == 0));
+        assert_eq!(jpeg[tiff_start + 10..tiff_start + 22], [0x12, 0x01, 3, 0, 1, 0, 0, 0, 6, 0, 0, 0]);
+        // The XMP packet is emptied
+        assert!(!has_location(&jpeg));
+        assert!(jpeg.windows(EMPTY_XMP.len()).any(|w| w == EMPTY_XMP));
+        assert_eq!(jpeg[length - 7..], [0xff, 0xda, 0, 2, 1, 2, 3]);
+        assert!(!strip_location(&mut jpeg, "image/jpeg"));
+        // Not a JPEG, truncated
+        assert!(!strip_location(&mut b"\x89PNG".to_vec(), "image/jpeg"));
+        let mut truncated = helper_exif_jpeg()[..40].to_vec();
+        assert!(!strip_location(&mut truncated, "image/jpeg"));
+    }
+
+    #[test]
+    fn test_strip_location_png() {
+        let png = helper_image(image::ImageFormat::Png);
+        // After the signature and IHDR
+        let (head, tail) = png.split_at(8 + 25);
+        let mut compressed_xmp = PNG_XMP.to_vec();
+        compressed_xmp.extend([1, 0, 0, 0, 0x78, 0x9c]);
+        let mut xmp = PNG_XMP.to_vec();
+        xmp.extend([0, 0, 0, 0]);
+        xmp.extend(XMP);
+        let mut with_location = head.to_vec();
+        with_location.extend(helper_png_chunk(b"eXIf", &helper_exif()));
+        with_location.extend(helper_png_chunk(b"iTXt", &xmp));
+        with_location.extend(helper_png_chunk(b"iTXt", &compressed_xmp));
+        with_location.extend(tail);
+        assert!(has_location(&with_location));
+
+        let mut stripped = with_location.clone();
+        assert!(strip_location(&mut stripped, "image/png"));
+        assert!(!has_location(&stripped));
+        // The compressed packet is removed, the others keep their size
+        assert_eq!(stripped.len(), with_location.len() - 12 - compressed_xmp.len());
+        // The CRCs were updated, which the decoder checks
+        assert!(image::load_from_memory_with_format(&stripped, image::ImageFormat::Png).is_ok());
+        assert!(!strip_location(&mut stripped, "image/png"));
+        let mut plain = png.clone();
+        assert!(!strip_location(&mut plain, "image/png"));
+        assert_eq!(plain, png);
+    }
+
+    #[test]
+    fn test_strip_location_webp() {
+        let webp = helper_image(image::ImageFormat::WebP);
+        let mut with_location = webp.clone();
+        let exif = helper_exif();
+        with_location.extend(b"EXIF");
+        with_location.extend((exif.len() as u32).to_le_bytes());
+        with_location.extend(&exif);
+        with_location.extend(b"XMP ");
+        with_location.extend((XMP.len() as u32).to_le_bytes());
+        with_location.extend(XMP);
+        let riff_size = (with_location.len() - 8) as u32;
+        with_location[4..8].copy_from_slice(&riff_size.to_le_bytes());
+
+        let mut stripped = with_location.clone();
+        assert!(strip_location(&mut stripped, "image/webp"));
+        assert!(!has_location(&stripped));
+        assert_eq!(stripped.len(), with_location.len());
+        assert_eq!(stripped[..webp.len()], with_location[..webp.len()]);
+        assert!(!strip_location(&mut stripped, "image/webp"));
+        // The type must match the content
+        assert!(!strip_location(&mut with_location.clone(), "image/png"));
+    }
+
+    #[test]
+    fn test_strip_location_gif() {
+        let gif = helper_image(image::ImageFormat::Gif);
+        // XMP application extension before the first block after the color table
+        let flags = gif[10];
+        let start = 13 + color_table_size(flags);
+        let mut extension = b"\x21\xff\x0bXMP DataXMP".to_vec();
+        for block in XMP.chunks(255) {
+            extension.push(block.len() as u8);
+            extension.extend(block);
+        }
+        extension.push(0);
+        let mut with_location = gif[..start].to_vec();
+        with_location.extend(&extension);
+        with_location.extend(&gif[start..]);
+
+        let mut stripped = with_location.clone();
+        assert!(strip_location(&mut stripped, "image/gif"));
+        assert_eq!(stripped, gif);
+        assert!(!strip_location(&mut stripped, "image/gif"));
+    }
+
+    #[test]
+    fn test_resize() {
+        let mut png = Vec::new();
+        DynamicImage::new_rgba8(100, 50).write_to(&mut Cursor::new(&mut png), image::ImageFormat::Png).unwrap();
+        let resized = resize(&png, &[80, 40, 200, 40], "webp", 80).unwrap();
+        assert_eq!((resized.width, resized.height), (100, 50));
+        let sizes: Vec<(u32, u32)> = resized.variants.iter().map(|v| (v.width, v.height)).collect();
+        assert_eq!(sizes, vec![(40, 20), (80, 40)]);
+        assert!(resized.variants[0].content.starts_with(b"RIFF"));
+
+        let mut jpeg = Vec::new();
+        DynamicImage::new_rgb8(30, 90).write_to(&mut Cursor::new(&mut jpeg), image::ImageFormat::Jpeg).unwrap();
+        let resized = resize(&jpeg, &[10], "jpg", 80).unwrap();
+        assert_eq!((resized.variants[0].width, resized.variants[0].height), (10, 30));
+        assert!(resized.variants[0].content.starts_with(&[0xff, 0xd8]));
+        assert!(resize(b"\x89PNG\r\n\x1a\nbroken", &[10], "webp", 80).is_err());
+    }
+}
@@ -1,13 +1,30 @@
-use rusqlite;
-use rusqlite::{Connection, params};
+use rusqlite::{Connection, OptionalExtension, params};
 use log::{error,debug, info};
+use std::fs;
+use std::path::PathBuf;
 use std::sync::Mutex;
+use std::sync::atomic::{AtomicBool, AtomicI64, AtomicU64, Ordering};
+use std::time::SystemTime;
 
-use super::super::entity::{User, PostIdent, Post};
+use super::super::entity::{ApiToken, ContentVersion, PostAuthor, PostSummary, Profile, ProfileInput, Role, User, PostIdent, Post, PostInput, PostStatus, PostRevision, ImageVariant, Media, MediaUpload, Series, SeriesInput, SeriesNav, TagCount, SearchResult, ArchivePeriod};
+use super::super::constants;
+use super::slug;
+use super::search;
+use super::summary;
+use super::related;
 
 // Struct for interacting with a SQLite database
 pub struct LiteDB {
     conn: Mutex<Connection>,
+    // Number of changes made to the posts through this instance, or seen by content_version
+    writes: AtomicU64,
+    // When the next scheduled post becomes visible (ms since epoch), i64::MIN when unknown
+    next_publication: AtomicI64,
+    // Database file, and its modification time when content_version last looked at it
+    file: Option<PathBuf>,
+    file_modified: Mutex<Option<SystemTime>>,
+    // Whether the related posts must be computed again before being read
+    related_stale: AtomicBool,
 }
 
 const BLOG_TABLES_SQL: &str = "BEGIN;
@@ -69,15 +86,388 @@ CREATE TABLE chat_log(
 COMMIT;
 ";
 
+// Schema changes applied on top of the tables above.
+// Entry N brings the database to `PRAGMA user_version` N+1, so only append to this list.
+const MIGRATIONS: &[&str] = &[
+    // 1: hashed API keys for the JSON API
+    "ALTER TABLE user ADD COLUMN api_key_hash TEXT;
+CREATE UNIQUE INDEX user_api_key_hash_idx ON user(api_key_hash);",
+    // 2: URL slugs for posts (existing posts get one from backfill_slugs,
+    // rows inserted by hand without one fall back to post-<id>)
+    "ALTER TABLE post ADD COLUMN slug TEXT;
+CREATE UNIQUE INDEX post_slug_idx ON post(slug);
+CREATE TRIGGER post_default_slug AFTER INSERT ON post WHEN NEW.slug IS NULL
+BEGIN
+  UPDATE post SET slug = 'post-' || NEW.id WHERE id = NEW.id;
+END;",
+    // 3: full-text index over posts, kept in sync by triggers (see search.rs for the tokenizer choice)
+    "CREATE VIRTUAL TABLE post_fts USING fts5(title, content, content='post', content_rowid='id', tokenize='trigram');
+CREATE TRIGGER post_fts_insert AFTER INSERT ON post
+BEGIN
+  INSERT INTO post_fts(rowid, title, content) VALUES (NEW.id, NEW.title, NEW.content);
+END;
+CREATE TRIGGER post_fts_delete AFTER DELETE ON post
+BEGIN
+  INSERT INTO post_fts(post_fts, rowid, title, content) VALUES ('delete', OLD.id, OLD.title, OLD.content);
+END;
+CREATE TRIGGER post_fts_update AFTER UPDATE OF title, content ON post
+BEGIN
+  INSERT INTO post_fts(post_fts, rowid, title, content) VALUES ('delete', OLD.id, OLD.title, OLD.content);
+  INSERT INTO post_fts(rowid, title, content) VALUES (NEW.id, NEW.title, NEW.content);
+END;
+INSERT INTO post_fts(post_fts) VALUES ('rebuild');",
+    // 4: drafts and scheduled posts, with a secret token for previewing them
+    "ALTER TABLE post ADD COLUMN status TEXT NOT NULL DEFAULT 'published';
+ALTER TABLE post ADD COLUMN published_at TIMESTAMP;
+ALTER TABLE post ADD COLUMN preview_token TEXT;
+UPDATE post SET published_at = created_at, preview_token = lower(hex(randomblob(16)));
+CREATE UNIQUE INDEX post_preview_token_idx ON post(preview_token);
+CREATE INDEX post_published_idx ON post(status, published_at);
+CREATE TRIGGER post_default_publication AFTER INSERT ON post
+BEGIN
+  UPDATE post SET preview_token = COALESCE(preview_token, lower(hex(randomblob(16)))),
+                  published_at = CASE WHEN status = 'published' THEN COALESCE(published_at, created_at) ELSE published_at END
+  WHERE id = NEW.id;
+END;",
+    // 5: previous versions of posts, saved whenever the title or content changes
+    "CREATE TABLE post_revision(
+  id INTEGER PRIMARY KEY,
+  post_id INTEGER NOT NULL,
+  title TEXT,
+  content TEXT,
+  created_at TIMESTAMP,
+  FOREIGN KEY(post_id) REFERENCES post(id) ON DELETE CASCADE
+);
+CREATE INDEX post_revision_post_idx ON post_revision(post_id);
+CREATE TRIGGER post_save_revision AFTER UPDATE OF title, content ON post
+WHEN OLD.title IS NOT NEW.title OR OLD.content IS NOT NEW.content
+BEGIN
+  INSERT INTO post_revision(post_id, title, content, created_at) VALUES (OLD.id, OLD.title, OLD.content, OLD.updated_at);
+END;",
+    // 6: markdown file (relative to the synced directory) that a post was imported from
+    "ALTER TABLE post ADD COLUMN source_path TEXT;",
+    // 7: precomputed scores of the posts related to each post
+    "CREATE TABLE post_related(
+  post_id INTEGER NOT NULL,
+  related_id INTEGER NOT NULL,
+  score REAL NOT NULL,
+  PRIMARY KEY(post_id, related_id),
+  FOREIGN KEY(post_id) REFERENCES post(id) ON DELETE CASCADE,
+  FOREIGN KEY(related_id) REFERENCES post(id) ON DELETE CASCADE
+);",
+    // 8: series of posts meant to be read in order
+    "CREATE TABLE series(
+  id INTEGER PRIMARY KEY,
+  slug TEXT NOT NULL UNIQUE,
+  title TEXT NOT NULL,
+  description TEXT NOT NULL DEFAULT ''
+);
+CREATE TABLE series_post(
+  post_id INTEGER PRIMARY KEY,
+  series_id INTEGER NOT NULL,
+  position INTEGER NOT NULL,
+  FOREIGN KEY(post_id) REFERENCES post(id) ON DELETE CASCADE,
+  FOREIGN KEY(series_id) REFERENCES series(id) ON DELETE CASCADE
+);
+CREATE INDEX series_post_series_idx ON series_post(series_id, position);",
+    // 9: uploaded files, named after the digest of their content
+    "CREATE TABLE media(
+  id INTEGER PRIMARY KEY,
+  hash TEXT NOT NULL UNIQUE,
+  file_name TEXT NOT NULL,
+  original_name TEXT,
+  mime_type TEXT NOT NULL,
+  size INTEGER NOT NULL,
+  post_id INTEGER,
+  uploader_id INTEGER,
+  created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
+  FOREIGN KEY(post_id) REFERENCES post(id) ON DELETE SET NULL,
+  FOREIGN KEY(uploader_id) REFERENCES user(id) ON DELETE SET NULL
+);
+CREATE INDEX media_post_idx ON media(post_id);",
+    // 10: size of the uploaded images and their resized copies
+    "ALTER TABLE media ADD COLUMN width INTEGER;
+ALTER TABLE media ADD COLUMN height INTEGER;
+CREATE TABLE media_variant(
+  media_id INTEGER NOT NULL,
+  width INTEGER NOT NULL,
+  height INTEGER NOT NULL,
+  file_name TEXT NOT NULL,
+  size INTEGER NOT NULL,
+  PRIMARY KEY(media_id, width),
+  FOREIGN KEY(media_id) REFERENCES media(id) ON DELETE CASCADE
+);",
+    // 11: passwords, and the sessions of the users who logged in with them
+    "ALTER TABLE user ADD COLUMN password_hash TEXT;
+CREATE TABLE session(
+  token_hash TEXT PRIMARY KEY,
+  user_id INTEGER NOT NULL,
+  created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
+  expires_at TIMESTAMP NOT NULL,
+  FOREIGN KEY(user_id) REFERENCES user(id) ON DELETE CASCADE
+);
+CREATE INDEX session_user_idx ON session(user_id);",
+    // 12: personal API tokens, each limited to some parts of the API (scopes, space separated)
+    "CREATE TABLE api_token(
+  id INTEGER PRIMARY KEY AUTOINCREMENT,
+  user_id INTEGER NOT NULL,
+  name TEXT NOT NULL,
+  token_hash TEXT NOT NULL UNIQUE,
+  scopes TEXT NOT NULL,
+  created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
+  expires_at TIMESTAMP,
+  last_used_at TIMESTAMP,
+  FOREIGN KEY(user_id) REFERENCES user(id) ON DELETE CASCADE
+);
+CREATE INDEX api_token_user_idx ON api_token(user_id);",
+    // 13: roles instead of the is_admin flag. The other users had no rights, so they become
+    // commenters until an admin gives them a role.
+    "ALTER TABLE user ADD COLUMN role TEXT NOT NULL DEFAULT 'commenter';
+UPDATE user SET role = CASE WHEN is_admin != 0 THEN 'admin' ELSE 'commenter' END;
+ALTER TABLE user DROP COLUMN is_admin;",
+    // 14: profiles shown on the author pages (links is a JSON list of {title, url})
+    "ALTER TABLE user ADD COLUMN display_name TEXT;
+ALTER TABLE user ADD COLUMN bio TEXT NOT NULL DEFAULT '';
+ALTER TABLE user ADD COLUMN links TEXT NOT NULL DEFAULT '[]';",
+    // 15: summaries shown by the listings, stored on every write of the content
+    // (existing posts get theirs from backfill_summaries)
+    "ALTER TABLE post ADD COLUMN word_count INTEGER;
+ALTER TABLE post ADD COLUMN reading_minutes INTEGER;
+ALTER TABLE post ADD COLUMN excerpt TEXT;",
+    // 16: posts each uploaded file is used by, as the same file can be uploaded for several posts.
+    // media.post_id is left empty from now on (SQLite can't drop a column with a foreign key).
+    "CREATE TABLE media_post(
+  media_id INTEGER NOT NULL,
+  post_id INTEGER NOT NULL,
+  PRIMARY KEY(media_id, post_id),
+  FOREIGN KEY(media_id) REFERENCES media(id) ON DELETE CASCADE,
+  FOREIGN KEY(post_id) REFERENCES post(id) ON DELETE CASCADE
+);
+CREATE INDEX media_post_post_idx ON media_post(post_id);
+INSERT INTO media_post (media_id, post_id) SELECT id, post_id FROM media WHERE post_id IS NOT NULL;
+UPDATE media SET post_id = NULL;",
+    // 17: the API keys of the users become API tokens with every scope their role can use,
+    // so they can be listed and revoked, and expire like the others
+    "INSERT INTO api_token (user_id, name, token_hash, scopes)
+SELECT id, 'API key', api_key_hash, CASE WHEN role = 'admin' THEN 'posts series media admin' ELSE 'posts series media' END
+FROM user WHERE api_key_hash IS NOT NULL;
+DROP INDEX user_api_key_hash_idx;
+ALTER TABLE user DROP COLUMN api_key_hash;",
+    // 18: whether the content of each post was last written by someone trusted with more HTML,
+    // set on every write. Existing posts get it from the role of their author.
+    "ALTER TABLE post ADD COLUMN trusted_html INTEGER NOT NULL DEFAULT 0;
+UPDATE post SET trusted_html = 1 WHERE author_id IN (SELECT id FROM user WHERE role = 'admin');",
+];
+
+// Posts anyone can see: published, and not scheduled for later
+const VISIBLE: &str = "p.status = 'published' AND p.published_at <= CURRENT_TIMESTAMP";
+
+// Columns read by ident_from_row
+const IDENT_COLUMNS: &str = "p.id, p.title, strftime('%s', p.created_at), p.slug, strftime('%s', p.published_at), \
+    p.word_count, p.reading_minutes, p.excerpt";
+// Columns read by post_from_row (starts with IDENT_COLUMNS)
+const POST_COLUMNS: &str = "p.id, p.title, strftime('%s', p.created_at), p.slug, strftime('%s', p.published_at), \
+    p.word_count, p.reading_minutes, p.excerpt, p.content, strftime('%s', p.updated_at), \
+    CASE WHEN p.status = 'draft' THEN 'draft' WHEN p.published_at > CURRENT_TIMESTAMP THEN 'scheduled' ELSE 'published' END, \
+    p.preview_token, p.source_path, p.trusted_html, a.id, a.username, COALESCE(a.display_name, a.username)";
+// Tables POST_COLUMNS are selected from
+const POST_TABLES: &str = "post p LEFT JOIN user a ON a.id = p.author_id";
+
+// Converts a unix time in seconds (as returned by strftime('%s', ...)) to milliseconds
+fn to_millis(seconds: &str) -> i64 {
+    seconds.parse::<i64>().unwrap_or_else(|e| {
+        info!("Timestamp cannot be read. {}, returning 0", e);
+        0
+    }) * 1000
+}
+
+const API_TOKEN_COLUMNS: &str = "id, name, scopes, strftime('%s', created_at), strftime('%s', expires_at), strftime('%s', last_used_at)";
+
+// Maps a row selected with API_TOKEN_COLUMNS
+fn api_token_from_row(row: &rusqlite::Row) -> rusqlite::Result<ApiToken> {
+    let scopes: String = row.get(2)?;
+    Ok(ApiToken {
+        id: row.get(0)?,
+        name: row.get(1)?,
+        scopes: scopes.split_whitespace().map(str::to_string).collect(),
+        created: to_millis(&row.get::<_, String>(3)?),
+        expires: row.get::<_, Option<String>>(4)?.as_deref().map(to_millis),
+        last_used: row.get::<_, Option<String>>(5)?.as_deref().map(to_millis),
+    })
+}
+
+const PROFILE_COLUMNS: &str = "id, username, display_name, bio, links";
+
+// Maps a row selected with PROFILE_COLUMNS
+fn profile_from_row(row: &rusqlite::Row) -> rusqlite::Result<Profile> {
+    let username: String = row.get(1)?;
+    let display_name: Option<String> = row.get(2)?;
+    let links: String = row.get(4)?;
+    Ok(Profile {
+        id: row.get(0)?,
+        name: display_name.clone().unwrap_or_else(|| username.clone()),
+        username,
+        display_name,
+        bio: row.get(3)?,
+        links: serde_json::from_str(&links).unwrap_or_else(|e| {
+            error!("Links of user {} cannot be read. {}", row.get::<_, u32>(0).unwrap_or(0), e);
+            Vec::new()
+        }),
+    })
+}
+
+// Maps a row starting with `u.id, u.username, u.role`
+fn user_from_row(row: &rusqlite::Row) -> rusqlite::Result<User> {
+    let role: String = row.get(2)?;
+    Ok(User {
+        id: row.get(0)?,
+        name: row.get(1)?,
+        // An unknown role gets the fewest permissions
+        role: Role::from_stored(&role).unwrap_or_else(|| {
+            error!("User {} has an unknown role {}", row.get::<_, u32>(0).unwrap_or(0), role);
+            Role::Commenter
+        }),
+    })
+}
+
+// Maps a row selected with IDENT_COLUMNS. Tags are filled in separately.
+fn ident_from_row(row: &rusqlite::Row) -> rusqlite::Result<PostIdent> {
+    // created_at is read as string.
+    // represents unix time in seconds.
+    let created_at: String = row.get(2)?;
+    let published_at: Option<String> = row.get(4)?;
+    Ok(
+        PostIdent {
+            id: row.get(0)?,
+            title: row.get(1)?,
+            created: to_millis(&created_at),
+            slug: row.get(3)?,
+            published: published_at.as_deref().map(to_millis),
+            tags: Vec::new(),
+            // Rows inserted by hand have none until the next start
+            summary: PostSummary {
+                word_count: row.get::<_, Option<u32>>(5)?.unwrap_or(0),
+                reading_minutes: row.get::<_, Option<u32>>(6)?.unwrap_or(1),
+                excerpt: row.get::<_, Option<String>>(7)?.unwrap_or_default(),
+            },
+        }
+    )
+}
+
+// Maps a row selected with POST_COLUMNS. Tags are filled in separately.
+fn post_from_row(row: &rusqlite::Row) -> rusqlite::Result<Post> {
+    let updated_at: String = row.get(9)?;
+    let status: String = row.get(10)?;
+    Ok(
+        Post {
+            ident: ident_from_row(row)?,
+            updated: to_millis(&updated_at),
+            content: row.get(8)?,
+            status: match status.as_str() {
+                "draft" => PostStatus::Draft,
+                "scheduled" => PostStatus::Scheduled,
+                _ => PostStatus::Published,
+            },
+            preview_token: row.get(11)?,
+            source_path: row.get(12)?,
+            trusted_html: row.get(13)?,
+            author: match row.get::<_, Option<u32>>(14)? {
+                Some(id) => Some(PostAuthor { id, username: row.get(15)?, name: row.get(16)? }),
+                None => None,
+            },
+        }
+    )
+}
+
+fn revision_from_row(row: &rusqlite::Row) -> rusqlite::Result<PostRevision> {
+    let created_at: Option<String> = row.get(4)?;
+    Ok(
+        PostRevision {
+            id: row.get(0)?,
+            post_id: row.get(1)?,
+            title: row.get::<_, Option<String>>(2)?.unwrap_or_default(),
+            content: row.get::<_, Option<String>>(3)?.unwrap_or_default(),
+            created: created_at.as_deref().map(to_millis).unwrap_or(0),
+        }
+    )
+}
+
+const MEDIA_COLUMNS: &str = "id, file_name, original_name, mime_type, size, strftime('%s', created_at), width, height";
+
+// Maps a row selected with MEDIA_COLUMNS. Posts and variants are filled in separately.
+fn media_from_row(row: &rusqlite::Row) -> rusqlite::Result<Media> {
+    let file_name: String = row.get(1)?;
+    Ok(Media {
+        id: row.get(0)?,
+        url: format!("{}/{}", constants::MEDIA_URL, file_name),
+        file_name,
+        original_name: row.get(2)?,
+        mime_type: row.get(3)?,
+        size: row.get(4)?,
+        post_ids: Vec::new(),
+        created: to_millis(&row.get::<_, String>(5)?),
+        width: row.get(6)?,
+        height: row.get(7)?,
+        variants: Vec::new(),
+    })
+}
+
+// Maps a row of `SELECT id, slug, title, description FROM series`. Posts are filled in separately.
+fn series_from_row(row: &rusqlite::Row) -> rusqlite::Result<Series> {
+    Ok(Series {
+        id: row.get(0)?,
+        slug: row.get(1)?,
+        title: row.get(2)?,
+        description: row.get(3)?,
+        posts: Vec::new(),
+    })
+}
+
+// Tags are stored lowercase, with whitespace turned into dashes and
+// anything that would need escaping in a URL path dropped.
+// Returns None when nothing is left.
+pub fn normalize_tag(name: &str) -> Option<String> {
+    let tag: String = name.split_whitespace()
+        .collect::<Vec<&str>>()
+        .join("-")
+        .to_lowercase()
+        .chars()
+        .filter(|c| c.is_alphanumeric() || matches!(c, '-' | '_' | '.' | '+'))
+        .collect();
+    if tag.is_empty() {
+        None
+    } else {
+        Some(tag)
+    }
+}
 
 impl LiteDB {
     pub fn load(file: &str) -> Self {
-        let conn = Mutex::new(Connection::open(&file).expect("Unable to connect to db file!"));
+        let conn = Connection::open(file).expect("Unable to connect to db file!");
+        // Needed for the ON DELETE CASCADE clauses to have any effect
+        conn.execute_batch("PRAGMA foreign_keys = ON;").expect("Unable to enable foreign keys!");
         Self {
-            conn
+            conn: Mutex::new(conn),
+            writes: AtomicU64::new(0),
+            next_publication: AtomicI64::new(i64::MIN),
+            file: (file != ":memory:").then(|| PathBuf::from(file)),
+            file_modified: Mutex::new(None),
+            // Posts may have changed since the last run
+            related_stale: AtomicBool::new(true),
         }
     }
 
+    // In-memory database with the schema and a single admin user (id 1)
+    #[cfg(test)]
+    pub fn for_tests() -> Self {
+        let db = Self::load(":memory:");
+        db.check_or_create_tables().unwrap();
+        db.conn.lock().unwrap()
+            .execute("INSERT INTO user (username, email, role) VALUES ('admin', 'admin@example.com', 'admin')", [])
+            .unwrap();
+        db
+    }
+
     pub fn close(self) -> Result<(), rusqlite::Error> {
         match self.conn.into_inner() {
             Ok(conn) => {
@@ -122,23 +512,20 @@ impl LiteDB {
                 Ok(())
             },
             None => Self::create_tables(&conn, CHAT_TABLES_SQL)
-        }
+        }?;
+        Self::migrate(&conn)?;
+        Self::backfill_slugs(&conn).map_err(|e| format!("Failed to generate post slugs. {}", e))?;
+        Self::backfill_summaries(&conn).map_err(|e| format!("Failed to summarize the posts. {}", e))
     }
 
     pub fn get_users(&self) -> rusqlite::Result<Vec<User>> {
         let conn = self.conn.lock().unwrap();
-        let mut stmt = conn.prepare("SELECT u.username, u.is_admin FROM user u")?;
+        let mut stmt = conn.prepare("SELECT u.id, u.username, u.role FROM user u")?;
         let results = stmt.query_map([], |row| -> rusqlite::Result<User> {
             debug!("Fetched row...");
-            Ok(
-                User {
-                    name: row.get_unwrap(0),
-                    is_admin: row.get_unwrap(1)
-                }
-            )
-        }).or_else(|e: rusqlite::Error| {
+            user_from_row(row)
+        }).inspect_err(|e: &rusqlite::Error| {
             error!("Error, {}", e);
-            Err(e)
         })?;
         // TODO use collect
         let mut list: Vec<User> = Vec::with_capacity(3);
@@ -150,101 +537,1664 @@ impl LiteDB {
         Ok(list)
     }
 
+    // Returns the user with the new role, None when there is no such user
+    pub fn set_user_role(&self, id: u32, role: Role) -> rusqlite::Result<Option<User>> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute("UPDATE user SET role=?1 WHERE id=?2", params![role.as_stored(), id])?;
+        conn.query_row("SELECT u.id, u.username, u.role FROM user u WHERE u.id=?1", params![id], user_from_row).optional()
+    }
+
+    // Public profile of a user, by their username
+    pub fn get_profile(&self, username: &str) -> rusqlite::Result<Option<Profile>> {
+        let conn = self.conn.lock().unwrap();
+        conn.query_row(&format!("SELECT {} FROM user WHERE username=?1", PROFILE_COLUMNS), params![username], profile_from_row)
+            .optional()
+    }
+
+    pub fn get_profile_by_id(&self, user_id: u32) -> rusqlite::Result<Option<Profile>> {
+        let conn = self.conn.lock().unwrap();
+        conn.query_row(&format!("SELECT {} FROM user WHERE id=?1", PROFILE_COLUMNS), params![user_id], profile_from_row)
+            .optional()
+    }
+
+    // Replace the profile of the user. An empty display name removes it.
+    pub fn set_profile(&self, user_id: u32, input: &ProfileInput) -> rusqlite::Result<Option<Profile>> {
+        let conn = self.conn.lock().unwrap();
+        let display_name = input.display_name.as_deref().map(str::trim).filter(|name| !name.is_empty());
+        let links = serde_json::to_string(&input.links).map_err(|e| rusqlite::Error::ToSqlConversionFailure(Box::new(e)))?;
+        conn.execute("UPDATE user SET display_name=?1, bio=?2, links=?3 WHERE id=?4",
+                     params![display_name, input.bio, links, user_id])?;
+        // Shown on the posts of the user
+        self.content_changed();
+        conn.query_row(&format!("SELECT {} FROM user WHERE id=?1", PROFILE_COLUMNS), params![user_id], profile_from_row)
+            .optional()
+    }
+
+    // Who wrote the post, None when there is no such post
+    pub fn get_post_author_id(&self, post_id: u32) -> rusqlite::Result<Option<u32>> {
+        let conn = self.conn.lock().unwrap();
+        conn.query_row("SELECT author_id FROM post WHERE id=?1", params![post_id], |row| row.get(0)).optional()
+    }
+
     /**
-     * Get post by id
-     */
-    pub fn get_post_by_id(&self, id: i32) -> Option<Post> {
-        let conn = self.conn.lock().unwrap();
-        let res: rusqlite::Result<Post> = conn.query_row("SELECT id, title, strftime(\"%s\", created_at) as created_at, content, strftime(\"%s\", updated_at) as updated_at FROM post WHERE id=?1",
-                 params![id],
-                |row| -> rusqlite::Result<Post> {
-                    let created_at: String = row.get_unwrap(2);
-                    let updated_at: String = row.get_unwrap(4);
-                       Ok(
-                           Post {
-                               ident: PostIdent {
-                                   id: row.get_unwrap(0),
-                                   title: row.get_unwrap(1),
-                                   created: created_at.parse::<i64>().or_else( |e| -> Result<i64, ()> {
-                                       info!("Created at cannot be read. {}, returning 0", e);
-                                       Ok(0 as i64)
-                                   }).unwrap() * 1000,
-                               },
-                               updated: updated_at.parse::<i64>().or_else( |e| -> Result<i64, ()> {
-                                   info!("Updated at cannot be read. {}, returning 0", e);
-                                   Ok(0 as i64)
-                               }).unwrap() * 1000,
-                               content: row.get_unwrap(3),
-                           }
-                       )
-                   }
-        );
-        let ret = match res {
-            Ok(val) => {
-                Some(val)
-            },
+     * Find a user by name, with the hash of their password (None when they have none)
+     */
+    pub fn get_user_credentials(&self, username: &str) -> rusqlite::Result<Option<(User, Option<String>)>> {
+        let conn = self.conn.lock().unwrap();
+        conn.query_row("SELECT u.id, u.username, u.role, u.password_hash FROM user u WHERE u.username=?1",
+                       params![username],
+                       |row| Ok((user_from_row(row)?, row.get(3)?))).optional()
+    }
+
+    /**
+     * Replace the password hash of a user and end their sessions.
+     * Returns false when there is no user with that name.
+     */
+    pub fn set_password_hash(&self, username: &str, password_hash: &str) -> rusqlite::Result<bool> {
+        let conn = self.conn.lock().unwrap();
+        let changed = conn.execute("UPDATE user SET password_hash=?1 WHERE username=?2", params![password_hash, username])?;
+        conn.execute("DELETE FROM session WHERE user_id IN (SELECT id FROM user WHERE username=?1)", params![username])?;
+        Ok(changed > 0)
+    }
+
+    /**
+     * Start a session lasting `days` for the user. Expired sessions are cleaned up at the same time.
+     */
+    pub fn create_session(&self, token_hash: &str, user_id: u32, days: u32) -> rusqlite::Result<()> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute("DELETE FROM session WHERE expires_at <= CURRENT_TIMESTAMP", [])?;
+        conn.execute("INSERT INTO session (token_hash, user_id, expires_at) VALUES (?1, ?2, datetime('now', ?3))",
+                     params![token_hash, user_id, format!("+{} days", days)])?;
+        Ok(())
+    }
+
+    /**
+     * Find the user of a session that has not expired (the token is looked up by its SHA-256 hex digest)
+     */
+    pub fn get_user_by_session(&self, token_hash: &str) -> rusqlite::Result<Option<User>> {
+        let conn = self.conn.lock().unwrap();
+        conn.query_row("SELECT u.id, u.username, u.role FROM user u JOIN session s ON s.user_id = u.id \
+                        WHERE s.token_hash=?1 AND s.expires_at > CURRENT_TIMESTAMP",
+                       params![token_hash],
+                       user_from_row).optional()
+    }
+
+    pub fn delete_session(&self, token_hash: &str) -> rusqlite::Result<()> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute("DELETE FROM session WHERE token_hash=?1", params![token_hash])?;
+        Ok(())
+    }
+
+    /**
+     * Record a personal API token of the user (only the digest of the secret is stored).
+     * `expires_at` is in ms since the Unix epoch, None for a token that does not expire.
+     */
+    pub fn create_api_token(&self, user_id: u32, name: &str, token_hash: &str, scopes: &[String],
+                            expires_at: Option<i64>) -> rusqlite::Result<ApiToken> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute("INSERT INTO api_token (user_id, name, token_hash, scopes, expires_at) \
+                      VALUES (?1, ?2, ?3, ?4, datetime(?5 / 1000, 'unixepoch'))",
+                     params![user_id, name, token_hash, scopes.join(" "), expires_at])?;
+        let id = conn.last_insert_rowid();
+        conn.query_row(&format!("SELECT {} FROM api_token WHERE id=?1", API_TOKEN_COLUMNS), params![id], api_token_from_row)
+    }
+
+    // Tokens of the user, newest first (expired ones included, until revoked)
+    pub fn get_api_tokens(&self, user_id: u32) -> rusqlite::Result<Vec<ApiToken>> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare(&format!("SELECT {} FROM api_token WHERE user_id=?1 ORDER BY id DESC", API_TOKEN_COLUMNS))?;
+        let tokens = stmt.query_map(params![user_id], api_token_from_row)?.collect();
+        tokens
+    }
+
+    // Revoke a token of the user. Returns false when they have no token with that id.
+    pub fn delete_api_token(&self, user_id: u32, id: u32) -> rusqlite::Result<bool> {
+        let conn = self.conn.lock().unwrap();
+        Ok(conn.execute("DELETE FROM api_token WHERE id=?1 AND user_id=?2", params![id, user_id])? > 0)
+    }
+
+    /**
+     * Owner and scopes of a token that has not expired (looked up by its SHA-256 hex digest),
+     * recording that it was used.
+     */
+    pub fn use_api_token(&self, token_hash: &str) -> rusqlite::Result<Option<(User, Vec<String>)>> {
+        let conn = self.conn.lock().unwrap();
+        let found = conn.query_row("SELECT u.id, u.username, u.role, t.scopes, t.id FROM user u \
+                                    JOIN api_token t ON t.user_id = u.id \
+                                    WHERE t.token_hash=?1 AND (t.expires_at IS NULL OR t.expires_at > CURRENT_TIMESTAMP)",
+                                   params![token_hash],
+                                   |row| {
+                                       let scopes: String = row.get(3)?;
+                                       let id: u32 = row.get(4)?;
+                                       Ok((user_from_row(row)?, scopes, id))
+                                   }).optional()?;
+        let Some((user, scopes, id)) = found else {
+            return Ok(None);
+        };
+        conn.execute("UPDATE api_token SET last_used_at=CURRENT_TIMESTAMP WHERE id=?1", params![id])?;
+        Ok(Some((user, scopes.split_whitespace().map(str::to_string).collect())))
+    }
+
+    /**
+     * Get post by id (whatever its status)
+     */
+    pub fn get_post_by_id(&self, id: u32) -> Option<Post> {
+        let conn = self.conn.lock().unwrap();
+        // A missing post is not an error, the routes answer 404
+        match Self::query_post(&conn, id).optional() {
+            Ok(post) => post,
             Err(e) => {
                 error!("Error getting post: {}", e);
                 None
             }
+        }
+    }
+
+    /**
+     * Get a visible post by its URL slug
+     */
+    pub fn get_post_by_slug(&self, slug: &str) -> Option<Post> {
+        self.find_post(&format!("p.slug=?1 AND {}", VISIBLE), slug)
+    }
+
+    /**
+     * Get a post by its URL slug, whatever its status
+     */
+    pub fn get_any_post_by_slug(&self, slug: &str) -> Option<Post> {
+        self.find_post("p.slug=?1", slug)
+    }
+
+    /**
+     * Get the post imported from a markdown file, by the path of the file
+     */
+    pub fn get_post_by_source(&self, source_path: &str) -> Option<Post> {
+        self.find_post("p.source_path=?1", source_path)
+    }
+
+    /**
+     * Whether a post other than `exclude_id` (the one being edited) already has the slug
+     */
+    pub fn is_slug_taken(&self, slug: &str, exclude_id: Option<u32>) -> rusqlite::Result<bool> {
+        let conn = self.conn.lock().unwrap();
+        Self::query_slug_taken(&conn, slug, exclude_id)
+    }
+
+    /**
+     * Get any post, including drafts and scheduled ones, by its secret preview token
+     */
+    pub fn get_post_by_preview_token(&self, token: &str) -> Option<Post> {
+        self.find_post("p.preview_token=?1", token)
+    }
+
+    /**
+     * Insert a new post written by `author_id` and return it as stored.
+     * Without an explicit slug, one is generated from the title.
+     * `trusted_html` tells whether the writer of the content has the EmbedHtml permission.
+     */
+    pub fn create_post(&self, author_id: u32, input: &PostInput, trusted_html: bool) -> rusqlite::Result<Post> {
+        let mut conn = self.conn.lock().unwrap();
+        self.content_changed();
+        self.related_stale.store(true, Ordering::Relaxed);
+        // The post, its summary and its tags are written together or not at all
+        let tx = conn.transaction()?;
+        let post_slug = match &input.slug {
+            Some(s) => slug::slugify(s),
+            None => Self::unique_slug(&tx, &slug::slugify(&input.title), None)?,
+        };
+        let status = input.status.unwrap_or(PostStatus::Published);
+        // Published posts without a date are published now, drafts get one when they are published.
+        // The preview token comes from the post_default_publication trigger.
+        tx.execute("INSERT INTO post (title, content, author_id, slug, status, published_at, trusted_html) \
+                    VALUES (?1, ?2, ?3, ?4, ?5, \
+                            CASE WHEN ?6 IS NOT NULL THEN datetime(?6 / 1000, 'unixepoch') \
+                                 WHEN ?5 = 'published' THEN CURRENT_TIMESTAMP END, ?7)",
+                   params![input.title, input.content, author_id, post_slug, status.as_stored(), input.published_at, trusted_html])?;
+        let id = tx.last_insert_rowid() as u32;
+        Self::store_summary(&tx, id, &input.content)?;
+        if let Some(tags) = &input.tags {
+            Self::set_post_tags(&tx, id, tags)?;
+        }
+        let post = Self::query_post(&tx, id)?;
+        tx.commit()?;
+        debug!("Created post {}", id);
+        Ok(post)
+    }
+
+    /**
+     * Replace the title and content of a post and bump its updated_at.
+     * The tags, slug, status and publication date only change when the input has them.
+     * The HTML of the post is then trusted as much as the writer (`trusted_html`, see create_post).
+     * Returns None when there is no post with that id.
+     */
+    pub fn update_post(&self, id: u32, input: &PostInput, trusted_html: bool) -> rusqlite::Result<Option<Post>> {
+        let mut conn = self.conn.lock().unwrap();
+        self.content_changed();
+        self.related_stale.store(true, Ordering::Relaxed);
+        let new_slug = input.slug.as_deref().map(slug::slugify);
+        let new_status = input.status.map(|s| s.as_stored());
+        // The post, its summary and its tags are written together or not at all
+        let tx = conn.transaction()?;
+        let changed = tx.execute("UPDATE post SET title=?1, content=?2, slug=COALESCE(?3, slug), \
+                                  status=COALESCE(?4, status), \
+                                  published_at=CASE WHEN ?5 IS NOT NULL THEN datetime(?5 / 1000, 'unixepoch') \
+                                                    WHEN COALESCE(?4, status) = 'published' THEN COALESCE(published_at, CURRENT_TIMESTAMP) \
+                                                    ELSE published_at END, \
+                                  trusted_html=?7, updated_at=CURRENT_TIMESTAMP WHERE id=?6",
+                                 params![input.title, input.content, new_slug, new_status, input.published_at, id, trusted_html])?;
+        if changed == 0 {
+            return Ok(None);
+        }
+        Self::store_summary(&tx, id, &input.content)?;
+        if let Some(tags) = &input.tags {
+            Self::set_post_tags(&tx, id, tags)?;
+        }
+        let post = Self::query_post(&tx, id)?;
+        tx.commit()?;
+        debug!("Updated post {}", id);
+        Ok(Some(post))
+    }
+
+    /**
+     * Delete a post. Returns false when there was nothing to delete.
+     */
+    pub fn delete_post(&self, id: u32) -> rusqlite::Result<bool> {
+        let conn = self.conn.lock().unwrap();
+        self.content_changed();
+        self.related_stale.store(true, Ordering::Relaxed);
+        let changed = conn.execute("DELETE FROM post WHERE id=?1", params![id])?;
+        debug!("Deleted {} post(s) with id {}", changed, id);
+        Ok(changed > 0)
+    }
+
+    /**
+     * Record the markdown file a post comes from (None when it is managed through the API)
+     */
+    pub fn set_post_source(&self, id: u32, source_path: Option<&str>) -> rusqlite::Result<()> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute("UPDATE post SET source_path=?1 WHERE id=?2", params![source_path, id])?;
+        Ok(())
+    }
+
+    /**
+     * Id and source file of every post imported from a markdown file
+     */
+    pub fn get_post_sources(&self) -> rusqlite::Result<Vec<(u32, String)>> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare("SELECT id, source_path FROM post WHERE source_path IS NOT NULL ORDER BY id")?;
+        let ret = stmt.query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?;
+        ret.collect()
+    }
+
+    /**
+     * Slug and last modification (ms since epoch) of every visible post, newest first
+     */
+    pub fn get_post_updates(&self) -> rusqlite::Result<Vec<(String, i64)>> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare(&format!("SELECT p.slug, strftime('%s', p.updated_at) FROM post p WHERE {} \
+                                              ORDER BY p.updated_at DESC, p.id DESC", VISIBLE))?;
+        let ret = stmt.query_map([], |row| Ok((row.get(0)?, to_millis(&row.get::<_, String>(1)?))))?;
+        ret.collect()
+    }
+
+    // Called on every write to what the visitors can see
+    fn content_changed(&self) {
+        self.writes.fetch_add(1, Ordering::Relaxed);
+        self.next_publication.store(i64::MIN, Ordering::Relaxed);
+    }
+
+    /**
+     * Changes whenever what the visitors can see may have changed: on every write through
+     * this instance, when the database file is written by another process (the sync command),
+     * and when a scheduled post becomes visible. Cheap enough for every page view: the
+     * database is only read after one of those.
+     */
+    pub fn content_version(&self) -> rusqlite::Result<ContentVersion> {
+        if let Some(file) = &self.file {
+            let modified = fs::metadata(file).and_then(|m| m.modified()).ok();
+            let mut file_modified = self.file_modified.lock().unwrap();
+            if *file_modified != modified {
+                *file_modified = modified;
+                self.content_changed();
+            }
+        }
+        if chrono::Utc::now().timestamp_millis() >= self.next_publication.load(Ordering::Relaxed) {
+            let conn = self.conn.lock().unwrap();
+            let next: Option<String> = conn.query_row(
+                "SELECT MIN(strftime('%s', published_at)) FROM post \
+                 WHERE status = 'published' AND published_at > CURRENT_TIMESTAMP",
+                [], |row| row.get(0))?;
+            self.next_publication.store(next.map_or(i64::MAX, |n| to_millis(&n)), Ordering::Relaxed);
+            self.writes.fetch_add(1, Ordering::Relaxed);
+        }
+        Ok(ContentVersion { writes: self.writes.load(Ordering::Relaxed) })
+    }
+
+    // Whether posts changed since the related posts were last computed
+    pub fn related_stale(&self) -> bool {
+        self.related_stale.load(Ordering::Relaxed)
+    }
+
+    /**
+     * Compute the related posts of every post again and store them. The scores are computed
+     * without holding the connection, and dropped if the posts change in the meantime (they are
+     * still stale then).
+     */
+    pub fn refresh_related(&self) -> rusqlite::Result<()> {
+        let documents = {
+            let conn = self.conn.lock().unwrap();
+            self.related_stale.store(false, Ordering::Relaxed);
+            let mut stmt = conn.prepare("SELECT id, COALESCE(title, ''), COALESCE(content, '') FROM post")?;
+            let rows = stmt.query_map([], |row| Ok((row.get::<_, u32>(0)?, row.get(1)?, row.get(2)?)))?
+                .collect::<rusqlite::Result<Vec<(u32, String, String)>>>()?;
+            let mut documents = Vec::new();
+            for (id, title, content) in rows {
+                documents.push(related::Document { id, title, content, tags: Self::query_tags(&conn, id)? });
+            }
+            documents
         };
-        ret
+        let scores = related::related_scores(&documents, related::STORED_PER_POST);
+        let mut conn = self.conn.lock().unwrap();
+        if self.related_stale.load(Ordering::Relaxed) {
+            debug!("Posts changed while computing the related posts");
+            return Ok(());
+        }
+        // The pages show the new suggestions
+        self.content_changed();
+        let tx = conn.transaction()?;
+        tx.execute("DELETE FROM post_related", [])?;
+        {
+            let mut stmt = tx.prepare("INSERT INTO post_related (post_id, related_id, score) VALUES (?1, ?2, ?3)")?;
+            for (post_id, related_id, score) in &scores {
+                stmt.execute(params![post_id, related_id, score])?;
+            }
+        }
+        tx.commit()?;
+        debug!("Stored {} related post scores for {} posts", scores.len(), documents.len());
+        Ok(())
     }
 
     /**
-     * Gets the list of posts (sorted by date created, descending)
+     * Gets the visible posts most related to a post, best first, from the scores stored by
+     * the last refresh_related
      */
-    pub fn get_posts(&self, limit: i32, offset: i32) -> rusqlite::Result<Vec<PostIdent>> {
+    pub fn get_related_posts(&self, post_id: u32, limit: i32) -> rusqlite::Result<Vec<PostIdent>> {
         let conn = self.conn.lock().unwrap();
-        let mut stmt = conn.prepare("SELECT id, title, strftime(\"%s\", created_at) as created_at FROM post ORDER BY created_at DESC LIMIT ?1 OFFSET ?2")?;
-        let ret = stmt.query_map(params![limit, offset], |row| -> rusqlite::Result<PostIdent> {
-            debug!("Fetched PostIdent row...");
-            // created_at is read as string.
-            // represents unix time in seconds.
-            let created_at: String = row.get_unwrap(2);
-            debug!("Created at: {}", created_at);
-            Ok(
-                PostIdent {
-                    id: row.get_unwrap(0),
-                    title: row.get_unwrap(1),
-                    created: created_at.parse::<i64>().or_else( |e| -> Result<i64, ()> {
-                        info!("Created at cannot be read. {}, returning 0", e);
-                        Ok(0 as i64)
-                    }).unwrap() * 1000,
-                }
-            )
-        }).or_else(|e: rusqlite::Error| {
-            error!("Error, {}", e);
-            Err(e)
-        })?;
-        // TODO use collect?
-        let mut items = Vec::new();
-        for item in ret {
-            items.push(item.unwrap());
+        Self::query_idents(&conn,
+                           &format!("JOIN post_related r ON r.related_id = p.id \
+                                     WHERE r.post_id = ?1 AND {} ORDER BY r.score DESC, p.id LIMIT ?2", VISIBLE),
+                           params![post_id, limit])
+    }
+
+    /**
+     * Insert a new series and return it as stored. Without an explicit slug, one is generated from the title.
+     */
+    pub fn create_series(&self, input: &SeriesInput) -> rusqlite::Result<Series> {
+        let mut conn = self.conn.lock().unwrap();
+        self.content_changed();
+        let tx = conn.transaction()?;
+        tx.execute("INSERT INTO series (slug, title, description) VALUES (?1, ?2, ?3)",
+                   params![Self::series_slug(input), input.title, input.description])?;
+        let id = tx.last_insert_rowid() as u32;
+        Self::set_series_posts(&tx, id, &input.posts)?;
+        let series = Self::query_series(&tx, id)?;
+        tx.commit()?;
+        debug!("Created series {}", id);
+        Ok(series)
+    }
+
+    /**
+     * Replace the title, description and parts of a series.
+     * Returns None when there is no series with that id.
+     */
+    pub fn update_series(&self, id: u32, input: &SeriesInput) -> rusqlite::Result<Option<Series>> {
+        let mut conn = self.conn.lock().unwrap();
+        self.content_changed();
+        let tx = conn.transaction()?;
+        let changed = tx.execute("UPDATE series SET slug=?1, title=?2, description=?3 WHERE id=?4",
+                                 params![Self::series_slug(input), input.title, input.description, id])?;
+        if changed == 0 {
+            return Ok(None);
+        }
+        Self::set_series_posts(&tx, id, &input.posts)?;
+        let series = Self::query_series(&tx, id)?;
+        tx.commit()?;
+        debug!("Updated series {}", id);
+        Ok(Some(series))
+    }
+
+    /**
+     * Delete a series (not its posts). Returns false when there was nothing to delete.
+     */
+    pub fn delete_series(&self, id: u32) -> rusqlite::Result<bool> {
+        let conn = self.conn.lock().unwrap();
+        self.content_changed();
+        let changed = conn.execute("DELETE FROM series WHERE id=?1", params![id])?;
+        debug!("Deleted {} series with id {}", changed, id);
+        Ok(changed > 0)
+    }
+
+    /**
+     * Gets every series, sorted by title
+     */
+    pub fn get_series_list(&self) -> rusqlite::Result<Vec<Series>> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare("SELECT id, slug, title, description FROM series ORDER BY title, id")?;
+        let mut items = stmt.query_map([], series_from_row)?
+            .collect::<rusqlite::Result<Vec<Series>>>()?;
+        for item in items.iter_mut() {
+            item.posts = Self::query_series_posts(&conn, item.id)?;
         }
         Ok(items)
     }
 
+    pub fn get_series_by_slug(&self, slug: &str) -> rusqlite::Result<Option<Series>> {
+        let conn = self.conn.lock().unwrap();
+        let id: Option<u32> = conn.query_row("SELECT id FROM series WHERE slug=?1", params![slug], |row| row.get(0))
+            .optional()?;
+        id.map(|id| Self::query_series(&conn, id)).transpose()
+    }
+
+    /**
+     * Gets the visible parts of a series, in reading order
+     */
+    pub fn get_series_parts(&self, series_id: u32) -> rusqlite::Result<Vec<PostIdent>> {
+        let conn = self.conn.lock().unwrap();
+        Self::query_idents(&conn,
+                           &format!("JOIN series_post s ON s.post_id = p.id \
+                                     WHERE s.series_id = ?1 AND {} ORDER BY s.position", VISIBLE),
+                           params![series_id])
+    }
+
+    /**
+     * Gets the series of a post, with its place among the visible parts and its neighbours.
+     * A post that is not visible yet (previewed) is counted at its place.
+     */
+    pub fn get_post_series(&self, post_id: u32) -> rusqlite::Result<Option<SeriesNav>> {
+        let conn = self.conn.lock().unwrap();
+        let series = conn.query_row("SELECT s.id, s.slug, s.title, s.description FROM series s \
+                                     JOIN series_post sp ON sp.series_id = s.id WHERE sp.post_id=?1",
+                                    params![post_id],
+                                    series_from_row).optional()?;
+        let Some(series) = series else {
+            return Ok(None);
+        };
+        let mut parts = Self::query_idents(&conn,
+                                           &format!("JOIN series_post s ON s.post_id = p.id \
+                                                     WHERE s.series_id = ?1 AND ({} OR p.id = ?2) ORDER BY s.position", VISIBLE),
+                                           params![series.id, post_id])?;
+        let Some(index) = parts.iter().position(|p| p.id == post_id) else {
+            return Ok(None);
+        };
+        let count = parts.len() as u32;
+        let next = (index + 1 < parts.len()).then(|| parts.remove(index + 1));
+        let previous = (index > 0).then(|| parts.remove(index - 1));
+        Ok(Some(SeriesNav {
+            slug: series.slug,
+            title: series.title,
+            part: index as u32 + 1,
+            parts: count,
+            previous,
+            next,
+        }))
+    }
+
+    /**
+     * Record an uploaded file and return it as stored.
+     * Returns None when a file with the same content was recorded first (e.g. by a simultaneous upload).
+     */
+    pub fn create_media(&self, uploader_id: u32, upload: &MediaUpload) -> rusqlite::Result<Option<Media>> {
+        let mut conn = self.conn.lock().unwrap();
+        let tx = conn.transaction()?;
+        let inserted = tx.execute("INSERT INTO media (hash, file_name, original_name, mime_type, size, uploader_id) \
+                                   VALUES (?1, ?2, ?3, ?4, ?5, ?6) ON CONFLICT(hash) DO NOTHING",
+                                  params![upload.hash, upload.file_name, upload.original_name, upload.mime_type,
+                                          upload.size, uploader_id])?;
+        if inserted == 0 {
+            return Ok(None);
+        }
+        let id = tx.last_insert_rowid() as u32;
+        if let Some(post_id) = upload.post_id {
+            tx.execute("INSERT INTO media_post (media_id, post_id) VALUES (?1, ?2)", params![id, post_id])?;
+        }
+        let media = Self::query_one_media(&tx, "id=?1", params![id])?;
+        tx.commit()?;
+        debug!("Recorded media {} ({})", id, upload.file_name);
+        Ok(media)
+    }
+
+    /**
+     * Link an uploaded file to one more post, and return it as stored.
+     * Returns None when there is no media with that id.
+     */
+    pub fn add_media_post(&self, id: u32, post_id: u32) -> rusqlite::Result<Option<Media>> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute("INSERT OR IGNORE INTO media_post (media_id, post_id) SELECT id, ?2 FROM media WHERE id=?1",
+                     params![id, post_id])?;
+        Self::query_one_media(&conn, "id=?1", params![id])
+    }
+
+    pub fn get_media(&self, id: u32) -> rusqlite::Result<Option<Media>> {
+        let conn = self.conn.lock().unwrap();
+        Self::query_one_media(&conn, "id=?1", params![id])
+    }
+
+    // The file with that content, if it was already uploaded
+    pub fn get_media_by_hash(&self, hash: &str) -> rusqlite::Result<Option<Media>> {
+        let conn = self.conn.lock().unwrap();
+        Self::query_one_media(&conn, "hash=?1", params![hash])
+    }
+
+    // The file served at /media/{file_name}
+    pub fn get_media_by_file_name(&self, file_name: &str) -> rusqlite::Result<Option<Media>> {
+        let conn = self.conn.lock().unwrap();
+        Self::query_one_media(&conn, "file_name=?1", params![file_name])
+    }
+
+    /**
+     * Gets the uploaded files (newest first), only those of a post when one is given
+     */
+    pub fn get_media_list(&self, post_id: Option<u32>) -> rusqlite::Result<Vec<Media>> {
+        let conn = self.conn.lock().unwrap();
+        Self::query_media(&conn,
+                          "WHERE ?1 IS NULL OR id IN (SELECT media_id FROM media_post WHERE post_id = ?1) \
+                           ORDER BY created_at DESC, id DESC",
+                          params![post_id])
+    }
+
+    /**
+     * Record the size of an image and replace its resized copies
+     */
+    pub fn set_media_variants(&self, id: u32, width: u32, height: u32, variants: &[ImageVariant]) -> rusqlite::Result<()> {
+        let mut conn = self.conn.lock().unwrap();
+        // The notes showing the image change
+        self.content_changed();
+        let tx = conn.transaction()?;
+        tx.execute("UPDATE media SET width=?1, height=?2 WHERE id=?3", params![width, height, id])?;
+        tx.execute("DELETE FROM media_variant WHERE media_id=?1", params![id])?;
+        for variant in variants {
+            tx.execute("INSERT INTO media_variant (media_id, width, height, file_name, size) VALUES (?1, ?2, ?3, ?4, ?5)",
+                       params![id, variant.width, variant.height, variant.file_name, variant.size])?;
+        }
+        tx.commit()
+    }
+
+    /**
+     * Forget an uploaded file (the file itself is deleted by the caller).
+     * Returns false when there was nothing to delete.
+     */
+    pub fn delete_media(&self, id: u32) -> rusqlite::Result<bool> {
+        let conn = self.conn.lock().unwrap();
+        // The notes showing the file change
+        self.content_changed();
+        let changed = conn.execute("DELETE FROM media WHERE id=?1", params![id])?;
+        debug!("Deleted {} media with id {}", changed, id);
+        Ok(changed > 0)
+    }
+
+    /**
+     * Gets the previous versions of a post (newest first)
+     */
+    pub fn get_revisions(&self, post_id: u32) -> rusqlite::Result<Vec<PostRevision>> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare("SELECT id, post_id, title, content, strftime('%s', created_at) FROM post_revision \
+                                     WHERE post_id=?1 ORDER BY id DESC")?;
+        let ret = stmt.query_map(params![post_id], revision_from_row)?;
+        ret.collect()
+    }
+
+    /**
+     * Gets one previous version of a post
+     */
+    pub fn get_revision(&self, post_id: u32, revision_id: u32) -> rusqlite::Result<Option<PostRevision>> {
+        let conn = self.conn.lock().unwrap();
+        conn.query_row("SELECT id, post_id, title, content, strftime('%s', created_at) FROM post_revision \
+                        WHERE post_id=?1 AND id=?2",
+                       params![post_id, revision_id],
+                       revision_from_row).optional()
+    }
+
+    /**
+     * Put back the title and content of a previous version.
+     * The version being replaced is saved as a new revision, so a restore can be undone.
+     * Whoever restores it becomes the writer of the content (`trusted_html`, see create_post).
+     * Returns None when the post has no such revision.
+     */
+    pub fn restore_revision(&self, post_id: u32, revision_id: u32, trusted_html: bool) -> rusqlite::Result<Option<Post>> {
+        let mut conn = self.conn.lock().unwrap();
+        self.content_changed();
+        self.related_stale.store(true, Ordering::Relaxed);
+        // The content and its summary are written together or not at all
+        let tx = conn.transaction()?;
+        let changed = tx.execute("UPDATE post SET (title, content) = \
+                                    (SELECT r.title, r.content FROM post_revision r WHERE r.post_id=?1 AND r.id=?2), \
+                                  trusted_html=?3, updated_at=CURRENT_TIMESTAMP \
+                                  WHERE id=?1 AND EXISTS (SELECT 1 FROM post_revision r WHERE r.post_id=?1 AND r.id=?2)",
+                                 params![post_id, revision_id, trusted_html])?;
+        if changed == 0 {
+            return Ok(None);
+        }
+        let content: String = tx.query_row("SELECT COALESCE(content, '') FROM post WHERE id=?1", params![post_id], |row| row.get(0))?;
+        Self::store_summary(&tx, post_id, &content)?;
+        let post = Self::query_post(&tx, post_id)?;
+        tx.commit()?;
+        debug!("Restored revision {} of post {}", revision_id, post_id);
+        Ok(Some(post))
+    }
+
+    /**
+     * Gets the list of visible posts (sorted by date published, descending)
+     */
+    pub fn get_posts(&self, limit: i32, offset: i32) -> rusqlite::Result<Vec<PostIdent>> {
+        let conn = self.conn.lock().unwrap();
+        Self::query_idents(&conn,
+                           &format!("WHERE {} ORDER BY p.published_at DESC, p.id DESC LIMIT ?1 OFFSET ?2", VISIBLE),
+                           params![limit, offset])
+    }
+
+    /**
+     * Number of visible posts
+     */
+    pub fn count_posts(&self) -> rusqlite::Result<u32> {
+        let conn = self.conn.lock().unwrap();
+        conn.query_row(&format!("SELECT COUNT(*) FROM post p WHERE {}", VISIBLE), [], |row| row.get(0))
+    }
+
+    /**
+     * Number of visible posts per month of publication (newest first)
+     */
+    pub fn get_archive(&self) -> rusqlite::Result<Vec<ArchivePeriod>> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare(&format!("SELECT CAST(strftime('%Y', p.published_at) AS INTEGER), \
+                                                     CAST(strftime('%m', p.published_at) AS INTEGER), COUNT(*) \
+                                              FROM post p WHERE {} GROUP BY 1, 2 ORDER BY 1 DESC, 2 DESC", VISIBLE))?;
+        let ret = stmt.query_map([], |row| Ok(ArchivePeriod {
+            year: row.get(0)?,
+            month: row.get(1)?,
+            count: row.get(2)?,
+        }))?;
+        ret.collect()
+    }
+
+    /**
+     * Gets the list of visible posts published in the given month (sorted by date published, descending)
+     */
+    pub fn get_posts_by_month(&self, year: i32, month: u32, limit: i32, offset: i32) -> rusqlite::Result<Vec<PostIdent>> {
+        let conn = self.conn.lock().unwrap();
+        Self::query_idents(&conn,
+                           &format!("WHERE strftime('%Y-%m', p.published_at) = ?1 AND {} \
+                                     ORDER BY p.published_at DESC, p.id DESC LIMIT ?2 OFFSET ?3", VISIBLE),
+                           params![format!("{:04}-{:02}", year, month), limit, offset])
+    }
+
+    /**
+     * Gets the latest visible posts with their content (sorted by date published, descending)
+     */
+    pub fn get_recent_posts(&self, limit: i32) -> rusqlite::Result<Vec<Post>> {
+        let conn = self.conn.lock().unwrap();
+        Self::query_posts(&conn,
+                          &format!("WHERE {} ORDER BY p.published_at DESC, p.id DESC LIMIT ?1", VISIBLE),
+                          params![limit])
+    }
+
+    /**
+     * Gets the list of visible posts having the given tag (sorted by date published, descending)
+     */
+    pub fn get_posts_by_tag(&self, tag: &str, limit: i32, offset: i32) -> rusqlite::Result<Vec<PostIdent>> {
+        let conn = self.conn.lock().unwrap();
+        Self::query_idents(&conn,
+                           &format!("JOIN post_tag pt ON pt.post_id = p.id JOIN tag t ON t.id = pt.tag_id \
+                                     WHERE t.name = ?1 AND {} ORDER BY p.published_at DESC, p.id DESC LIMIT ?2 OFFSET ?3", VISIBLE),
+                           params![tag, limit, offset])
+    }
+
+    /**
+     * Visible posts written by the user, newest first
+     */
+    pub fn get_posts_by_author(&self, author_id: u32, limit: i32, offset: i32) -> rusqlite::Result<Vec<PostIdent>> {
+        let conn = self.conn.lock().unwrap();
+        Self::query_idents(&conn,
+                           &format!("WHERE p.author_id = ?1 AND {} ORDER BY p.published_at DESC, p.id DESC LIMIT ?2 OFFSET ?3", VISIBLE),
+                           params![author_id, limit, offset])
+    }
+
+    pub fn count_posts_by_author(&self, author_id: u32) -> rusqlite::Result<u32> {
+        let conn = self.conn.lock().unwrap();
+        conn.query_row(&format!("SELECT COUNT(*) FROM post p WHERE p.author_id = ?1 AND {}", VISIBLE),
+                       params![author_id],
+                       |row| row.get(0))
+    }
+
+    /**
+     * Number of visible posts having the given tag
+     */
+    pub fn count_posts_by_tag(&self, tag: &str) -> rusqlite::Result<u32> {
+        let conn = self.conn.lock().unwrap();
+        conn.query_row(&format!("SELECT COUNT(*) FROM post p \
+                                 JOIN post_tag pt ON pt.post_id = p.id JOIN tag t ON t.id = pt.tag_id \
+                                 WHERE t.name = ?1 AND {}", VISIBLE),
+                       params![tag],
+                       |row| row.get(0))
+    }
+
+    /**
+     * Full-text search over visible posts' titles and content. Every term of the query must match.
+     * Results are ranked by relevance when the FTS index could be used, by date otherwise.
+     */
+    pub fn search_posts(&self, query: &str, limit: i32) -> rusqlite::Result<Vec<SearchResult>> {
+        let terms = search::terms(query);
+        if terms.is_empty() {
+            return Ok(Vec::new());
+        }
+        // ?1 is the MATCH expression (possibly unused), then one LIKE pattern per short term
+        let match_expr = search::match_expression(&terms);
+        let mut params: Vec<String> = vec![match_expr.clone().unwrap_or_default()];
+        let mut conditions: Vec<String> = vec![VISIBLE.to_string()];
+        for term in terms.iter().filter(|t| !search::is_indexable(t)) {
+            params.push(search::like_pattern(term));
+            conditions.push(format!("(p.title LIKE ?{0} ESCAPE '\\' OR p.content LIKE ?{0} ESCAPE '\\')", params.len()));
+        }
+        let sql = match match_expr {
+            Some(_) => {
+                conditions.insert(0, "post_fts MATCH ?1".to_string());
+                // bm25 is lower for better matches; title hits weigh more than content hits
+                format!("SELECT {}, p.content, -bm25(post_fts, 10.0, 1.0) AS score \
+                         FROM post_fts JOIN post p ON p.id = post_fts.rowid \
+                         WHERE {} ORDER BY score DESC LIMIT {}",
+                        IDENT_COLUMNS, conditions.join(" AND "), limit)
+            },
+            None => {
+                conditions.insert(0, "?1 = ''".to_string());
+                format!("SELECT {}, p.content, 0.0 AS score FROM post p \
+                         WHERE {} ORDER BY p.published_at DESC, p.id DESC LIMIT {}",
+                        IDENT_COLUMNS, conditions.join(" AND "), limit)
+            },
+        };
+        debug!("Search query: {}", sql);
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare(&sql)?;
+        let mut results = stmt.query_map(rusqlite::params_from_iter(params.iter()), |row| {
+            let content: String = row.get(8)?;
+            Ok(SearchResult {
+                ident: ident_from_row(row)?,
+                snippet: search::snippet(&content, &terms),
+                score: row.get(9)?,
+            })
+        })?.collect::<rusqlite::Result<Vec<SearchResult>>>()?;
+        for result in results.iter_mut() {
+            result.ident.tags = Self::query_tags(&conn, result.ident.id)?;
+        }
+        Ok(results)
+    }
+
+    /**
+     * Gets every tag used by at least one visible post, with its number of posts (sorted by name)
+     */
+    pub fn get_tags(&self) -> rusqlite::Result<Vec<TagCount>> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare(&format!("SELECT t.name, COUNT(pt.post_id) FROM tag t \
+                                              JOIN post_tag pt ON pt.tag_id = t.id \
+                                              JOIN post p ON p.id = pt.post_id \
+                                              WHERE {} GROUP BY t.id ORDER BY t.name", VISIBLE))?;
+        let ret = stmt.query_map([], |row| Ok(TagCount {
+            name: row.get(0)?,
+            count: row.get(1)?,
+        }))?;
+        ret.collect()
+    }
+
     fn check_table(conn: &Connection, table: &str) -> Option<()> {
-        let res = conn.query_row("SELECT name FROM sqlite_master WHERE type='table' AND name=?1",
-                                 params![table],
-                                 |_| Ok(Some(())))
+        conn.query_row("SELECT name FROM sqlite_master WHERE type='table' AND name=?1",
+                       params![table],
+                       |_| Ok(Some(())))
             .or_else(|e| -> Result<Option<()>, String> {
                 debug!("{}", e);
                 Ok(None)
-            }).unwrap();
-
-        return res;
+            }).unwrap()
     }
 
     fn create_tables(conn: &Connection, sql: &str) -> Result<(), String> {
         info!("Creating tables...");
-        conn.execute_batch(sql).or_else( |e| {
+        conn.execute_batch(sql).map_err( |e| {
             error!("{}", e);
-            Err(format!("Failed to create tables. {}", e))
+            format!("Failed to create tables. {}", e)
             }
         )
     }
 
+    fn migrate(conn: &Connection) -> Result<(), String> {
+        let version: usize = conn.query_row("PRAGMA user_version", [], |row| row.get(0))
+            .map_err(|e| format!("Failed to read schema version. {}", e))?;
+        for (idx, sql) in MIGRATIONS.iter().enumerate().skip(version) {
+            info!("Migrating database to version {}...", idx + 1);
+            let batch = format!("BEGIN;\n{}\nPRAGMA user_version = {};\nCOMMIT;", sql, idx + 1);
+            conn.execute_batch(&batch).map_err(|e| {
+                error!("{}", e);
+                // Leave the database as it was before this step
+                let _ = conn.execute_batch("ROLLBACK;");
+                format!("Failed to migrate database to version {}. {}", idx + 1, e)
+            })?;
+        }
+        Ok(())
+    }
+
+    // Gives a slug to every post without one (posts created before slugs existed)
+    fn backfill_slugs(conn: &Connection) -> rusqlite::Result<()> {
+        let mut stmt = conn.prepare("SELECT id, title FROM post WHERE slug IS NULL ORDER BY id")?;
+        let missing = stmt.query_map([], |row| Ok((row.get::<_, u32>(0)?, row.get::<_, Option<String>>(1)?)))?
+            .collect::<rusqlite::Result<Vec<(u32, Option<String>)>>>()?;
+        for (id, title) in missing {
+            let post_slug = Self::unique_slug(conn, &slug::slugify(&title.unwrap_or_default()), Some(id))?;
+            info!("Post {} gets slug {}", id, post_slug);
+            conn.execute("UPDATE post SET slug=?1 WHERE id=?2", params![post_slug, id])?;
+        }
+        Ok(())
+    }
+
+    // Posts without a summary: the ones written before summaries were stored, or inserted by hand
+    fn backfill_summaries(conn: &Connection) -> rusqlite::Result<()> {
+        let mut stmt = conn.prepare("SELECT id, COALESCE(content, '') FROM post WHERE excerpt IS NULL ORDER BY id")?;
+        let missing = stmt.query_map([], |row| Ok((row.get::<_, u32>(0)?, row.get::<_, String>(1)?)))?
+            .collect::<rusqlite::Result<Vec<(u32, String)>>>()?;
+        for (id, content) in missing {
+            debug!("Summarizing post {}", id);
+            Self::store_summary(conn, id, &content)?;
+        }
+        Ok(())
+    }
+
+    // Summarizes the new content of a post for the listings
+    fn store_summary(conn: &Connection, id: u32, content: &str) -> rusqlite::Result<()> {
+        let summary = summary::summarize(content);
+        conn.execute("UPDATE post SET word_count=?1, reading_minutes=?2, excerpt=?3 WHERE id=?4",
+                     params![summary.word_count, summary.reading_minutes, summary.excerpt, id])?;
+        Ok(())
+    }
+
+    // Whether a post other than `exclude_id` has the slug
+    fn query_slug_taken(conn: &Connection, slug: &str, exclude_id: Option<u32>) -> rusqlite::Result<bool> {
+        Ok(conn.query_row("SELECT 1 FROM post WHERE slug=?1 AND id IS NOT ?2", params![slug, exclude_id], |_| Ok(()))
+            .optional()?
+            .is_some())
+    }
 
+    // First of `base`, `base-2`, `base-3`... that is neither reserved nor used by another post
+    fn unique_slug(conn: &Connection, base: &str, exclude_id: Option<u32>) -> rusqlite::Result<String> {
+        let mut candidate = base.to_string();
+        let mut n = 1;
+        loop {
+            let taken = slug::is_reserved(&candidate) || Self::query_slug_taken(conn, &candidate, exclude_id)?;
+            if !taken {
+                return Ok(candidate);
+            }
+            n += 1;
+            candidate = format!("{}-{}", base, n);
+        }
+    }
+
+    // Single post matching `condition`, which has one parameter
+    fn find_post(&self, condition: &str, param: &str) -> Option<Post> {
+        let conn = self.conn.lock().unwrap();
+        let res = conn.query_row(&format!("SELECT {} FROM {} WHERE {}", POST_COLUMNS, POST_TABLES, condition),
+                                 params![param],
+                                 post_from_row)
+            .optional()
+            .and_then(|post| match post {
+                Some(mut post) => {
+                    post.ident.tags = Self::query_tags(&conn, post.ident.id)?;
+                    Ok(Some(post))
+                },
+                None => Ok(None),
+            });
+        res.unwrap_or_else(|e| {
+            error!("Error getting post: {}", e);
+            None
+        })
+    }
+
+    fn query_post(conn: &Connection, id: u32) -> rusqlite::Result<Post> {
+        let mut post = conn.query_row(&format!("SELECT {} FROM {} WHERE p.id=?1", POST_COLUMNS, POST_TABLES),
+                                      params![id],
+                                      post_from_row)?;
+        post.ident.tags = Self::query_tags(conn, id)?;
+        Ok(post)
+    }
+
+    // Selects post idents, `clause` being everything after the FROM
+    fn query_idents<P: rusqlite::Params>(conn: &Connection, clause: &str, params: P) -> rusqlite::Result<Vec<PostIdent>> {
+        let mut stmt = conn.prepare(&format!("SELECT {} FROM post p {}", IDENT_COLUMNS, clause))?;
+        let mut items = stmt.query_map(params, ident_from_row)
+            .inspect_err(|e: &rusqlite::Error| {
+                error!("Error, {}", e);
+            })?
+            .collect::<rusqlite::Result<Vec<PostIdent>>>()?;
+        for item in items.iter_mut() {
+            item.tags = Self::query_tags(conn, item.id)?;
+        }
+        Ok(items)
+    }
+
+    // Same as query_idents, with the content
+    fn query_posts<P: rusqlite::Params>(conn: &Connection, clause: &str, params: P) -> rusqlite::Result<Vec<Post>> {
+        let mut stmt = conn.prepare(&format!("SELECT {} FROM {} {}", POST_COLUMNS, POST_TABLES, clause))?;
+        let mut items = stmt.query_map(params, post_from_row)?
+            .collect::<rusqlite::Result<Vec<Post>>>()?;
+        for item in items.iter_mut() {
+            item.ident.tags = Self::query_tags(conn, item.ident.id)?;
+        }
+        Ok(items)
+    }
+
+    fn query_series(conn: &Connection, id: u32) -> rusqlite::Result<Series> {
+        let mut series = conn.query_row("SELECT id, slug, title, description FROM series WHERE id=?1",
+                                        params![id],
+                                        series_from_row)?;
+        series.posts = Self::query_series_posts(conn, id)?;
+        Ok(series)
+    }
+
+    fn query_series_posts(conn: &Connection, series_id: u32) -> rusqlite::Result<Vec<u32>> {
+        let mut stmt = conn.prepare_cached("SELECT post_id FROM series_post WHERE series_id=?1 ORDER BY position")?;
+        let ret = stmt.query_map(params![series_id], |row| row.get(0))?;
+        ret.collect()
+    }
+
+    fn series_slug(input: &SeriesInput) -> String {
+        slug::slugify(input.slug.as_deref().unwrap_or(&input.title))
+    }
+
+    // Replaces the parts of a series, taking them out of any other series
+    fn set_series_posts(conn: &Connection, series_id: u32, post_ids: &[u32]) -> rusqlite::Result<()> {
+        conn.execute("DELETE FROM series_post WHERE series_id=?1", params![series_id])?;
+        for (position, post_id) in post_ids.iter().enumerate() {
+            conn.execute("INSERT OR REPLACE INTO series_post (post_id, series_id, position) VALUES (?1, ?2, ?3)",
+                         params![post_id, series_id, position as u32 + 1])?;
+        }
+        Ok(())
+    }
+
+    // Selects media, `clause` being everything after the FROM
+    fn query_media<P: rusqlite::Params>(conn: &Connection, clause: &str, params: P) -> rusqlite::Result<Vec<Media>> {
+        let mut stmt = conn.prepare(&format!("SELECT {} FROM media {}", MEDIA_COLUMNS, clause))?;
+        let mut items = stmt.query_map(params, media_from_row)?
+            .collect::<rusqlite::Result<Vec<Media>>>()?;
+        let mut variants = conn.prepare_cached("SELECT width, height, file_name, size FROM media_variant \
+                                                WHERE media_id=?1 ORDER BY width")?;
+        let mut posts = conn.prepare_cached("SELECT post_id FROM media_post WHERE media_id=?1 ORDER BY post_id")?;
+        for item in items.iter_mut() {
+            item.post_ids = posts.query_map(params![item.id], |row| row.get(0))?
+                .collect::<rusqlite::Result<Vec<u32>>>()?;
+            item.variants = variants.query_map(params![item.id], |row| {
+                let file_name: String = row.get(2)?;
+                Ok(ImageVariant {
+                    width: row.get(0)?,
+                    height: row.get(1)?,
+                    url: format!("{}/{}", constants::MEDIA_URL, file_name),
+                    file_name,
+                    size: row.get(3)?,
+                })
+            })?.collect::<rusqlite::Result<Vec<ImageVariant>>>()?;
+        }
+        Ok(items)
+    }
+
+    fn query_one_media<P: rusqlite::Params>(conn: &Connection, condition: &str, params: P) -> rusqlite::Result<Option<Media>> {
+        Ok(Self::query_media(conn, &format!("WHERE {}", condition), params)?.pop())
+    }
+
+    fn query_tags(conn: &Connection, post_id: u32) -> rusqlite::Result<Vec<String>> {
+        let mut stmt = conn.prepare_cached("SELECT t.name FROM tag t JOIN post_tag pt ON pt.tag_id = t.id \
+                                            WHERE pt.post_id=?1 ORDER BY t.name")?;
+        let ret = stmt.query_map(params![post_id], |row| row.get(0))?;
+        ret.collect()
+    }
+
+    // Replaces the tags of a post, creating the missing ones
+    fn set_post_tags(conn: &Connection, post_id: u32, tags: &[String]) -> rusqlite::Result<()> {
+        conn.execute("DELETE FROM post_tag WHERE post_id=?1", params![post_id])?;
+        for tag in tags.iter().filter_map(|t| normalize_tag(t)) {
+            conn.execute("INSERT OR IGNORE INTO tag (name) VALUES (?1)", params![tag])?;
+            conn.execute("INSERT OR IGNORE INTO post_tag (post_id, tag_id) SELECT ?1, id FROM tag WHERE name=?2",
+                         params![post_id, tag])?;
+        }
+        Ok(())
+    }
+
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::super::entity::ProfileLink;
+
+    fn helper_test_db() -> LiteDB {
+        LiteDB::for_tests()
+    }
+
+    fn helper_input(title: &str, content: &str) -> PostInput {
+        PostInput {
+            title: title.to_string(),
+            content: content.to_string(),
+            tags: None,
+            slug: None,
+            status: None,
+            published_at: None,
+        }
+    }
+
+    #[test]
+    fn test_create_update_delete_post() {
+        let db = helper_test_db();
+        let created = db.create_post(1, &helper_input("Hello", "*world*"), false).unwrap();
+        assert_eq!(created.ident.title, "Hello");
+        assert_eq!(created.content, "*world*");
+        assert!(created.ident.created > 0);
+
+        let updated = db.update_post(created.ident.id, &helper_input("Hello again", "bye"), false).unwrap().unwrap();
+        assert_eq!(updated.ident.id, created.ident.id);
+        assert_eq!(updated.ident.title, "Hello again");
+        assert_eq!(updated.content, "bye");
+        assert!(updated.updated >= created.updated);
+
+        assert!(db.delete_post(created.ident.id).unwrap());
+        assert!(db.get_post_by_id(created.ident.id).is_none());
+        assert!(!db.delete_post(created.ident.id).unwrap());
+    }
+
+    #[test]
+    fn test_post_writes_are_atomic() {
+        let db = helper_test_db();
+        let post = db.create_post(1, &helper_input("Kept", "Before"), false).unwrap();
+        // Tags can't be stored: the post must not be half written
+        db.conn.lock().unwrap()
+            .execute_batch("CREATE TRIGGER no_tags BEFORE INSERT ON post_tag BEGIN SELECT RAISE(ABORT, 'no tags'); END;")
+            .unwrap();
+        let mut input = helper_input("Tagged", "After");
+        input.tags = Some(vec!["rust".to_string()]);
+        assert!(db.create_post(1, &input, false).is_err());
+        assert!(db.get_any_post_by_slug("tagged").is_none());
+        assert!(db.update_post(post.ident.id, &input, false).is_err());
+        let kept = db.get_post_by_id(post.ident.id).unwrap();
+        assert_eq!((kept.ident.title.as_str(), kept.content.as_str()), ("Kept", "Before"));
+        assert_eq!(kept.ident.summary.excerpt, "Before");
+        assert!(db.get_post_by_id(42).is_none());
+    }
+
+    #[test]
+    fn test_update_missing_post() {
+        let db = helper_test_db();
+        assert!(db.update_post(42, &helper_input("Nope", ""), false).unwrap().is_none());
+    }
+
+    // Database of an earlier schema version, with the rows of `sql`, migrated to the current one
+    fn helper_migrated_db(version: usize, sql: &str) -> LiteDB {
+        let db = LiteDB::load(":memory:");
+        {
+            let conn = db.conn.lock().unwrap();
+            conn.execute_batch(BLOG_TABLES_SQL).unwrap();
+            conn.execute_batch(CHAT_TABLES_SQL).unwrap();
+            for migration in &MIGRATIONS[..version] {
+                conn.execute_batch(migration).unwrap();
+            }
+            conn.execute_batch(&format!("PRAGMA user_version = {};\n{}", version, sql)).unwrap();
+        }
+        db.check_or_create_tables().unwrap();
+        db
+    }
+
+    #[test]
+    fn test_roles_migration() {
+        let db = helper_migrated_db(12, "INSERT INTO user (username, email, is_admin) VALUES
+            ('admin', 'admin@example.com', 1), ('bob', 'bob@example.com', 0);");
+        let roles: Vec<(String, Role)> = db.get_users().unwrap().into_iter().map(|u| (u.name, u.role)).collect();
+        assert_eq!(roles, vec![("admin".to_string(), Role::Admin), ("bob".to_string(), Role::Commenter)]);
+    }
+
+    #[test]
+    fn test_api_keys_become_tokens() {
+        // Users had API keys before migration 17
+        let db = helper_migrated_db(16, "INSERT INTO user (username, email, role, api_key_hash) VALUES
+            ('admin', 'admin@example.com', 'admin', 'abc'), ('bob', 'bob@example.com', 'author', 'def'),
+            ('carol', 'carol@example.com', 'author', NULL);");
+
+        let (user, scopes) = db.use_api_token("abc").unwrap().unwrap();
+        assert_eq!((user.name.as_str(), user.role), ("admin", Role::Admin));
+        assert_eq!(scopes, vec!["posts", "series", "media", "admin"]);
+        let (user, scopes) = db.use_api_token("def").unwrap().unwrap();
+        assert_eq!((user.name.as_str(), scopes), ("bob", vec!["posts".to_string(), "series".to_string(), "media".to_string()]));
+        let tokens = db.get_api_tokens(2).unwrap();
+        assert_eq!((tokens.len(), tokens[0].name.as_str()), (1, "API key"));
+        assert!(tokens[0].last_used.is_some());
+        assert!(db.get_api_tokens(3).unwrap().is_empty());
+        // Revoked like the other tokens
+        assert!(db.delete_api_token(2, tokens[0].id).unwrap());
+        assert!(db.use_api_token("def").unwrap().is_none());
+    }
+
+    #[test]
+    fn test_user_roles() {
+        let db = helper_test_db();
+        assert_eq!(db.get_users().unwrap()[0].role, Role::Admin);
+        let post = db.create_post(1, &helper_input("Mine", ""), false).unwrap();
+        assert_eq!(db.set_user_role(1, Role::ChatModerator).unwrap().unwrap().role, Role::ChatModerator);
+        assert!(db.set_user_role(2, Role::Editor).unwrap().is_none());
+
+        assert_eq!(db.get_post_author_id(post.ident.id).unwrap(), Some(1));
+        assert_eq!(db.get_post_author_id(42).unwrap(), None);
+    }
+
+    #[test]
+    fn test_trusted_html() {
+        let db = helper_test_db();
+        let post = db.create_post(1, &helper_input("Embed", "<iframe></iframe>"), true).unwrap();
+        assert!(post.trusted_html);
+        // Whoever writes the content last decides, whoever the author is
+        let id = post.ident.id;
+        assert!(!db.update_post(id, &helper_input("Embed", "<iframe src=\"x\"></iframe>"), false).unwrap().unwrap().trusted_html);
+        assert!(!db.get_post_by_id(id).unwrap().trusted_html);
+        let revision = db.get_revisions(id).unwrap()[0].id;
+        assert!(!db.restore_revision(id, revision, false).unwrap().unwrap().trusted_html);
+        let revision = db.get_revisions(id).unwrap()[0].id;
+        assert!(db.restore_revision(id, revision, true).unwrap().unwrap().trusted_html);
+
+        // Posts from before the column are trusted when their author is an admin
+        let db = helper_migrated_db(17, "INSERT INTO user (username, email, role) VALUES
+              ('admin', 'admin@example.com', 'admin'), ('bob', 'bob@example.com', 'author');
+            INSERT INTO post (title, content, author_id) VALUES ('A', '', 1), ('B', '', 2);");
+        let trusted: Vec<bool> = (1..=2).map(|id| db.get_post_by_id(id).unwrap().trusted_html).collect();
+        assert_eq!(trusted, vec![true, false]);
+    }
+
+    #[test]
+    fn test_profiles_and_authors() {
+        let db = helper_test_db();
+        let post = db.create_post(1, &helper_input("Mine", ""), false).unwrap();
+        let author = post.author.unwrap();
+        assert_eq!((author.id, author.username.as_str(), author.name.as_str()), (1, "admin", "admin"));
+
+        let profile = db.get_profile("admin").unwrap().unwrap();
+        assert_eq!((profile.display_name, profile.bio.as_str(), profile.links.len()), (None, "", 0));
+        let links = vec![ProfileLink { title: "GitHub".to_string(), url: "https://github.com/admin".to_string() }];
+        let input = ProfileInput { display_name: Some(" Ada ".to_string()), bio: "Hi *there*".to_string(), links: links.clone() };
+        let version = db.content_version().unwrap();
+        let profile = db.set_profile(1, &input).unwrap().unwrap();
+        assert_eq!((profile.name.as_str(), profile.bio.as_str(), &profile.links), ("Ada", "Hi *there*", &links));
+        assert_ne!(db.content_version().unwrap(), version);
+        assert_eq!(db.get_post_by_id(post.ident.id).unwrap().author.unwrap().name, "Ada");
+        assert_eq!(db.get_profile_by_id(1).unwrap().unwrap().display_name.as_deref(), Some("Ada"));
+        assert!(db.get_profile("nobody").unwrap().is_none());
+        assert!(db.set_profile(2, &input).unwrap().is_none());
+
+        let mut draft = helper_input("Draft", "");
+        draft.status = Some(PostStatus::Draft);
+        db.create_post(1, &draft, false).unwrap();
+        assert_eq!(db.count_posts_by_author(1).unwrap(), 1);
+        assert_eq!(db.get_posts_by_author(1, 10, 0).unwrap()[0].id, post.ident.id);
+        assert_eq!(db.count_posts_by_author(2).unwrap(), 0);
+    }
+
+    #[test]
+    fn test_sessions() {
+        let db = helper_test_db();
+        let (user, password) = db.get_user_credentials("admin").unwrap().unwrap();
+        assert_eq!((user.id, password), (1, None));
+        assert!(db.get_user_credentials("nobody").unwrap().is_none());
+        assert!(db.set_password_hash("admin", "$argon2id$x").unwrap());
+        assert!(!db.set_password_hash("nobody", "$argon2id$x").unwrap());
+        assert_eq!(db.get_user_credentials("admin").unwrap().unwrap().1.as_deref(), Some("$argon2id$x"));
+
+        db.create_session("t1", 1, 30).unwrap();
+        assert_eq!(db.get_user_by_session("t1").unwrap().unwrap().name, "admin");
+        assert!(db.get_user_by_session("t2").unwrap().is_none());
+        db.delete_session("t1").unwrap();
+        assert!(db.get_user_by_session("t1").unwrap().is_none());
+
+        // Expired, and ended by a new password
+        db.create_session("t3", 1, 30).unwrap();
+        db.conn.lock().unwrap()
+            .execute("UPDATE session SET expires_at = datetime('now', '-1 minute') WHERE token_hash='t3'", [])
+            .unwrap();
+        assert!(db.get_user_by_session("t3").unwrap().is_none());
+        db.create_session("t4", 1, 30).unwrap();
+        db.set_password_hash("admin", "$argon2id$y").unwrap();
+        assert!(db.get_user_by_session("t4").unwrap().is_none());
+    }
+
+    #[test]
+    fn test_api_tokens() {
+        let db = helper_test_db();
+        let scopes = vec!["posts".to_string(), "media".to_string()];
+        let token = db.create_api_token(1, "ci", "h1", &scopes, None).unwrap();
+        assert_eq!((token.name.as_str(), &token.scopes, token.expires, token.last_used), ("ci", &scopes, None, None));
+        assert!(token.created > 0);
+        assert!(db.create_api_token(1, "dup", "h1", &scopes, None).is_err());
+
+        let (user, used_scopes) = db.use_api_token("h1").unwrap().unwrap();
+        assert_eq!((user.name.as_str(), used_scopes), ("admin", scopes.clone()));
+        assert!(db.get_api_tokens(1).unwrap()[0].last_used.is_some());
+        assert!(db.use_api_token("h2").unwrap().is_none());
+
+        // Expired
+        let expired = db.create_api_token(1, "old", "h3", &scopes, Some(1_000_000_000_000)).unwrap();
+        assert_eq!(expired.expires, Some(1_000_000_000_000));
+        assert!(db.use_api_token("h3").unwrap().is_none());
+        assert_eq!(db.get_api_tokens(1).unwrap().iter().map(|t| t.id).collect::<Vec<_>>(), vec![expired.id, token.id]);
+
+        assert!(!db.delete_api_token(2, token.id).unwrap());
+        assert!(db.delete_api_token(1, token.id).unwrap());
+        assert!(db.use_api_token("h1").unwrap().is_none());
+    }
+
+    #[test]
+    fn test_normalize_tag() {
+        assert_eq!(normalize_tag("Rust"), Some("rust".to_string()));
+        assert_eq!(normalize_tag("  web  dev "), Some("web-dev".to_string()));
+        assert_eq!(normalize_tag("c++/c#"), Some("c++c".to_string()));
+        assert_eq!(normalize_tag("日本語"), Some("日本語".to_string()));
+        assert_eq!(normalize_tag(" ?! "), None);
+    }
+
+    #[test]
+    fn test_post_tags() {
+        let db = helper_test_db();
+        let mut input = helper_input("Tagged", "");
+        input.tags = Some(vec!["Rust".to_string(), "web dev".to_string(), "rust".to_string()]);
+        let first = db.create_post(1, &input, false).unwrap();
+        assert_eq!(first.ident.tags, vec!["rust", "web-dev"]);
+
+        input.tags = Some(vec!["rust".to_string()]);
+        let second = db.create_post(1, &input, false).unwrap();
+
+        let tags = db.get_tags().unwrap();
+        assert_eq!(tags.len(), 2);
+        assert_eq!((tags[0].name.as_str(), tags[0].count), ("rust", 2));
+        assert_eq!((tags[1].name.as_str(), tags[1].count), ("web-dev", 1));
+
+        let rust_posts = db.get_posts_by_tag("rust", 10, 0).unwrap();
+        assert_eq!(rust_posts.len(), 2);
+        let web_posts = db.get_posts_by_tag("web-dev", 10, 0).unwrap();
+        assert_eq!(web_posts.len(), 1);
+        assert_eq!(web_posts[0].id, first.ident.id);
+
+        // an edit without tags keeps them
+        input.tags = None;
+        let kept = db.update_post(first.ident.id, &input, false).unwrap().unwrap();
+        assert_eq!(kept.ident.tags, vec!["rust", "web-dev"]);
+
+        // updating replaces the tags, unused tags drop out of the index
+        input.tags = Some(Vec::new());
+        db.update_post(first.ident.id, &input, false).unwrap();
+        let tags = db.get_tags().unwrap();
+        assert_eq!(tags.len(), 1);
+        assert_eq!(tags[0].count, 1);
+        assert_eq!(db.get_posts_by_tag("rust", 10, 0).unwrap()[0].id, second.ident.id);
+    }
+
+    #[test]
+    fn test_post_slugs() {
+        let db = helper_test_db();
+        let first = db.create_post(1, &helper_input("Hello World", ""), false).unwrap();
+        assert_eq!(first.ident.slug, "hello-world");
+        let second = db.create_post(1, &helper_input("Hello, world!", ""), false).unwrap();
+        assert_eq!(second.ident.slug, "hello-world-2");
+        let reserved = db.create_post(1, &helper_input("Tags", ""), false).unwrap();
+        assert_eq!(reserved.ident.slug, "tags-2");
+
+        // the slug is kept on edits unless one is given
+        let edited = db.update_post(first.ident.id, &helper_input("Renamed", ""), false).unwrap().unwrap();
+        assert_eq!(edited.ident.slug, "hello-world");
+        let mut input = helper_input("Renamed", "");
+        input.slug = Some("My Slug".to_string());
+        let edited = db.update_post(first.ident.id, &input, false).unwrap().unwrap();
+        assert_eq!(edited.ident.slug, "my-slug");
+        assert_eq!(db.get_post_by_slug("my-slug").unwrap().ident.id, first.ident.id);
+        assert!(db.get_post_by_slug("hello-world").is_none());
+
+        // explicit slugs must be unique
+        assert!(db.is_slug_taken("my-slug", Some(second.ident.id)).unwrap());
+        assert!(!db.is_slug_taken("my-slug", Some(first.ident.id)).unwrap());
+        assert!(!db.is_slug_taken("free", None).unwrap());
+        assert!(db.update_post(second.ident.id, &input, false).is_err());
+    }
+
+    #[test]
+    fn test_backfill_slugs() {
+        let db = helper_test_db();
+        {
+            let conn = db.conn.lock().unwrap();
+            conn.execute("INSERT INTO post (title, content, author_id) VALUES ('Old post', '', 1), ('Old post', '', 1)", []).unwrap();
+            assert_eq!(LiteDB::query_post(&conn, 1).unwrap().ident.slug, "post-1");
+            // as if the posts were there before the slug column
+            conn.execute("UPDATE post SET slug = NULL", []).unwrap();
+        }
+        db.check_or_create_tables().unwrap();
+        let posts = db.get_posts(10, 0).unwrap();
+        let mut slugs: Vec<String> = posts.into_iter().map(|p| p.slug).collect();
+        slugs.sort();
+        assert_eq!(slugs, vec!["old-post", "old-post-2"]);
+    }
+
+    #[test]
+    fn test_stored_summaries() {
+        let db = helper_test_db();
+        let post = db.create_post(1, &helper_input("Title", "Some *words* here"), false).unwrap();
+        assert_eq!((post.ident.summary.word_count, post.ident.summary.excerpt.as_str()), (3, "Some words here"));
+        let post = db.update_post(post.ident.id, &helper_input("Title", "Changed"), false).unwrap().unwrap();
+        assert_eq!(db.get_posts(10, 0).unwrap()[0].summary.excerpt, "Changed");
+        // as if the post was there before the summary columns
+        db.conn.lock().unwrap().execute("UPDATE post SET word_count = NULL, reading_minutes = NULL, excerpt = NULL", []).unwrap();
+        assert_eq!(db.get_posts(10, 0).unwrap()[0].summary.excerpt, "");
+        db.check_or_create_tables().unwrap();
+        let ident = &db.get_posts(10, 0).unwrap()[0];
+        assert_eq!((ident.id, ident.summary.word_count, ident.summary.excerpt.as_str()), (post.ident.id, 1, "Changed"));
+    }
+
+    #[test]
+    fn test_search_posts() {
+        let db = helper_test_db();
+        let rust = db.create_post(1, &helper_input("Rust notes", "Ownership and borrowing in Rust."), false).unwrap();
+        let japanese = db.create_post(1, &helper_input("日本語の勉強", "今日は日本語を勉強しました。Rust も少し。"), false).unwrap();
+        db.create_post(1, &helper_input("Chinese", "我在学习中文。"), false).unwrap();
+
+        // title matches rank first
+        let results = db.search_posts("rust", 10).unwrap();
+        assert_eq!(results.len(), 2);
+        assert_eq!(results[0].ident.id, rust.ident.id);
+        assert!(results[0].score >= results[1].score);
+        assert!(results[0].snippet.contains("<mark>Rust</mark>"));
+
+        // CJK without spaces, long and short terms
+        let results = db.search_posts("勉強しました", 10).unwrap();
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].ident.id, japanese.ident.id);
+        let results = db.search_posts("中文", 10).unwrap();
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].ident.title, "Chinese");
+        let results = db.search_posts("日本 rust", 10).unwrap();
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].ident.id, japanese.ident.id);
+
+        // the index follows edits and deletes
+        db.update_post(rust.ident.id, &helper_input("Go notes", "Goroutines."), false).unwrap();
+        assert_eq!(db.search_posts("rust", 10).unwrap().len(), 1);
+        assert_eq!(db.search_posts("goroutines", 10).unwrap().len(), 1);
+        db.delete_post(rust.ident.id).unwrap();
+        assert!(db.search_posts("goroutines", 10).unwrap().is_empty());
+
+        // operators are taken literally
+        assert!(db.search_posts("\"OR\" NEAR( *", 10).unwrap().is_empty());
+        assert!(db.search_posts("%", 10).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_post_status() {
+        let db = helper_test_db();
+        let public = db.create_post(1, &helper_input("Public", "visible text"), false).unwrap();
+        assert_eq!(public.status, PostStatus::Published);
+        assert!(public.ident.published.is_some());
+
+        let mut input = helper_input("Draft", "hidden text");
+        input.status = Some(PostStatus::Draft);
+        input.tags = Some(vec!["secret".to_string()]);
+        let draft = db.create_post(1, &input, false).unwrap();
+        assert_eq!(draft.status, PostStatus::Draft);
+        assert!(draft.ident.published.is_none());
+
+        let mut input = helper_input("Later", "hidden text");
+        input.published_at = Some(4_102_444_800_000); // 2100-01-01
+        let scheduled = db.create_post(1, &input, false).unwrap();
+        assert_eq!(scheduled.status, PostStatus::Scheduled);
+        assert_eq!(scheduled.ident.published, Some(4_102_444_800_000));
+
+        // only the published post shows up anywhere public
+        let ids: Vec<u32> = db.get_posts(10, 0).unwrap().iter().map(|p| p.id).collect();
+        assert_eq!(ids, vec![public.ident.id]);
+        assert_eq!(db.get_recent_posts(10).unwrap().len(), 1);
+        assert!(db.search_posts("hidden", 10).unwrap().is_empty());
+        assert!(db.get_tags().unwrap().is_empty());
+        assert!(db.get_posts_by_tag("secret", 10, 0).unwrap().is_empty());
+        assert!(db.get_post_by_slug(&draft.ident.slug).is_none());
+        assert!(db.get_post_by_slug(&scheduled.ident.slug).is_none());
+
+        // but can be previewed with its token
+        let preview = db.get_post_by_preview_token(&draft.preview_token).unwrap();
+        assert_eq!(preview.ident.id, draft.ident.id);
+        assert!(db.get_post_by_preview_token("nope").is_none());
+
+        // publishing a draft dates it
+        let mut input = helper_input("Draft", "hidden text");
+        input.status = Some(PostStatus::Published);
+        let published = db.update_post(draft.ident.id, &input, false).unwrap().unwrap();
+        assert_eq!(published.status, PostStatus::Published);
+        assert!(published.ident.published.is_some());
+        assert_eq!(db.get_posts(10, 0).unwrap().len(), 2);
+
+        // scheduled posts appear when their time arrives
+        let mut input = helper_input("Later", "hidden text");
+        input.published_at = Some(1_000_000_000_000);
+        db.update_post(scheduled.ident.id, &input, false).unwrap();
+        assert_eq!(db.get_posts(10, 0).unwrap().len(), 3);
+    }
+
+    #[test]
+    fn test_revisions() {
+        let db = helper_test_db();
+        let post = db.create_post(1, &helper_input("v1", "one"), false).unwrap();
+        let id = post.ident.id;
+        db.update_post(id, &helper_input("v2", "two"), false).unwrap();
+        // no revision when only the metadata changes
+        db.update_post(id, &helper_input("v2", "two"), false).unwrap();
+        db.update_post(id, &helper_input("v3", "three"), false).unwrap();
+
+        let revisions = db.get_revisions(id).unwrap();
+        let titles: Vec<&str> = revisions.iter().map(|r| r.title.as_str()).collect();
+        assert_eq!(titles, vec!["v2", "v1"]);
+        assert_eq!(revisions[1].content, "one");
+
+        let oldest = revisions[1].id;
+        assert_eq!(db.get_revision(id, oldest).unwrap().unwrap().title, "v1");
+        assert!(db.get_revision(id + 1, oldest).unwrap().is_none());
+
+        let restored = db.restore_revision(id, oldest, false).unwrap().unwrap();
+        assert_eq!(restored.ident.title, "v1");
+        assert_eq!(restored.content, "one");
+        assert_eq!(restored.ident.summary.excerpt, "one");
+        // the restored-over version is kept
+        assert_eq!(db.get_revisions(id).unwrap()[0].title, "v3");
+        assert!(db.restore_revision(id, 999, false).unwrap().is_none());
+
+        // revisions go away with the post
+        db.delete_post(id).unwrap();
+        assert!(db.get_revisions(id).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_pagination_and_archive() {
+        let db = helper_test_db();
+        let dates = [
+            1_700_000_000_000, // 2023-11-14
+            1_701_000_000_000, // 2023-11-26
+            1_704_100_000_000, // 2024-01-01
+        ];
+        for (i, date) in dates.iter().enumerate() {
+            let mut input = helper_input(&format!("Post {}", i), "");
+            input.published_at = Some(*date);
+            input.tags = Some(vec!["all".to_string()]);
+            db.create_post(1, &input, false).unwrap();
+        }
+        let mut draft = helper_input("Draft", "");
+        draft.status = Some(PostStatus::Draft);
+        db.create_post(1, &draft, false).unwrap();
+
+        assert_eq!(db.count_posts().unwrap(), 3);
+        assert_eq!(db.count_posts_by_tag("all").unwrap(), 3);
+        let titles = |posts: Vec<PostIdent>| posts.into_iter().map(|p| p.title).collect::<Vec<String>>();
+        assert_eq!(titles(db.get_posts(2, 0).unwrap()), vec!["Post 2", "Post 1"]);
+        assert_eq!(titles(db.get_posts(2, 2).unwrap()), vec!["Post 0"]);
+
+        let archive = db.get_archive().unwrap();
+        let periods: Vec<(i32, u32, u32)> = archive.iter().map(|a| (a.year, a.month, a.count)).collect();
+        assert_eq!(periods, vec![(2024, 1, 1), (2023, 11, 2)]);
+        assert_eq!(titles(db.get_posts_by_month(2023, 11, 10, 0).unwrap()), vec!["Post 1", "Post 0"]);
+        assert!(db.get_posts_by_month(2023, 12, 10, 0).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_content_version() {
+        let db = helper_test_db();
+        let empty = db.content_version().unwrap();
+        assert_eq!(db.content_version().unwrap(), empty);
+        let post = db.create_post(1, &helper_input("Title", "content"), false).unwrap();
+        let created = db.content_version().unwrap();
+        assert_ne!(created, empty);
+        assert_eq!(db.content_version().unwrap(), created);
+        db.update_post(post.ident.id, &helper_input("Title", "changed"), false).unwrap();
+        assert_ne!(db.content_version().unwrap(), created);
+        // Scheduled post becoming visible, without any write
+        let mut scheduled = helper_input("Later", "");
+        scheduled.published_at = Some(chrono::Utc::now().timestamp_millis() + 1000);
+        db.create_post(1, &scheduled, false).unwrap();
+        let before = db.content_version().unwrap();
+        assert_eq!(db.content_version().unwrap(), before);
+        std::thread::sleep(std::time::Duration::from_millis(1100));
+        assert_ne!(db.content_version().unwrap(), before);
+    }
+
+    #[test]
+    fn test_related_posts() {
+        let db = helper_test_db();
+        let mut input = helper_input("Rust lifetimes", "The borrow checker and lifetimes.");
+        input.tags = Some(vec!["rust".to_string()]);
+        let first = db.create_post(1, &input, false).unwrap();
+        input.title = "Rust traits".to_string();
+        input.content = "Traits and the borrow checker.".to_string();
+        let second = db.create_post(1, &input, false).unwrap();
+        let bread = db.create_post(1, &helper_input("Bread", "Flour and water."), false).unwrap();
+        assert!(db.related_stale());
+        db.refresh_related().unwrap();
+        assert!(!db.related_stale());
+        let related = db.get_related_posts(first.ident.id, 5).unwrap();
+        assert_eq!(related.iter().map(|p| p.id).collect::<Vec<u32>>(), vec![second.ident.id]);
+        assert!(db.get_related_posts(bread.ident.id, 5).unwrap().is_empty());
+
+        // Computed again after a change, not when they are read
+        input.title = "Bread with the borrow checker".to_string();
+        input.content = "Flour, water and lifetimes.".to_string();
+        db.update_post(bread.ident.id, &input, false).unwrap();
+        assert!(db.related_stale());
+        assert_eq!(db.get_related_posts(first.ident.id, 5).unwrap().len(), 1);
+        let version = db.content_version().unwrap();
+        db.refresh_related().unwrap();
+        assert_ne!(db.content_version().unwrap(), version);
+        assert_eq!(db.get_related_posts(first.ident.id, 5).unwrap().len(), 2);
+        assert_eq!(db.get_related_posts(first.ident.id, 1).unwrap()[0].id, second.ident.id);
+
+        // Drafts are not suggested
+        input.status = Some(PostStatus::Draft);
+        db.update_post(second.ident.id, &input, false).unwrap();
+        assert!(db.get_related_posts(first.ident.id, 5).unwrap().iter().all(|p| p.id != second.ident.id));
+        db.delete_post(bread.ident.id).unwrap();
+        let stored: u32 = db.conn.lock().unwrap()
+            .query_row("SELECT COUNT(*) FROM post_related WHERE related_id=?1", params![bread.ident.id], |row| row.get(0))
+            .unwrap();
+        assert_eq!(stored, 0);
+    }
+
+    #[test]
+    fn test_series() {
+        let db = helper_test_db();
+        let parts: Vec<u32> = (1..=3)
+            .map(|n| db.create_post(1, &helper_input(&format!("Part {}", n), ""), false).unwrap().ident.id)
+            .collect();
+        let input = SeriesInput {
+            title: "Rust Tutorial".to_string(),
+            slug: None,
+            description: "From zero".to_string(),
+            posts: vec![parts[1], parts[0], parts[2]],
+        };
+        let series = db.create_series(&input).unwrap();
+        assert_eq!(series.slug, "rust-tutorial");
+        assert_eq!(series.posts, vec![parts[1], parts[0], parts[2]]);
+        assert_eq!(db.get_series_by_slug("rust-tutorial").unwrap().unwrap().id, series.id);
+
+        let nav = db.get_post_series(parts[0]).unwrap().unwrap();
+        assert_eq!((nav.part, nav.parts), (2, 3));
+        assert_eq!(nav.previous.unwrap().id, parts[1]);
+        assert_eq!(nav.next.unwrap().id, parts[2]);
+        let first = db.get_post_series(parts[1]).unwrap().unwrap();
+        assert!(first.previous.is_none());
+
+        // Drafts are not counted, except when previewing them
+        let mut draft = helper_input("Part 2", "");
+        draft.status = Some(PostStatus::Draft);
+        db.update_post(parts[0], &draft, false).unwrap();
+        let nav = db.get_post_series(parts[2]).unwrap().unwrap();
+        assert_eq!((nav.part, nav.parts), (2, 2));
+        assert_eq!(nav.previous.unwrap().id, parts[1]);
+        assert_eq!(db.get_post_series(parts[0]).unwrap().unwrap().parts, 3);
+        assert_eq!(db.get_series_parts(series.id).unwrap().iter().map(|p| p.id).collect::<Vec<u32>>(),
+                   vec![parts[1], parts[2]]);
+
+        // A post moves to the last series it is put in
+        let other = db.create_series(&SeriesInput { title: "Other".to_string(), slug: None, description: String::new(), posts: vec![parts[2]] }).unwrap();
+        assert_eq!(db.get_post_series(parts[2]).unwrap().unwrap().slug, "other");
+        assert_eq!(db.get_series_list().unwrap().iter().map(|s| s.posts.len()).collect::<Vec<usize>>(), vec![1, 2]);
+        db.delete_post(parts[1]).unwrap();
+        assert_eq!(db.get_series_by_slug("rust-tutorial").unwrap().unwrap().posts, vec![parts[0]]);
+        assert!(db.delete_series(other.id).unwrap());
+        assert!(db.get_post_series(parts[2]).unwrap().is_none());
+        assert!(db.update_series(other.id, &input).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_media() {
+        let db = helper_test_db();
+        let post = db.create_post(1, &helper_input("Photos", ""), false).unwrap();
+        let upload = |hash: &str, post_id| MediaUpload {
+            hash: hash.to_string(),
+            file_name: format!("{}.png", hash),
+            original_name: Some("cat.png".to_string()),
+            mime_type: "image/png".to_string(),
+            size: 42,
+            post_id,
+        };
+        let media = db.create_media(1, &upload("aa", Some(post.ident.id))).unwrap().unwrap();
+        assert_eq!(media.url, "/media/aa.png");
+        assert_eq!((media.size, &media.post_ids), (42, &vec![post.ident.id]));
+        db.create_media(1, &upload("bb", None)).unwrap().unwrap();
+        assert!(db.create_media(1, &upload("aa", None)).unwrap().is_none());
+        assert_eq!(db.get_media_by_hash("aa").unwrap().unwrap().id, media.id);
+        assert_eq!(db.get_media_list(None).unwrap().len(), 2);
+        assert_eq!(db.get_media_list(Some(post.ident.id)).unwrap().len(), 1);
+
+        // The same file used by another post
+        let other = db.create_post(1, &helper_input("More photos", ""), false).unwrap();
+        let linked = db.add_media_post(media.id, other.ident.id).unwrap().unwrap();
+        assert_eq!(linked.post_ids, vec![post.ident.id, other.ident.id]);
+        assert_eq!(db.add_media_post(media.id, other.ident.id).unwrap().unwrap().post_ids.len(), 2);
+        assert_eq!(db.get_media_list(Some(other.ident.id)).unwrap()[0].id, media.id);
+        assert!(db.add_media_post(999, other.ident.id).unwrap().is_none());
+
+        // Kept when the posts go away
+        db.delete_post(post.ident.id).unwrap();
+        db.delete_post(other.ident.id).unwrap();
+        assert!(db.get_media(media.id).unwrap().unwrap().post_ids.is_empty());
+        let variant = ImageVariant {
+            width: 10,
+            height: 5,
+            file_name: "aa-10.webp".to_string(),
+            url: String::new(),
+            size: 7,
+        };
+        db.set_media_variants(media.id, 20, 10, &[variant]).unwrap();
+        let media = db.get_media_by_file_name("aa.png").unwrap().unwrap();
+        assert_eq!((media.width, media.height), (Some(20), Some(10)));
+        assert_eq!(media.variants.len(), 1);
+        assert_eq!(media.variants[0].url, "/media/aa-10.webp");
+        // The pages showing it are rendered again
+        let version = db.content_version().unwrap();
+        assert!(db.delete_media(media.id).unwrap());
+        assert_ne!(db.content_version().unwrap(), version);
+        assert!(db.get_media(media.id).unwrap().is_none());
+        assert!(!db.delete_media(media.id).unwrap());
+    }
 }
@@ -0,0 +1,110 @@
+// Files uploaded for the notes (images, videos...), stored in the media directory
+// under the SHA-256 digest of their content, so a file uploaded twice is stored once.
+
+use std::fs;
+use std::io::{self, Write};
+use std::path::Path;
+
+use super::super::auth;
+
+// Bytes found at an offset of the file
+type Magic = (usize, &'static [u8]);
+
+// Signatures of the files that can be uploaded: (magic bytes, MIME type, extension).
+// The type comes from the content, never from what the client claims.
+const SIGNATURES: &[(&[Magic], &str, &str)] = &[
+    (&[(0, b"\x89PNG\r\n\x1a\n")], "image/png", "png"),
+    (&[(0, b"\xff\xd8\xff")], "image/jpeg", "jpg"),
+    (&[(0, b"GIF87a")], "image/gif", "gif"),
+    (&[(0, b"GIF89a")], "image/gif", "gif"),
+    (&[(0, b"RIFF"), (8, b"WEBP")], "image/webp", "webp"),
+    (&[(4, b"ftypavif")], "image/avif", "avif"),
+    (&[(0, b"%PDF-")], "application/pdf", "pdf"),
+    (&[(4, b"ftyp")], "video/mp4", "mp4"),
+    (&[(0, b"\x1a\x45\xdf\xa3")], "video/webm", "webm"),
+    (&[(0, b"OggS")], "audio/ogg", "ogg"),
+    (&[(0, b"ID3")], "audio/mpeg", "mp3"),
+];
+
+/**
+ * MIME type and file extension of the content, None when it is not a known type
+ */
+pub fn sniff(content: &[u8]) -> Option<(&'static str, &'static str)> {
+    SIGNATURES.iter()
+        .find(|(parts, _, _)| parts.iter().all(|(offset, magic)| content.get(*offset..offset + magic.len()) == Some(*magic)))
+        .map(|(_, mime, extension)| (*mime, *extension))
+}
+
+// Hex encoded SHA-256 digest of the content
+pub fn content_hash(content: &[u8]) -> String {
+    auth::sha256_hex(content)
+}
+
+// Name of the stored file: the digest of the content with the extension of its type
+pub fn file_name(hash: &str, extension: &str) -> String {
+    format!("{}.{}", hash, extension)
+}
+
+// Name of a resized copy of an image
+pub fn variant_file_name(hash: &str, width: u32, extension: &str) -> String {
+    format!("{}-{}.{}", hash, width, extension)
+}
+
+/**
+ * Write the file in the media directory, unless it is already there.
+ * The content goes to a temporary file first so a partial file is never served.
+ */
+pub fn store(dir: &Path, name: &str, content: &[u8]) -> io::Result<()> {
+    let path = dir.join(name);
+    if path.exists() {
+        return Ok(());
+    }
+    fs::create_dir_all(dir)?;
+    let temp_path = dir.join(format!(".{}.tmp", name));
+    let mut file = fs::File::create(&temp_path)?;
+    file.write_all(content)?;
+    file.sync_all()?;
+    fs::rename(&temp_path, &path)
+}
+
+// Delete a stored file. A file already gone is not an error.
+pub fn remove(dir: &Path, name: &str) -> io::Result<()> {
+    match fs::remove_file(dir.join(name)) {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+        _ => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sniff() {
+        assert_eq!(sniff(b"\x89PNG\r\n\x1a\n...."), Some(("image/png", "png")));
+        assert_eq!(sniff(b"\xff\xd8\xff\xe0"), Some(("image/jpeg", "jpg")));
+        assert_eq!(sniff(b"RIFF\x00\x00\x00\x00WEBPVP8 "), Some(("image/webp", "webp")));
+        assert_eq!(sniff(b"\x00\x00\x00\x1cftypavif"), Some(("image/avif", "avif")));
+        assert_eq!(sniff(b"\x00\x00\x00\x18ftypmp42"), Some(("video/mp4", "mp4")));
+        assert_eq!(sniff(b"RIFF\x00\x00\x00\x00WAVEfmt "), None);
+        assert_eq!(sniff(b"<svg onload=\"alert(1)\">"), None);
+        assert_eq!(sniff(b"<html>"), None);
+        assert_eq!(sniff(b""), None);
+    }
+
+    #[test]
+    fn test_store_and_remove() {
+        let dir = std::env::temp_dir().join(format!("media-test-{}", std::process::id()));
+        let name = file_name(&content_hash(b"GIF89a"), "gif");
+        assert_eq!(name.len(), 64 + ".gif".len());
+        store(&dir, &name, b"GIF89a").unwrap();
+        assert_eq!(fs::read(dir.join(&name)).unwrap(), b"GIF89a");
+        // Stored once
+        store(&dir, &name, b"GIF89a").unwrap();
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 1);
+        remove(&dir, &name).unwrap();
+        assert!(!dir.join(&name).exists());
+        remove(&dir, &name).unwrap();
+        fs::remove_dir(&dir).unwrap();
+    }
+}
@@ -2,16 +2,34 @@ use handlebars::{Handlebars, DirectorySourceOptions};
 mod config;
 mod lite_db;
 pub mod solver;
+pub mod slug;
+mod search;
+mod cache;
+mod summary;
+mod related;
+pub mod media;
+pub mod images;
+pub mod sync;
 
-use config::Config;
-use lite_db::LiteDB;
+pub use config::Config;
+pub use lite_db::LiteDB;
+pub use cache::RenderCache;
 
 use log::info;
 
+use super::auth;
+use super::entity::{Permission, User};
+use super::highlight::Highlighter;
+use super::markdown::Renderer;
+use super::sanitize::Sanitizer;
+
 pub struct Datasources {
     hb: handlebars::Handlebars<'static>,
     config: Config,
     db: LiteDB,
+    markdown: Renderer,
+    cache: RenderCache,
+    session_key: Vec<u8>,
 }
 
 impl Datasources {
@@ -38,13 +56,45 @@ impl Datasources {
         let db = LiteDB::load(&config.db_file);
         info!("Database loaded!");
         db.check_or_create_tables().expect("Failed to create tables!");
+        Self::with(handlebars, config, db)
+    }
+
+    // Everything else comes from the config
+    fn with(handlebars: Handlebars<'static>, config: Config, db: LiteDB) -> Self {
+        info!("Loading syntax highlighting...");
+        let highlighter = Highlighter::new(&config.highlight_theme);
+        info!("Syntax highlighting loaded!");
+        let sanitizer = Sanitizer::new(&config.admin_html_tags, &config.author_html_tags);
+        let markdown = Renderer::new(&config.markdown_extensions, highlighter, sanitizer);
+        let cache = RenderCache::new(config.render_cache_size);
+        let session_key = auth::session_key(config.session_secret.as_deref());
         Self {
             hb: handlebars,
             config,
             db,
+            markdown,
+            cache,
+            session_key,
         }
     }
 
+    // Without templates, over LiteDB::for_tests
+    #[cfg(test)]
+    pub fn for_tests() -> Self {
+        let config = serde_json::from_value(serde_json::json!({
+            "site_domain": "example.com",
+            "site_author": "John Titor",
+            "author_twitter": "john_titor",
+            "author_email": "john_titor@example.com",
+            "author_github_name": "john_titor",
+            "port": 3001,
+            "host": "0.0.0.0",
+            "db_file": ":memory:",
+            "static_files": "./external_files"
+        })).unwrap();
+        Self::with(Handlebars::new(), config, LiteDB::for_tests())
+    }
+
     pub fn handlebars(&self) -> &handlebars::Handlebars<'static> {
         &self.hb
     }
@@ -57,6 +107,36 @@ impl Datasources {
         &self.db
     }
 
+    pub fn markdown(&self) -> &Renderer {
+        &self.markdown
+    }
+
+    pub fn cache(&self) -> &RenderCache {
+        &self.cache
+    }
+
+    // Whether the role of the user gives them the permission
+    pub fn can(&self, user: &User, permission: Permission) -> bool {
+        user.role.can(permission)
+    }
+
+    /**
+     * Whether the user may edit (or delete) the post: any post with EditAnyPost, the ones they
+     * wrote with EditOwnPosts. A post that does not exist is left to the caller to report as not found.
+     */
+    pub fn can_edit_post(&self, user: &User, post_id: u32) -> rusqlite::Result<bool> {
+        if self.can(user, Permission::EditAnyPost) {
+            return Ok(true);
+        }
+        Ok(self.can(user, Permission::EditOwnPosts)
+            && self.db.get_post_author_id(post_id)?.is_none_or(|author_id| author_id == user.id))
+    }
+
+    // Key of the HMAC signing the session cookies
+    pub fn session_key(&self) -> &[u8] {
+        &self.session_key
+    }
+
     pub fn close_db(self) -> Result<(), rusqlite::Error> {
         info!("Closing database connection...");
         self.db.close()
@@ -0,0 +1,198 @@
+// Scores of how related two posts are, from the tags they share and the words they have in common.
+//
+// The text similarity is the cosine of the TF-IDF vectors of the posts: words used by every
+// post weigh little, words only two posts use weigh a lot. Text without spaces (CJK) is split
+// into pairs of characters instead of words.
+
+use std::collections::{HashMap, HashSet};
+
+use super::summary;
+
+// Share of the score coming from the tags, the rest comes from the text
+const TAG_WEIGHT: f64 = 0.6;
+const TEXT_WEIGHT: f64 = 1.0 - TAG_WEIGHT;
+// Pairs scoring less are not considered related
+const MIN_SCORE: f64 = 0.05;
+// Related posts stored for each post (more than shown, as some may not be visible)
+pub const STORED_PER_POST: usize = 10;
+
+// Common English words, which say nothing about the subject of a post
+const STOP_WORDS: &[&str] = &[
+    "about", "after", "all", "also", "and", "any", "are", "because", "been", "but", "can", "could",
+    "did", "does", "for", "from", "had", "has", "have", "her", "his", "how", "into", "its", "just",
+    "like", "more", "most", "not", "now", "one", "only", "other", "our", "out", "over", "should",
+    "some", "such", "than", "that", "the", "their", "them", "then", "there", "these", "they",
+    "this", "those", "use", "used", "very", "was", "way", "were", "what", "when", "where", "which",
+    "while", "who", "why", "will", "with", "would", "you", "your",
+];
+
+// What is compared of a post
+pub struct Document {
+    pub id: u32,
+    pub title: String,
+    pub content: String,
+    pub tags: Vec<String>,
+}
+
+/**
+ * Lowercased words of the text, without the short and common ones.
+ * Runs of CJK characters give one term per pair of consecutive characters.
+ */
+pub fn terms(text: &str) -> Vec<String> {
+    let mut terms = Vec::new();
+    let mut word = String::new();
+    let mut previous_cjk: Option<char> = None;
+    let push_word = |word: &mut String, terms: &mut Vec<String>| {
+        if word.chars().count() >= 3 && !STOP_WORDS.contains(&word.as_str()) {
+            terms.push(word.clone());
+        }
+        word.clear();
+    };
+    for c in text.chars() {
+        if summary::is_cjk(c) {
+            push_word(&mut word, &mut terms);
+            if let Some(previous) = previous_cjk {
+                terms.push(format!("{}{}", previous, c));
+            }
+            previous_cjk = Some(c);
+        } else {
+            previous_cjk = None;
+            if c.is_alphanumeric() {
+                word.extend(c.to_lowercase());
+            } else {
+                push_word(&mut word, &mut terms);
+            }
+        }
+    }
+    push_word(&mut word, &mut terms);
+    terms
+}
+
+// TF-IDF vector of each term list, normalized to a length of 1
+fn tf_idf(documents: &[Vec<String>]) -> Vec<HashMap<&str, f64>> {
+    let mut document_frequency: HashMap<&str, usize> = HashMap::new();
+    for terms in documents {
+        for term in terms.iter().map(String::as_str).collect::<HashSet<&str>>() {
+            *document_frequency.entry(term).or_default() += 1;
+        }
+    }
+    let count = documents.len() as f64;
+    documents.iter().map(|terms| {
+        let mut frequency: HashMap<&str, usize> = HashMap::new();
+        for term in terms {
+            *frequency.entry(term.as_str()).or_default() += 1;
+        }
+        let mut vector: HashMap<&str, f64> = frequency.into_iter()
+            .map(|(term, n)| {
+                // Smoothed, so that terms shared by every post still count a little
+                let idf = (1.0 + count / document_frequency[term] as f64).ln();
+                (term, (1.0 + (n as f64).ln()) * idf)
+            })
+            .collect();
+        let norm = vector.values().map(|w| w * w).sum::<f64>().sqrt();
+        if norm > 0.0 {
+            vector.values_mut().for_each(|w| *w /= norm);
+        }
+        vector
+    }).collect()
+}
+
+fn cosine(a: &HashMap<&str, f64>, b: &HashMap<&str, f64>) -> f64 {
+    let (small, large) = if a.len() <= b.len() { (a, b) } else { (b, a) };
+    small.iter().filter_map(|(term, w)| large.get(term).map(|v| w * v)).sum()
+}
+
+// Shared tags over all the tags of the two posts
+fn jaccard(a: &HashSet<&str>, b: &HashSet<&str>) -> f64 {
+    let union = a.union(b).count();
+    if union == 0 {
+        return 0.0;
+    }
+    a.intersection(b).count() as f64 / union as f64
+}
+
+/**
+ * (post, related post, score) of the best `per_post` related posts of every document, best first.
+ * Scores are between 0 and 1, and the same in both directions.
+ */
+pub fn related_scores(documents: &[Document], per_post: usize) -> Vec<(u32, u32, f64)> {
+    let texts: Vec<Vec<String>> = documents.iter()
+        .map(|d| {
+            // The title counts twice, it says the most about the subject
+            let mut t = terms(&d.title);
+            t.extend(terms(&d.title));
+            t.extend(terms(&summary::plain_text(&d.content, false)));
+            t
+        })
+        .collect();
+    let vectors = tf_idf(&texts);
+    let tags: Vec<HashSet<&str>> = documents.iter().map(|d| d.tags.iter().map(String::as_str).collect()).collect();
+
+    let mut candidates: Vec<Vec<(u32, f64)>> = vec![Vec::new(); documents.len()];
+    for i in 0..documents.len() {
+        for j in (i + 1)..documents.len() {
+            let score = TAG_WEIGHT * jaccard(&tags[i], &tags[j]) + TEXT_WEIGHT * cosine(&vectors[i], &vectors[j]);
+            if score >= MIN_SCORE {
+                candidates[i].push((documents[j].id, score));
+                candidates[j].push((documents[i].id, score));
+            }
+        }
+    }
+    let mut scores = Vec::new();
+    for (document, mut related) in documents.iter().zip(candidates) {
+        related.sort_by(|a, b| b.1.total_cmp(&a.1).then(a.0.cmp(&b.0)));
+        scores.extend(related.into_iter().take(per_post).map(|(id, score)| (document.id, id, score)));
+    }
+    scores
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn helper_document(id: u32, title: &str, content: &str, tags: &[&str]) -> Document {
+        Document {
+            id,
+            title: title.to_string(),
+            content: content.to_string(),
+            tags: tags.iter().map(|t| t.to_string()).collect(),
+        }
+    }
+
+    #[test]
+    fn test_terms() {
+        assert_eq!(terms("The **Borrow** checker, and the borrow-checker's rules!"),
+                   vec!["borrow", "checker", "borrow", "checker", "rules"]);
+        assert_eq!(terms("日本語です a"), vec!["日本", "本語", "語で", "です"]);
+    }
+
+    #[test]
+    fn test_related_scores() {
+        let documents = vec![
+            helper_document(1, "Lifetimes in Rust", "The borrow checker and lifetimes.", &["rust"]),
+            helper_document(2, "Rust traits", "Traits, generics and the borrow checker.", &["rust"]),
+            helper_document(3, "Sourdough bread", "Flour, water and salt.", &["baking"]),
+            helper_document(4, "Baguettes", "Flour, water, salt and yeast.", &[]),
+        ];
+        let scores = related_scores(&documents, 10);
+        let score = |a: u32, b: u32| scores.iter().find(|s| s.0 == a && s.1 == b).map(|s| s.2);
+        assert!(score(1, 2).unwrap() > 0.6);
+        assert_eq!(score(1, 2), score(2, 1));
+        // Only the text in common
+        assert!(score(3, 4).unwrap() > MIN_SCORE);
+        assert!(score(3, 4).unwrap() < score(1, 2).unwrap());
+        assert_eq!(score(1, 3), None);
+        assert_eq!(score(2, 4), None);
+    }
+
+    #[test]
+    fn test_best_first() {
+        let documents = vec![
+            helper_document(1, "A", "", &["a", "b"]),
+            helper_document(2, "B", "", &["a"]),
+            helper_document(3, "C", "", &["a", "b"]),
+        ];
+        let scores = related_scores(&documents, 1);
+        assert_eq!(scores.iter().map(|s| (s.0, s.1)).collect::<Vec<_>>(), vec![(1, 3), (2, 1), (3, 1)]);
+    }
+}
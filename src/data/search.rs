@@ -0,0 +1,153 @@
+// Helpers for the full-text search over posts.
+//
+// The post_fts index uses the FTS5 trigram tokenizer so that text without spaces
+// (Japanese, Chinese) can be searched: a term matches wherever it appears as a substring.
+// Trigrams need at least 3 characters, so shorter terms (common for CJK words such as 日本)
+// are matched with LIKE instead.
+
+// Characters of context shown around the first match
+const SNIPPET_BEFORE: usize = 30;
+const SNIPPET_LENGTH: usize = 160;
+
+/**
+ * Split a user query into terms, dropping duplicates
+ */
+pub fn terms(query: &str) -> Vec<String> {
+    let mut terms: Vec<String> = Vec::new();
+    for term in query.split_whitespace().map(|t| t.trim_matches('"').to_lowercase()) {
+        if !term.is_empty() && !terms.contains(&term) {
+            terms.push(term);
+        }
+    }
+    terms
+}
+
+// Whether the term is long enough for the trigram index
+pub fn is_indexable(term: &str) -> bool {
+    term.chars().count() >= 3
+}
+
+/**
+ * FTS5 MATCH expression requiring every indexable term, or None when there is none.
+ * Terms are quoted so that FTS5 operators in the input are taken literally.
+ */
+pub fn match_expression(terms: &[String]) -> Option<String> {
+    let quoted: Vec<String> = terms.iter()
+        .filter(|t| is_indexable(t))
+        .map(|t| format!("\"{}\"", t.replace('"', "\"\"")))
+        .collect();
+    if quoted.is_empty() {
+        None
+    } else {
+        Some(quoted.join(" AND "))
+    }
+}
+
+/**
+ * LIKE pattern matching the term anywhere (with `\` as the escape character)
+ */
+pub fn like_pattern(term: &str) -> String {
+    let escaped = term.replace('\\', "\\\\").replace('%', "\\%").replace('_', "\\_");
+    format!("%{}%", escaped)
+}
+
+fn escape_html(c: char, out: &mut String) {
+    match c {
+        '&' => out.push_str("&amp;"),
+        '<' => out.push_str("&lt;"),
+        '>' => out.push_str("&gt;"),
+        '"' => out.push_str("&quot;"),
+        '\'' => out.push_str("&#39;"),
+        _ => out.push(c),
+    }
+}
+
+// Position (in chars) of every match of a term in `lowered`
+fn find_all(lowered: &[char], term: &[char]) -> Vec<usize> {
+    if term.is_empty() || term.len() > lowered.len() {
+        return Vec::new();
+    }
+    (0..=lowered.len() - term.len())
+        .filter(|&i| lowered[i..i + term.len()] == *term)
+        .collect()
+}
+
+/**
+ * HTML snippet of `text` around the first match of any term, with the matches wrapped in <mark>.
+ * Everything else is escaped, so the result can be inserted in a page as is.
+ */
+pub fn snippet(text: &str, terms: &[String]) -> String {
+    let chars: Vec<char> = text.chars().collect();
+    // Lowercase char by char to keep the positions aligned with `chars`
+    let lowered: Vec<char> = chars.iter()
+        .map(|c| c.to_lowercase().next().unwrap_or(*c))
+        .collect();
+    let mut marked = vec![false; chars.len()];
+    for term in terms {
+        let term: Vec<char> = term.chars().collect();
+        for start in find_all(&lowered, &term) {
+            marked[start..start + term.len()].iter_mut().for_each(|m| *m = true);
+        }
+    }
+    let first = marked.iter().position(|m| *m).unwrap_or(0);
+    let start = first.saturating_sub(SNIPPET_BEFORE);
+    let end = (start + SNIPPET_LENGTH).min(chars.len());
+
+    let mut out = String::new();
+    if start > 0 {
+        out.push('…');
+    }
+    let mut in_mark = false;
+    for i in start..end {
+        if marked[i] != in_mark {
+            out.push_str(if marked[i] { "<mark>" } else { "</mark>" });
+            in_mark = marked[i];
+        }
+        // Keep the snippet on one line
+        let c = if chars[i].is_whitespace() { ' ' } else { chars[i] };
+        escape_html(c, &mut out);
+    }
+    if in_mark {
+        out.push_str("</mark>");
+    }
+    if end < chars.len() {
+        out.push('…');
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_terms() {
+        assert_eq!(terms("  Rust \"SQLite\" rust 日本 "), vec!["rust", "sqlite", "日本"]);
+        assert!(terms("   ").is_empty());
+    }
+
+    #[test]
+    fn test_match_expression() {
+        let t = terms("rust 日本 日本語 a\"b\"c");
+        assert_eq!(match_expression(&t), Some("\"rust\" AND \"日本語\" AND \"a\"\"b\"\"c\"".to_string()));
+        assert_eq!(match_expression(&terms("日本 go")), None);
+    }
+
+    #[test]
+    fn test_like_pattern() {
+        assert_eq!(like_pattern("100%_x"), "%100\\%\\_x%");
+    }
+
+    #[test]
+    fn test_snippet() {
+        let text = "Learning <Rust> with\nfriends. 日本語を勉強しましょう";
+        assert_eq!(snippet(text, &terms("rust 日本")),
+                   "Learning &lt;<mark>Rust</mark>&gt; with friends. <mark>日本</mark>語を勉強しましょう");
+        let long = format!("{}needle{}", "a".repeat(100), "b".repeat(300));
+        let s = snippet(&long, &terms("needle"));
+        assert!(s.starts_with('…'));
+        assert!(s.ends_with('…'));
+        assert!(s.contains("<mark>needle</mark>"));
+        assert_eq!(s.chars().count(), SNIPPET_LENGTH + 2 + "<mark></mark>".len());
+    }
+}
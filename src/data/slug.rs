@@ -0,0 +1,62 @@
+use super::super::constants;
+
+/**
+ * Turn a title into a URL slug: lowercase alphanumerics (any script, so CJK titles
+ * keep their characters) separated by single dashes.
+ * Returns "post" when nothing usable is left.
+ */
+pub fn slugify(title: &str) -> String {
+    let mut slug = String::with_capacity(title.len());
+    for c in title.chars().flat_map(char::to_lowercase) {
+        if c.is_alphanumeric() {
+            slug.push(c);
+        } else if !slug.is_empty() && !slug.ends_with('-') {
+            slug.push('-');
+        }
+    }
+    let slug = slug.trim_end_matches('-');
+    if slug.is_empty() {
+        "post".to_string()
+    } else {
+        slug.to_string()
+    }
+}
+
+// Slugs that would be shadowed by other routes under /notes/
+pub fn is_reserved(slug: &str) -> bool {
+    constants::RESERVED_SLUGS.contains(&slug)
+}
+
+/**
+ * Percent-encode a slug for use in a URL path or a Location header
+ */
+pub fn encode(slug: &str) -> String {
+    let mut encoded = String::with_capacity(slug.len());
+    for b in slug.bytes() {
+        if b.is_ascii_alphanumeric() || matches!(b, b'-' | b'_' | b'.' | b'~') {
+            encoded.push(b as char);
+        } else {
+            encoded.push_str(&format!("%{:02X}", b));
+        }
+    }
+    encoded
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_slugify() {
+        assert_eq!(slugify("Hello, World!"), "hello-world");
+        assert_eq!(slugify("  Rust & SQLite -- notes  "), "rust-sqlite-notes");
+        assert_eq!(slugify("日本語の勉強 Part 2"), "日本語の勉強-part-2");
+        assert_eq!(slugify("!!!"), "post");
+    }
+
+    #[test]
+    fn test_encode() {
+        assert_eq!(encode("hello-world"), "hello-world");
+        assert_eq!(encode("日本"), "%E6%97%A5%E6%9C%AC");
+    }
+}
@@ -15,10 +15,7 @@ struct Combinatorial {
 // inefficient algorithm complexity: total^size or O(n^m)
 impl Combinatorial {
     fn new(size: usize, total: u32) -> Combinatorial {
-        let mut current : Vec<u32> = Vec::new();
-        for _ in 0..size {
-            current.push(0);
-        }
+        let current : Vec<u32> = vec![0; size];
         Self {
             total,
             current,
@@ -48,7 +45,7 @@ impl Iterator for Combinatorial {
                 return Some(self.current.clone());
             }
         }
-        return None;
+        None
     }
 }
 
@@ -73,7 +70,7 @@ pub fn compute(input: Vec<CategoryResult>) -> Vec<Category> {
                     // sum of (item.price * item.items_sold) = i.summary.total_sale
                     let matches = (i.category.items.clone().into_iter()
                         .map( |item| item.price)
-                        .zip(guess.into_iter())
+                        .zip(guess)
                         .map(| (x, y) | x*(y as f64))
                         .sum::<f64>() - i.summary.total_sale).abs() <= TOLERANCE;
                     if matches {
@@ -85,7 +82,7 @@ pub fn compute(input: Vec<CategoryResult>) -> Vec<Category> {
         }
         solutions.into_iter().for_each( | soln: Vec<u32> | {
             trace!("Combo {:?} matched!", soln);
-            for x in 0..i.category.items.len() {
+            for (x, &sold_count) in soln.iter().enumerate() {
                 //let copy = soln.clone();
                 let curr_items = i.category.items[x].items_sold.clone();
                 let curr_totals = i.category.items[x].total_price.clone();
@@ -101,7 +98,7 @@ pub fn compute(input: Vec<CategoryResult>) -> Vec<Category> {
                         //sold.push(copy[x] as usize);
                     }
                 }
-                new_items.push(soln[x] as usize);
+                new_items.push(sold_count as usize);
                 i.category.items[x].items_sold = Some(new_items);
 
                 match curr_totals {
@@ -113,7 +110,7 @@ pub fn compute(input: Vec<CategoryResult>) -> Vec<Category> {
                     }
                 }
                 // round result to 2 decimal places:
-                new_totals.push((item_price * (soln[x] as f64) * 100.0).round() / 100.0);
+                new_totals.push((item_price * (sold_count as f64) * 100.0).round() / 100.0);
                 i.category.items[x].total_price = Some(new_totals);
             }
         });
@@ -0,0 +1,151 @@
+use pulldown_cmark::{Event, Options, Parser, Tag, TagEnd};
+
+use super::super::constants;
+use super::super::entity::PostSummary;
+
+// Marker ending the excerpt, when the automatic one is not good enough
+pub const MORE_MARKER: &str = "<!-- more -->";
+
+// Reading speeds, per minute
+const WORDS_PER_MINUTE: u32 = 200;
+const CJK_CHARS_PER_MINUTE: u32 = 500;
+
+// Chinese, Japanese and Korean characters are counted one by one, as they are not separated by spaces
+pub fn is_cjk(c: char) -> bool {
+    matches!(c,
+        '\u{3040}'..='\u{30ff}'     // Hiragana, Katakana
+        | '\u{3400}'..='\u{4dbf}'   // CJK Extension A
+        | '\u{4e00}'..='\u{9fff}'   // CJK Unified Ideographs
+        | '\u{f900}'..='\u{faff}'   // CJK Compatibility Ideographs
+        | '\u{ac00}'..='\u{d7af}'   // Hangul syllables
+        | '\u{20000}'..='\u{2fa1f}' // CJK Extensions B to F
+    )
+}
+
+/**
+ * Text of the markdown, without the markup. Paragraphs are separated by new lines.
+ * Code blocks are left out unless `with_code` is set.
+ */
+pub fn plain_text(content: &str, with_code: bool) -> String {
+    let mut options = Options::empty();
+    options.insert(Options::ENABLE_TABLES);
+    options.insert(Options::ENABLE_FOOTNOTES);
+    options.insert(Options::ENABLE_STRIKETHROUGH);
+    let mut text = String::new();
+    let mut in_code_block = false;
+    for event in Parser::new_ext(content, options) {
+        match event {
+            Event::Start(Tag::CodeBlock(_)) => in_code_block = true,
+            Event::End(TagEnd::CodeBlock) => {
+                in_code_block = false;
+                text.push('\n');
+            },
+            Event::Text(t) if in_code_block && with_code => text.push_str(&t),
+            Event::Text(_) if in_code_block => {},
+            // Lines of the admonition and figure blocks (:::note)
+            Event::Text(t) if t.trim_start().starts_with(":::") => {},
+            Event::Text(t) | Event::Code(t) => text.push_str(&t),
+            Event::SoftBreak | Event::HardBreak => text.push(' '),
+            Event::End(TagEnd::Paragraph | TagEnd::Heading(_) | TagEnd::Item | TagEnd::TableCell) => text.push('\n'),
+            _ => {},
+        }
+    }
+    text
+}
+
+// (words, CJK characters) in the text
+fn count_words(text: &str) -> (u32, u32) {
+    let mut words = 0;
+    let mut cjk = 0;
+    let mut in_word = false;
+    for c in text.chars() {
+        if is_cjk(c) {
+            cjk += 1;
+            in_word = false;
+        } else if c.is_alphanumeric() {
+            if !in_word {
+                words += 1;
+            }
+            in_word = true;
+        } else if c != '\'' && c != '-' && c != '_' {
+            in_word = false;
+        }
+    }
+    (words, cjk)
+}
+
+// Cuts the text after `max_chars` characters, at the end of a word when there is one nearby
+fn truncate(text: &str, max_chars: usize) -> String {
+    let Some((cut, _)) = text.char_indices().nth(max_chars) else {
+        return text.to_string();
+    };
+    let head = &text[..cut];
+    // Don't go back more than a few characters for text without spaces (CJK)
+    let head = match head.rfind(char::is_whitespace) {
+        Some(space) if head[space..].chars().count() < 20 => &head[..space],
+        _ => head,
+    };
+    format!("{}…", head.trim_end_matches(|c: char| c.is_whitespace() || c.is_ascii_punctuation()))
+}
+
+/**
+ * Word count, reading time and excerpt of the markdown content of a post. The excerpt is the
+ * text before `<!-- more -->`, or the beginning of the post.
+ */
+pub fn summarize(content: &str) -> PostSummary {
+    let (words, cjk) = count_words(&plain_text(content, true));
+    let reading_minutes = (words.div_ceil(WORDS_PER_MINUTE) + cjk.div_ceil(CJK_CHARS_PER_MINUTE)).max(1);
+    let excerpt = match content.split_once(MORE_MARKER) {
+        Some((head, _)) => plain_text(head, false).split_whitespace().collect::<Vec<&str>>().join(" "),
+        None => {
+            let text = plain_text(content, false).split_whitespace().collect::<Vec<&str>>().join(" ");
+            truncate(&text, constants::EXCERPT_LENGTH)
+        }
+    };
+    PostSummary {
+        word_count: words + cjk,
+        reading_minutes,
+        excerpt,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_word_count() {
+        let summary = summarize("# Title\n\nSome *emphasis*, it's `code` and a [link](https://example.com).\n\n```\nlet a = 1;\n```\n");
+        assert_eq!(summary.word_count, 11);
+        assert_eq!(summary.reading_minutes, 1);
+        assert_eq!(summarize("").reading_minutes, 1);
+        assert_eq!(summarize(&"word ".repeat(401)).reading_minutes, 3);
+    }
+
+    #[test]
+    fn test_cjk() {
+        let summary = summarize("日本語の文章です。Rust is fun");
+        assert_eq!(summary.word_count, 8 + 3);
+        assert_eq!(summarize(&"漢".repeat(1000)).reading_minutes, 2);
+        assert_eq!(summarize(&"漢".repeat(1001)).reading_minutes, 3);
+    }
+
+    #[test]
+    fn test_excerpt() {
+        let summary = summarize("# Title\n\nFirst **paragraph**.\n\n```\ncode\n```\n\n:::note\nA note\n:::\n");
+        assert_eq!(summary.excerpt, "Title First paragraph. A note");
+        let long = "word ".repeat(100);
+        let excerpt = summarize(&long).excerpt;
+        assert!(excerpt.ends_with("word…"));
+        assert!(excerpt.chars().count() <= constants::EXCERPT_LENGTH + 1);
+        let cjk = summarize(&"漢".repeat(300)).excerpt;
+        assert_eq!(cjk.chars().count(), constants::EXCERPT_LENGTH + 1);
+    }
+
+    #[test]
+    fn test_more_marker() {
+        let summary = summarize("Intro with a [link](/).\n\n<!-- more -->\n\nThe rest");
+        assert_eq!(summary.excerpt, "Intro with a link.");
+        assert_eq!(summary.word_count, 6);
+    }
+}
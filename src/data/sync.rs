@@ -0,0 +1,412 @@
+// Import of notes written as markdown files.
+//
+// Each `.md` file under the content directory may start with front matter, either YAML
+// between `---` lines or TOML between `+++` lines:
+//
+//   ---
+//   title: My note
+//   date: 2024-05-01
+//   tags: [rust, sqlite]
+//   slug: my-note
+//   draft: false
+//   ---
+//
+// Posts are matched by the path of their file, so running the sync again only touches the
+// posts whose file changed, and changing the slug in the front matter renames the post.
+// New files take over the post with their slug (taken from the file name when missing) only
+// when it was not imported from a file that still exists.
+
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use chrono::{DateTime, NaiveDate, NaiveDateTime};
+use log::{debug, error, info};
+use serde::Deserialize;
+
+use super::super::entity::{Permission, Post, PostInput, PostStatus, Role, User};
+use super::lite_db::{LiteDB, normalize_tag};
+use super::slug;
+
+#[derive(Deserialize, Default, Debug)]
+struct FrontMatter {
+    title: Option<String>,
+    date: Option<String>,
+    #[serde(default)]
+    tags: Vec<String>,
+    slug: Option<String>,
+    #[serde(default)]
+    draft: bool,
+}
+
+// A markdown file ready to be stored
+#[derive(Debug)]
+struct Note {
+    // Path relative to the content directory
+    source_path: String,
+    slug: String,
+    input: PostInput,
+}
+
+pub struct SyncOptions {
+    // Delete the posts imported from files that no longer exist
+    pub delete_missing: bool,
+    // Username of the author of new posts (the first admin when missing)
+    pub author: Option<String>,
+}
+
+#[derive(Default, Debug)]
+pub struct SyncReport {
+    pub created: u32,
+    pub updated: u32,
+    pub unchanged: u32,
+    pub deleted: u32,
+    pub failed: u32,
+}
+
+// Splits the front matter from the markdown body
+fn split_front_matter(text: &str) -> Result<(FrontMatter, &str), String> {
+    let text = text.strip_prefix('\u{feff}').unwrap_or(text);
+    for (fence, is_toml) in [("---", false), ("+++", true)] {
+        let Some(rest) = text.strip_prefix(fence).and_then(|r| r.strip_prefix('\n').or_else(|| r.strip_prefix("\r\n"))) else {
+            continue;
+        };
+        // The closing fence must be on its own line
+        let mut offset = 0;
+        for line in rest.split_inclusive('\n') {
+            if line.trim_end() == fence {
+                let header = &rest[..offset];
+                let body = &rest[offset + line.len()..];
+                let front_matter = if is_toml {
+                    parse_toml(header)?
+                } else if header.trim().is_empty() {
+                    FrontMatter::default()
+                } else {
+                    serde_yaml::from_str(header).map_err(|e| format!("Invalid YAML front matter: {}", e))?
+                };
+                return Ok((front_matter, body));
+            }
+            offset += line.len();
+        }
+        return Err(format!("Front matter is not closed by {}", fence));
+    }
+    Ok((FrontMatter::default(), text))
+}
+
+fn parse_toml(header: &str) -> Result<FrontMatter, String> {
+    let mut table: toml::Table = header.parse().map_err(|e| format!("Invalid TOML front matter: {}", e))?;
+    // TOML has its own date type, read it like the YAML strings
+    if let Some(toml::Value::Datetime(date)) = table.get("date") {
+        let date = date.to_string();
+        table.insert("date".to_string(), toml::Value::String(date));
+    }
+    table.try_into().map_err(|e| format!("Invalid TOML front matter: {}", e))
+}
+
+/**
+ * Parse a front matter date (RFC 3339, `YYYY-MM-DD HH:MM:SS` or `YYYY-MM-DD`, UTC unless
+ * an offset is given) to ms since the Unix epoch
+ */
+fn parse_date(date: &str) -> Result<i64, String> {
+    let date = date.trim();
+    if let Ok(d) = DateTime::parse_from_rfc3339(date) {
+        return Ok(d.timestamp_millis());
+    }
+    for format in ["%Y-%m-%d %H:%M:%S", "%Y-%m-%dT%H:%M:%S", "%Y-%m-%d %H:%M"] {
+        if let Ok(d) = NaiveDateTime::parse_from_str(date, format) {
+            return Ok(d.and_utc().timestamp_millis());
+        }
+    }
+    NaiveDate::parse_from_str(date, "%Y-%m-%d")
+        .map(|d| d.and_hms_opt(0, 0, 0).unwrap_or_default().and_utc().timestamp_millis())
+        .map_err(|_| format!("Invalid date {}", date))
+}
+
+fn parse_note(source_path: String, text: &str) -> Result<Note, String> {
+    let (front_matter, body) = split_front_matter(text)?;
+    let stem = Path::new(&source_path).file_stem()
+        .map(|s| s.to_string_lossy().to_string())
+        .unwrap_or_default();
+    let slug = slug::slugify(front_matter.slug.as_deref().unwrap_or(&stem));
+    if slug::is_reserved(&slug) {
+        return Err(format!("Slug {} is reserved", slug));
+    }
+    let title = front_matter.title.filter(|t| !t.trim().is_empty()).unwrap_or(stem);
+    let published_at = front_matter.date.as_deref().map(parse_date).transpose()?;
+    Ok(Note {
+        source_path,
+        slug: slug.clone(),
+        input: PostInput {
+            title,
+            content: body.trim_start_matches(['\r', '\n']).to_string(),
+            tags: Some(front_matter.tags),
+            slug: Some(slug),
+            status: Some(if front_matter.draft { PostStatus::Draft } else { PostStatus::Published }),
+            published_at,
+        },
+    })
+}
+
+// Every markdown file under `dir`, sorted.
+// Links to directories are not followed, they could loop back to a parent.
+fn find_markdown_files(dir: &Path) -> Result<Vec<PathBuf>, String> {
+    let mut files = Vec::new();
+    let entries = fs::read_dir(dir).map_err(|e| format!("Unable to read {}: {}", dir.display(), e))?;
+    for entry in entries {
+        let entry = entry.map_err(|e| format!("Unable to read {}: {}", dir.display(), e))?;
+        let file_type = entry.file_type().map_err(|e| format!("Unable to read {}: {}", entry.path().display(), e))?;
+        let path = entry.path();
+        if file_type.is_dir() {
+            files.append(&mut find_markdown_files(&path)?);
+        } else if file_type.is_symlink() && path.is_dir() {
+            debug!("Not following the link to a directory {}", path.display());
+        } else if path.extension().is_some_and(|ext| ext == "md") {
+            files.push(path);
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+// Whether storing the note would change the post
+fn differs(post: &Post, note: &Note) -> bool {
+    let input = &note.input;
+    let mut tags: Vec<String> = input.tags.iter().flatten().filter_map(|t| normalize_tag(t)).collect();
+    tags.sort();
+    tags.dedup();
+    let status_differs = match input.status {
+        Some(PostStatus::Draft) => post.status != PostStatus::Draft,
+        _ => post.status == PostStatus::Draft,
+    };
+    let date_differs = input.published_at.is_some() && input.published_at != post.ident.published;
+    post.ident.title != input.title
+        || post.ident.slug != note.slug
+        || post.content != input.content
+        || post.ident.tags != tags
+        || status_differs
+        || date_differs
+}
+
+fn find_author(db: &LiteDB, author: Option<&str>) -> Result<User, String> {
+    let users = db.get_users().map_err(|e| format!("Unable to read users: {}", e))?;
+    let user = match author {
+        Some(name) => users.into_iter().find(|u| u.name == name),
+        None => users.into_iter().find(|u| u.role == Role::Admin),
+    };
+    user.ok_or_else(|| match author {
+        Some(name) => format!("No user named {}", name),
+        None => "No admin user to own the new posts, use --author".to_string(),
+    })
+}
+
+/**
+ * Create or update a post for every markdown file under `dir`
+ */
+pub fn sync_directory(db: &LiteDB, dir: &Path, options: &SyncOptions) -> Result<SyncReport, String> {
+    let author = find_author(db, options.author.as_deref())?;
+    let mut report = SyncReport::default();
+    // Files seen in this run, even if they could not be imported, so their posts are kept
+    let mut seen_paths: HashSet<String> = HashSet::new();
+    let mut seen_slugs: HashMap<String, String> = HashMap::new();
+
+    for path in find_markdown_files(dir)? {
+        let source_path = path.strip_prefix(dir).unwrap_or(&path).to_string_lossy().replace('\\', "/");
+        seen_paths.insert(source_path.clone());
+        let note = fs::read_to_string(&path)
+            .map_err(|e| format!("Unable to read file: {}", e))
+            .and_then(|text| parse_note(source_path.clone(), &text));
+        let note = match note {
+            Ok(note) => note,
+            Err(e) => {
+                error!("Skipping {}: {}", source_path, e);
+                report.failed += 1;
+                continue;
+            }
+        };
+        if let Some(other) = seen_slugs.insert(note.slug.clone(), source_path.clone()) {
+            error!("Skipping {}: slug {} is already used by {}", source_path, note.slug, other);
+            report.failed += 1;
+            continue;
+        }
+        match store_note(db, dir, &author, &note) {
+            Ok(Stored::Created) => report.created += 1,
+            Ok(Stored::Updated) => report.updated += 1,
+            Ok(Stored::Unchanged) => report.unchanged += 1,
+            Err(e) => {
+                error!("Failed to store {}: {}", source_path, e);
+                report.failed += 1;
+            }
+        }
+    }
+
+    if options.delete_missing {
+        let sources = db.get_post_sources().map_err(|e| format!("Unable to read posts: {}", e))?;
+        for (id, source_path) in sources.into_iter().filter(|(_, p)| !seen_paths.contains(p)) {
+            info!("Deleting post {} ({} no longer exists)", id, source_path);
+            db.delete_post(id).map_err(|e| format!("Unable to delete post {}: {}", id, e))?;
+            report.deleted += 1;
+        }
+    }
+    // The server only notices its own writes
+    if report.created + report.updated + report.deleted > 0 {
+        db.refresh_related().map_err(|e| format!("Unable to compute the related posts: {}", e))?;
+    }
+    Ok(report)
+}
+
+enum Stored {
+    Created,
+    Updated,
+    Unchanged,
+}
+
+// The post imported from the file before, or the one with the same slug when it does not come
+// from another file that still exists (a post written through the API, or a renamed file)
+fn find_post(db: &LiteDB, dir: &Path, note: &Note) -> Result<Option<Post>, String> {
+    if let Some(post) = db.get_post_by_source(&note.source_path) {
+        return Ok(Some(post));
+    }
+    match db.get_any_post_by_slug(&note.slug) {
+        Some(post) => match post.source_path.as_deref() {
+            Some(other) if dir.join(other).exists() =>
+                Err(format!("slug {} is already used by the post from {}", note.slug, other)),
+            _ => Ok(Some(post)),
+        },
+        None => Ok(None),
+    }
+}
+
+fn store_note(db: &LiteDB, dir: &Path, author: &User, note: &Note) -> Result<Stored, String> {
+    let post = find_post(db, dir, note)?;
+    store_post(db, author, note, post).map_err(|e| e.to_string())
+}
+
+// Imported contents count as written by the sync author, also when updating the posts of others
+fn store_post(db: &LiteDB, author: &User, note: &Note, post: Option<Post>) -> rusqlite::Result<Stored> {
+    let trusted_html = author.role.can(Permission::EmbedHtml);
+    let stored = match post {
+        Some(post) if !differs(&post, note) => {
+            debug!("{} is up to date", note.source_path);
+            if post.source_path.as_deref() != Some(note.source_path.as_str()) {
+                db.set_post_source(post.ident.id, Some(&note.source_path))?;
+            }
+            return Ok(Stored::Unchanged);
+        },
+        Some(post) => {
+            info!("Updating post {} from {}", post.ident.id, note.source_path);
+            db.update_post(post.ident.id, &note.input, trusted_html)?;
+            (post.ident.id, Stored::Updated)
+        },
+        None => {
+            let post = db.create_post(author.id, &note.input, trusted_html)?;
+            info!("Created post {} from {}", post.ident.id, note.source_path);
+            (post.ident.id, Stored::Created)
+        },
+    };
+    db.set_post_source(stored.0, Some(&note.source_path))?;
+    Ok(stored.1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_yaml_front_matter() {
+        let text = "---\ntitle: \"Hello: world\"\ndate: 2023-11-14\ntags: [Rust, web dev]\ndraft: true\n---\n\n# Body\n";
+        let note = parse_note("2023/hello.md".to_string(), text).unwrap();
+        assert_eq!(note.slug, "hello");
+        assert_eq!(note.input.title, "Hello: world");
+        assert_eq!(note.input.content, "# Body\n");
+        assert_eq!(note.input.tags, Some(vec!["Rust".to_string(), "web dev".to_string()]));
+        assert_eq!(note.input.status, Some(PostStatus::Draft));
+        assert_eq!(note.input.published_at, Some(1_699_920_000_000));
+    }
+
+    #[test]
+    fn test_toml_front_matter() {
+        let text = "+++\ntitle = \"Hello\"\ndate = 2023-11-14T22:13:20Z\nslug = \"Custom Slug\"\n+++\nBody";
+        let note = parse_note("hello.md".to_string(), text).unwrap();
+        assert_eq!(note.slug, "custom-slug");
+        assert_eq!(note.input.published_at, Some(1_700_000_000_000));
+        assert_eq!(note.input.status, Some(PostStatus::Published));
+        assert_eq!(note.input.content, "Body");
+    }
+
+    #[test]
+    fn test_no_front_matter() {
+        let note = parse_note("My Note.md".to_string(), "--- not a fence\ntext").unwrap();
+        assert_eq!(note.slug, "my-note");
+        assert_eq!(note.input.title, "My Note");
+        assert_eq!(note.input.content, "--- not a fence\ntext");
+        assert!(parse_note("x.md".to_string(), "---\ntitle: x\n").is_err());
+    }
+
+    #[test]
+    fn test_parse_date() {
+        assert_eq!(parse_date("2023-11-14T22:13:20+09:00"), Ok(1_699_967_600_000));
+        assert_eq!(parse_date("2023-11-14 22:13:20"), Ok(1_700_000_000_000));
+        assert!(parse_date("14/11/2023").is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_symlink_loop() {
+        let dir = std::env::temp_dir().join(format!("xmithd_sync_loop_test_{}", std::process::id()));
+        fs::create_dir_all(dir.join("sub")).unwrap();
+        fs::write(dir.join("sub/note.md"), "Note").unwrap();
+        std::os::unix::fs::symlink(&dir, dir.join("sub/loop")).unwrap();
+        assert_eq!(find_markdown_files(&dir).unwrap(), vec![dir.join("sub/note.md")]);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_sync_directory() {
+        let dir = std::env::temp_dir().join(format!("xmithd_sync_test_{}", std::process::id()));
+        fs::create_dir_all(dir.join("sub")).unwrap();
+        fs::write(dir.join("first.md"), "---\ntitle: First\ndate: 2023-01-01\ntags: [a]\n---\nOne").unwrap();
+        fs::write(dir.join("sub/second.md"), "---\ntitle: Second\ndate: 2023-01-02\n---\nTwo").unwrap();
+        fs::write(dir.join("ignored.txt"), "not markdown").unwrap();
+
+        let db = LiteDB::for_tests();
+        let keep = SyncOptions { delete_missing: false, author: None };
+        let delete = SyncOptions { delete_missing: true, author: None };
+
+        let report = sync_directory(&db, &dir, &keep).unwrap();
+        assert_eq!((report.created, report.updated, report.unchanged), (2, 0, 0));
+        let first = db.get_post_by_slug("first").unwrap();
+        assert_eq!(first.ident.tags, vec!["a"]);
+        assert_eq!(first.source_path.as_deref(), Some("first.md"));
+
+        // running again changes nothing
+        let report = sync_directory(&db, &dir, &keep).unwrap();
+        assert_eq!((report.created, report.updated, report.unchanged), (0, 0, 2));
+        assert!(db.get_revisions(first.ident.id).unwrap().is_empty());
+
+        fs::write(dir.join("first.md"), "---\ntitle: First\ndate: 2023-01-01\ntags: [a]\n---\nOne, edited").unwrap();
+        fs::remove_file(dir.join("sub/second.md")).unwrap();
+        let report = sync_directory(&db, &dir, &keep).unwrap();
+        assert_eq!((report.updated, report.unchanged, report.deleted), (1, 0, 0));
+        assert!(db.get_post_by_slug("second").is_some());
+
+        let report = sync_directory(&db, &dir, &delete).unwrap();
+        assert_eq!((report.unchanged, report.deleted), (1, 1));
+        assert!(db.get_post_by_slug("second").is_none());
+        assert_eq!(db.get_post_by_slug("first").unwrap().content, "One, edited");
+
+        // a new slug renames the post instead of adding one
+        fs::write(dir.join("first.md"), "---\ntitle: First\nslug: renamed\ndate: 2023-01-01\ntags: [a]\n---\nOne, edited").unwrap();
+        let report = sync_directory(&db, &dir, &delete).unwrap();
+        assert_eq!((report.created, report.updated, report.deleted), (0, 1, 0));
+        assert_eq!(db.get_post_by_slug("renamed").unwrap().ident.id, first.ident.id);
+        assert!(db.get_post_by_slug("first").is_none());
+
+        // a file cannot take over the post of another file (and first.md then has a duplicate slug)
+        fs::write(dir.join("copy.md"), "---\ntitle: Copy\nslug: renamed\n---\nCopied").unwrap();
+        let report = sync_directory(&db, &dir, &keep).unwrap();
+        assert_eq!((report.created, report.updated, report.failed), (0, 0, 2));
+        assert_eq!(db.get_post_by_slug("renamed").unwrap().content, "One, edited");
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
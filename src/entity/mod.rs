@@ -3,8 +3,79 @@ use serde::{Serialize, Deserialize};
 
 #[derive(Serialize, Deserialize, Debug)]
 pub struct User {
+    pub id: u32,
     pub name: String,
-    pub is_admin: u32,
+    pub role: Role,
+}
+
+// What a user is on the site. Each role comes with a set of permissions.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub enum Role {
+    // Everything, including managing the users
+    Admin,
+    // Writes notes, and edits everyone's notes, series and uploads
+    Editor,
+    // Writes and edits their own notes
+    Author,
+    Commenter,
+    // Commenter who also moderates the chat rooms
+    ChatModerator,
+}
+
+// Something a user may be allowed to do
+#[derive(Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub enum Permission {
+    // Create notes and edit (or delete) the ones you wrote
+    EditOwnPosts,
+    EditAnyPost,
+    ManageSeries,
+    UploadMedia,
+    // Delete any upload, or make its resized copies again
+    ManageMedia,
+    ManageUsers,
+    // Write notes with the HTML only admins are trusted with (like iframes)
+    EmbedHtml,
+    // Site internals, like the render cache
+    ManageSite,
+    Comment,
+    ModerateChat,
+}
+
+impl Role {
+    pub const ALL: &'static [Role] = &[Role::Admin, Role::Editor, Role::Author, Role::Commenter, Role::ChatModerator];
+
+    // Value of the user.role column
+    pub fn as_stored(&self) -> &'static str {
+        match self {
+            Role::Admin => "admin",
+            Role::Editor => "editor",
+            Role::Author => "author",
+            Role::Commenter => "commenter",
+            Role::ChatModerator => "chat-moderator",
+        }
+    }
+
+    pub fn from_stored(value: &str) -> Option<Role> {
+        Role::ALL.iter().copied().find(|role| role.as_stored() == value)
+    }
+
+    pub fn permissions(&self) -> &'static [Permission] {
+        use Permission::*;
+        match self {
+            Role::Admin => &[EditOwnPosts, EditAnyPost, ManageSeries, UploadMedia, ManageMedia, ManageUsers, EmbedHtml,
+                             ManageSite, Comment, ModerateChat],
+            Role::Editor => &[EditOwnPosts, EditAnyPost, ManageSeries, UploadMedia, ManageMedia, Comment],
+            Role::Author => &[EditOwnPosts, UploadMedia, Comment],
+            Role::Commenter => &[Comment],
+            Role::ChatModerator => &[Comment, ModerateChat],
+        }
+    }
+
+    pub fn can(&self, permission: Permission) -> bool {
+        self.permissions().contains(&permission)
+    }
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -19,10 +90,36 @@ pub struct PostIdent {
     // Timestamp when it was created
     // (ms since Unix epoch - but only accurate to the second)
     pub created: i64,
+
+    // Unique URL name: /notes/{slug}
+    pub slug: String,
+
+    // Timestamp when it was (or will be) published, None for drafts never published
+    // (ms since Unix epoch - but only accurate to the second)
+    pub published: Option<i64>,
+
+    // Tag names, sorted
+    pub tags: Vec<String>,
+
+    pub summary: PostSummary,
 }
 
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+pub struct PostSummary {
+    // Words, plus CJK characters which are counted one by one
+    pub word_count: u32,
+
+    // Estimated reading time (at least 1 minute)
+    pub reading_minutes: u32,
+
+    // Plain text teaser: the text before <!-- more -->, or the beginning of the post
+    pub excerpt: String,
+}
+
+#[derive(Serialize, Debug)]
 pub struct Post {
     // post ident info
+    #[serde(flatten)]
     pub ident: PostIdent,
 
     // markdown content
@@ -31,6 +128,315 @@ pub struct Post {
     // Timestamp when it was last updated
     // (ms since Unix epoch - but only accurate to the second)
     pub updated: i64,
+
+    pub status: PostStatus,
+
+    // Secret part of the preview URL (/notes/preview/{token}).
+    // Only send to admins.
+    pub preview_token: String,
+
+    // Markdown file the post was imported from, if any
+    pub source_path: Option<String>,
+
+    // Whether the content was last written by someone with the EmbedHtml permission, which
+    // allows more HTML in it
+    #[serde(skip)]
+    pub trusted_html: bool,
+
+    // None when the account of the author is gone
+    pub author: Option<PostAuthor>,
+}
+
+// Who wrote a post
+#[derive(Serialize, Debug)]
+pub struct PostAuthor {
+    pub id: u32,
+    pub username: String,
+    // Display name, the username when there is none
+    pub name: String,
+}
+
+// A previous version of a post
+#[derive(Serialize, Debug)]
+pub struct PostRevision {
+    pub id: u32,
+    pub post_id: u32,
+    pub title: String,
+
+    // markdown content
+    pub content: String,
+
+    // Timestamp when this version was written
+    // (ms since Unix epoch - but only accurate to the second)
+    pub created: i64,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum PostStatus {
+    // Only visible through the preview URL
+    Draft,
+    // Visible once the publication date has passed
+    Published,
+    // Published with a date in the future (stored as published)
+    Scheduled,
+}
+
+impl PostStatus {
+    // Value of the post.status column
+    pub fn as_stored(&self) -> &'static str {
+        match self {
+            PostStatus::Draft => "draft",
+            PostStatus::Published | PostStatus::Scheduled => "published",
+        }
+    }
+}
+
+// Body of the post create/update API calls
+#[derive(Deserialize, Debug)]
+pub struct PostInput {
+    pub title: String,
+
+    // markdown content
+    pub content: String,
+
+    // Replaces the current tags of the post, left unchanged on edits when missing
+    #[serde(default)]
+    pub tags: Option<Vec<String>>,
+
+    // Generated from the title on creation when missing, left unchanged on edits when missing
+    #[serde(default)]
+    pub slug: Option<String>,
+
+    // Published when missing on creation, left unchanged on edits when missing
+    #[serde(default)]
+    pub status: Option<PostStatus>,
+
+    // Publication date (ms since Unix epoch). A date in the future schedules the post.
+    // Defaults to the moment the post is first published.
+    #[serde(default)]
+    pub published_at: Option<i64>,
+}
+
+// Posts meant to be read in order, like a tutorial in several parts
+#[derive(Serialize, Debug)]
+pub struct Series {
+    pub id: u32,
+
+    // Unique URL name: /notes/series/{slug}
+    pub slug: String,
+
+    pub title: String,
+
+    pub description: String,
+
+    // Ids of the parts in reading order, whatever their status
+    pub posts: Vec<u32>,
+}
+
+// Body of the series create/update API calls
+#[derive(Deserialize, Debug)]
+pub struct SeriesInput {
+    pub title: String,
+
+    // Generated from the title when missing
+    #[serde(default)]
+    pub slug: Option<String>,
+
+    #[serde(default)]
+    pub description: String,
+
+    // Ids of the parts in reading order. A post is in one series at most:
+    // listing it here takes it out of its previous series.
+    pub posts: Vec<u32>,
+}
+
+// Where a post is in its series, counting only the parts visitors can see
+#[derive(Serialize, Debug)]
+pub struct SeriesNav {
+    pub slug: String,
+    pub title: String,
+
+    // 1 for the first part
+    pub part: u32,
+    pub parts: u32,
+
+    pub previous: Option<PostIdent>,
+    pub next: Option<PostIdent>,
+}
+
+// A file uploaded for the notes
+#[derive(Serialize, Debug)]
+pub struct Media {
+    pub id: u32,
+
+    // Name of the stored file: digest of the content and extension
+    pub file_name: String,
+
+    // Where the file is served: /media/{file_name}
+    pub url: String,
+
+    // Name of the file on the uploader's machine
+    pub original_name: Option<String>,
+
+    // Detected from the content
+    pub mime_type: String,
+
+    // In bytes
+    pub size: u64,
+
+    // Posts the file was uploaded for
+    pub post_ids: Vec<u32>,
+
+    // Timestamp of the upload
+    // (ms since Unix epoch - but only accurate to the second)
+    pub created: i64,
+
+    // Size of images, once rotated as their EXIF orientation says
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+
+    // Resized copies of images, narrowest first
+    pub variants: Vec<ImageVariant>,
+}
+
+// A resized copy of an uploaded image
+#[derive(Serialize, Debug)]
+pub struct ImageVariant {
+    pub width: u32,
+    pub height: u32,
+    pub file_name: String,
+    pub url: String,
+
+    // In bytes
+    pub size: u64,
+}
+
+// What is recorded of an upload
+#[derive(Debug)]
+pub struct MediaUpload {
+    // Hex encoded SHA-256 digest of the content
+    pub hash: String,
+    pub file_name: String,
+    pub original_name: Option<String>,
+    pub mime_type: String,
+    pub size: u64,
+    pub post_id: Option<u32>,
+}
+
+// A personal API token, without its secret
+#[derive(Serialize, Debug)]
+pub struct ApiToken {
+    pub id: u32,
+    pub name: String,
+
+    // Parts of the API it can be used for
+    pub scopes: Vec<String>,
+
+    // Timestamps (ms since Unix epoch - but only accurate to the second)
+    pub created: i64,
+    // None when it does not expire
+    pub expires: Option<i64>,
+    // None until it is first used
+    pub last_used: Option<i64>,
+}
+
+// A token just created: the only time its secret is given
+#[derive(Serialize, Debug)]
+pub struct NewApiToken {
+    pub token: String,
+    #[serde(flatten)]
+    pub info: ApiToken,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct ApiTokenInput {
+    pub name: String,
+    pub scopes: Vec<String>,
+    // ms since Unix epoch, no expiry when missing
+    #[serde(default)]
+    pub expires_at: Option<i64>,
+}
+
+// What a user shows about themselves on their author page
+#[derive(Serialize, Debug)]
+pub struct Profile {
+    pub id: u32,
+    pub username: String,
+    pub display_name: Option<String>,
+    // Display name, the username when there is none
+    pub name: String,
+    // markdown
+    pub bio: String,
+    pub links: Vec<ProfileLink>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ProfileLink {
+    pub title: String,
+    pub url: String,
+}
+
+// Body of the profile API call, replaces the profile
+#[derive(Deserialize, Debug)]
+pub struct ProfileInput {
+    #[serde(default)]
+    pub display_name: Option<String>,
+    #[serde(default)]
+    pub bio: String,
+    #[serde(default)]
+    pub links: Vec<ProfileLink>,
+}
+
+// Body of the user role API call
+#[derive(Deserialize, Debug)]
+pub struct RoleInput {
+    pub role: Role,
+}
+
+#[derive(Serialize, Debug)]
+pub struct SearchResult {
+    #[serde(flatten)]
+    pub ident: PostIdent,
+
+    // HTML excerpt of the content with the matches in <mark> tags
+    pub snippet: String,
+
+    // Relevance, higher is better (0 when the full-text index was not used)
+    pub score: f64,
+}
+
+// Number of posts published in a month
+#[derive(Serialize, Deserialize, Debug)]
+pub struct ArchivePeriod {
+    pub year: i32,
+
+    // 1 to 12
+    pub month: u32,
+
+    pub count: u32,
+}
+
+// See LiteDB::content_version
+#[derive(Clone, Copy, PartialEq, Debug, Default)]
+pub struct ContentVersion {
+    pub writes: u64,
+}
+
+#[derive(Serialize, Debug)]
+pub struct CacheStats {
+    pub hits: u64,
+    pub misses: u64,
+    pub entries: usize,
+    pub capacity: usize,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct TagCount {
+    pub name: String,
+
+    // Number of posts with this tag
+    pub count: u32,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -0,0 +1,83 @@
+use axum::{
+    extract::multipart::MultipartError,
+    http::StatusCode,
+    response::{IntoResponse, Json, Response},
+};
+use serde_json::json;
+
+// Errors returned by the JSON API.
+// Each one is sent as `{"error": "<message>"}` with the matching status code.
+#[derive(Debug)]
+pub enum ApiError {
+    // No (or an unknown) API token or session was sent
+    Unauthorized,
+    // The caller is known but not allowed to do this
+    Forbidden,
+    NotFound(String),
+    BadRequest(String),
+    // e.g. a unique column already has that value
+    Conflict(String),
+    // Uploaded file over the size limit
+    PayloadTooLarge(String),
+    // Uploaded file of a type that is not allowed
+    UnsupportedMediaType(String),
+    // Details are logged, not sent to the client
+    Internal(String),
+}
+
+impl ApiError {
+    fn status(&self) -> StatusCode {
+        match self {
+            ApiError::Unauthorized => StatusCode::UNAUTHORIZED,
+            ApiError::Forbidden => StatusCode::FORBIDDEN,
+            ApiError::NotFound(_) => StatusCode::NOT_FOUND,
+            ApiError::BadRequest(_) => StatusCode::BAD_REQUEST,
+            ApiError::Conflict(_) => StatusCode::CONFLICT,
+            ApiError::PayloadTooLarge(_) => StatusCode::PAYLOAD_TOO_LARGE,
+            ApiError::UnsupportedMediaType(_) => StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            ApiError::Internal(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    fn message(&self) -> &str {
+        match self {
+            ApiError::Unauthorized => "Authentication required",
+            ApiError::Forbidden => "Forbidden",
+            ApiError::NotFound(msg) => msg,
+            ApiError::BadRequest(msg) => msg,
+            ApiError::Conflict(msg) => msg,
+            ApiError::PayloadTooLarge(msg) => msg,
+            ApiError::UnsupportedMediaType(msg) => msg,
+            ApiError::Internal(_) => "Internal server error",
+        }
+    }
+}
+
+impl From<rusqlite::Error> for ApiError {
+    fn from(e: rusqlite::Error) -> Self {
+        match e.sqlite_error_code() {
+            Some(rusqlite::ErrorCode::ConstraintViolation) => {
+                ApiError::Conflict("Conflicts with existing data".to_string())
+            },
+            _ => ApiError::Internal(format!("Database error: {}", e)),
+        }
+    }
+}
+
+impl From<MultipartError> for ApiError {
+    fn from(e: MultipartError) -> Self {
+        match e.status() {
+            StatusCode::PAYLOAD_TOO_LARGE => ApiError::PayloadTooLarge("Upload too large".to_string()),
+            _ => ApiError::BadRequest(e.body_text()),
+        }
+    }
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        if let ApiError::Internal(details) = &self {
+            log::error!("{}", details);
+        }
+        (self.status(), Json(json!({ "error": self.message() }))).into_response()
+    }
+}
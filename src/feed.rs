@@ -0,0 +1,256 @@
+use chrono::{DateTime, SecondsFormat, Utc};
+use serde_json::json;
+
+use super::data::Config;
+use super::data::slug;
+use super::entity::Post;
+use super::markdown::Renderer;
+use super::sanitize::Trust;
+
+// Number of posts included in the feeds
+pub const FEED_SIZE: i32 = 20;
+
+pub fn site_url(conf: &Config) -> String {
+    format!("https://{}", conf.site_domain)
+}
+
+fn post_url(conf: &Config, post: &Post) -> String {
+    format!("{}/notes/{}", site_url(conf), slug::encode(&post.ident.slug))
+}
+
+/**
+ * Permanent id of a post in the feeds (a tag URI, https://www.rfc-editor.org/rfc/rfc4151).
+ * Unlike the URL it does not change when the slug is edited, so readers don't show the post again.
+ */
+fn post_id(conf: &Config, post: &Post) -> String {
+    format!("tag:{},{}:post-{}", conf.site_domain, to_datetime(post.ident.created).format("%Y"), post.ident.id)
+}
+
+// Feeds only contain published posts, which always have a publication date
+fn published(post: &Post) -> i64 {
+    post.ident.published.unwrap_or(post.ident.created)
+}
+
+fn to_datetime(ms: i64) -> DateTime<Utc> {
+    DateTime::from_timestamp_millis(ms).unwrap_or_default()
+}
+
+pub fn rfc3339(ms: i64) -> String {
+    to_datetime(ms).to_rfc3339_opts(SecondsFormat::Secs, true)
+}
+
+fn rfc2822(ms: i64) -> String {
+    to_datetime(ms).to_rfc2822()
+}
+
+// Latest modification of any post, used as the feed update time
+fn last_updated(posts: &[Post]) -> i64 {
+    posts.iter().map(|p| p.updated).max().unwrap_or(0)
+}
+
+pub fn escape_xml(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+fn feed_title(conf: &Config) -> String {
+    format!("Notes - {}", conf.site_domain)
+}
+
+/**
+ * Atom 1.0 document for the given posts (https://www.rfc-editor.org/rfc/rfc4287)
+ */
+pub fn atom(conf: &Config, markdown: &Renderer, posts: &[Post]) -> String {
+    let site = site_url(conf);
+    let mut out = String::new();
+    out.push_str("<?xml version=\"1.0\" encoding=\"utf-8\"?>\n");
+    out.push_str(&format!("<feed xmlns=\"http://www.w3.org/2005/Atom\" xml:base=\"{}/\">\n", escape_xml(&site)));
+    out.push_str(&format!("  <title>{}</title>\n", escape_xml(&feed_title(conf))));
+    out.push_str(&format!("  <id>{}/notes</id>\n", escape_xml(&site)));
+    out.push_str(&format!("  <link rel=\"alternate\" type=\"text/html\" href=\"{}/notes\"/>\n", escape_xml(&site)));
+    out.push_str(&format!("  <link rel=\"self\" type=\"application/atom+xml\" href=\"{}/notes/feed.atom\"/>\n", escape_xml(&site)));
+    out.push_str(&format!("  <updated>{}</updated>\n", rfc3339(last_updated(posts))));
+    out.push_str(&format!("  <author>\n    <name>{}</name>\n    <email>{}</email>\n    <uri>{}/about</uri>\n  </author>\n",
+                          escape_xml(&conf.site_author), escape_xml(&conf.author_email), escape_xml(&site)));
+    for post in posts {
+        let url = escape_xml(&post_url(conf, post));
+        out.push_str("  <entry>\n");
+        out.push_str(&format!("    <title>{}</title>\n", escape_xml(&post.ident.title)));
+        out.push_str(&format!("    <id>{}</id>\n", escape_xml(&post_id(conf, post))));
+        out.push_str(&format!("    <link rel=\"alternate\" type=\"text/html\" href=\"{}\"/>\n", url));
+        out.push_str(&format!("    <published>{}</published>\n", rfc3339(published(post))));
+        out.push_str(&format!("    <updated>{}</updated>\n", rfc3339(post.updated)));
+        for tag in &post.ident.tags {
+            out.push_str(&format!("    <category term=\"{}\"/>\n", escape_xml(tag)));
+        }
+        out.push_str(&format!("    <summary>{}</summary>\n", escape_xml(&post.ident.summary.excerpt)));
+        out.push_str(&format!("    <content type=\"html\">{}</content>\n", escape_xml(&markdown.to_html(&post.content, Trust::of_post(post.trusted_html)))));
+        out.push_str("  </entry>\n");
+    }
+    out.push_str("</feed>\n");
+    out
+}
+
+/**
+ * RSS 2.0 document for the given posts (https://www.rssboard.org/rss-specification).
+ * RSS has no modification date for items, so atom:updated is added to each one.
+ */
+pub fn rss(conf: &Config, markdown: &Renderer, posts: &[Post]) -> String {
+    let site = site_url(conf);
+    let author = escape_xml(&format!("{} ({})", conf.author_email, conf.site_author));
+    let mut out = String::new();
+    out.push_str("<?xml version=\"1.0\" encoding=\"utf-8\"?>\n");
+    out.push_str("<rss version=\"2.0\" xmlns:atom=\"http://www.w3.org/2005/Atom\">\n<channel>\n");
+    out.push_str(&format!("  <title>{}</title>\n", escape_xml(&feed_title(conf))));
+    out.push_str(&format!("  <link>{}/notes</link>\n", escape_xml(&site)));
+    out.push_str(&format!("  <description>Notes by {}</description>\n", escape_xml(&conf.site_author)));
+    out.push_str(&format!("  <atom:link href=\"{}/notes/feed.rss\" rel=\"self\" type=\"application/rss+xml\"/>\n", escape_xml(&site)));
+    out.push_str(&format!("  <managingEditor>{}</managingEditor>\n", author));
+    out.push_str(&format!("  <lastBuildDate>{}</lastBuildDate>\n", rfc2822(last_updated(posts))));
+    for post in posts {
+        let url = escape_xml(&post_url(conf, post));
+        out.push_str("  <item>\n");
+        out.push_str(&format!("    <title>{}</title>\n", escape_xml(&post.ident.title)));
+        out.push_str(&format!("    <link>{}</link>\n", url));
+        out.push_str(&format!("    <guid isPermaLink=\"false\">{}</guid>\n", escape_xml(&post_id(conf, post))));
+        out.push_str(&format!("    <author>{}</author>\n", author));
+        out.push_str(&format!("    <pubDate>{}</pubDate>\n", rfc2822(published(post))));
+        out.push_str(&format!("    <atom:updated>{}</atom:updated>\n", rfc3339(post.updated)));
+        for tag in &post.ident.tags {
+            out.push_str(&format!("    <category>{}</category>\n", escape_xml(tag)));
+        }
+        out.push_str(&format!("    <description>{}</description>\n", escape_xml(&markdown.to_html(&post.content, Trust::of_post(post.trusted_html)))));
+        out.push_str("  </item>\n");
+    }
+    out.push_str("</channel>\n</rss>\n");
+    out
+}
+
+/**
+ * JSON Feed 1.1 document for the given posts (https://www.jsonfeed.org/version/1.1/)
+ */
+pub fn json_feed(conf: &Config, markdown: &Renderer, posts: &[Post]) -> serde_json::Value {
+    let site = site_url(conf);
+    let author = json!({
+        "name": &conf.site_author,
+        "url": format!("mailto:{}", conf.author_email),
+    });
+    let items: Vec<serde_json::Value> = posts.iter().map(|post| {
+        let url = post_url(conf, post);
+        json!({
+            "id": post_id(conf, post),
+            "url": &url,
+            "title": &post.ident.title,
+            "summary": &post.ident.summary.excerpt,
+            "content_html": markdown.to_html(&post.content, Trust::of_post(post.trusted_html)),
+            "date_published": rfc3339(published(post)),
+            "date_modified": rfc3339(post.updated),
+            "tags": &post.ident.tags,
+        })
+    }).collect();
+    json!({
+        "version": "https://jsonfeed.org/version/1.1",
+        "title": feed_title(conf),
+        "home_page_url": format!("{}/notes", site),
+        "feed_url": format!("{}/notes/feed.json", site),
+        "authors": [author],
+        "items": items,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::entity::{PostIdent, PostStatus, PostSummary};
+    use super::super::highlight::{Highlighter, DEFAULT_THEME};
+    use super::super::sanitize::Sanitizer;
+
+    fn helper_conf() -> Config {
+        serde_json::from_value(json!({
+            "site_domain": "example.com",
+            "site_author": "John Titor",
+            "author_twitter": "john_titor",
+            "author_email": "john_titor@example.com",
+            "author_github_name": "john_titor",
+            "port": 3001,
+            "host": "0.0.0.0",
+            "db_file": ":memory:",
+            "static_files": "./external_files"
+        })).unwrap()
+    }
+
+    fn helper_renderer() -> Renderer {
+        let conf = helper_conf();
+        Renderer::new(&conf.markdown_extensions, Highlighter::new(DEFAULT_THEME),
+                      Sanitizer::new(&conf.admin_html_tags, &conf.author_html_tags))
+    }
+
+    fn helper_posts() -> Vec<Post> {
+        vec![Post {
+            ident: PostIdent {
+                id: 1,
+                title: "Fish & Chips <3".to_string(),
+                created: 1_700_000_000_000,
+                slug: "fish-chips-3".to_string(),
+                published: Some(1_700_000_000_000),
+                tags: vec!["food".to_string()],
+                summary: PostSummary {
+                    word_count: 1,
+                    reading_minutes: 1,
+                    excerpt: "tasty & <good>".to_string(),
+                },
+            },
+            content: "*tasty*".to_string(),
+            updated: 1_700_000_060_000,
+            status: PostStatus::Published,
+            preview_token: "secret".to_string(),
+            source_path: None,
+            trusted_html: true,
+            author: None,
+        }]
+    }
+
+    #[test]
+    fn test_atom() {
+        let doc = atom(&helper_conf(), &helper_renderer(), &helper_posts());
+        assert!(doc.contains("<title>Fish &amp; Chips &lt;3</title>"));
+        assert!(doc.contains("<id>tag:example.com,2023:post-1</id>"));
+        assert!(doc.contains("href=\"https://example.com/notes/fish-chips-3\""));
+        assert!(doc.contains("<published>2023-11-14T22:13:20Z</published>"));
+        assert!(doc.contains("<updated>2023-11-14T22:14:20Z</updated>"));
+        assert!(doc.contains("<content type=\"html\">&lt;p&gt;&lt;em&gt;tasty&lt;/em&gt;&lt;/p&gt;\n</content>"));
+        assert!(doc.contains("<category term=\"food\"/>"));
+        assert!(doc.contains("<summary>tasty &amp; &lt;good&gt;</summary>"));
+        assert!(doc.contains("<email>john_titor@example.com</email>"));
+    }
+
+    #[test]
+    fn test_rss() {
+        let doc = rss(&helper_conf(), &helper_renderer(), &helper_posts());
+        assert!(doc.contains("<pubDate>Tue, 14 Nov 2023 22:13:20 +0000</pubDate>"));
+        assert!(doc.contains("<author>john_titor@example.com (John Titor)</author>"));
+        assert!(doc.contains("<guid isPermaLink=\"false\">tag:example.com,2023:post-1</guid>"));
+        assert!(doc.contains("<link>https://example.com/notes/fish-chips-3</link>"));
+    }
+
+    #[test]
+    fn test_json_feed() {
+        let doc = json_feed(&helper_conf(), &helper_renderer(), &helper_posts());
+        assert_eq!(doc["items"][0]["id"], "tag:example.com,2023:post-1");
+        assert_eq!(doc["items"][0]["url"], "https://example.com/notes/fish-chips-3");
+        assert_eq!(doc["items"][0]["content_html"], "<p><em>tasty</em></p>\n");
+        assert_eq!(doc["items"][0]["summary"], "tasty & <good>");
+        assert_eq!(doc["items"][0]["date_modified"], "2023-11-14T22:14:20Z");
+        assert_eq!(doc["authors"][0]["name"], "John Titor");
+    }
+}
@@ -0,0 +1,94 @@
+use log::error;
+use syntect::highlighting::ThemeSet;
+use syntect::html::{css_for_theme_with_class_style, ClassStyle, ClassedHTMLGenerator};
+use syntect::parsing::SyntaxSet;
+use syntect::util::LinesWithEndings;
+
+use super::markdown::escape_html;
+
+// Used when the configured theme does not exist
+pub const DEFAULT_THEME: &str = "Solarized (dark)";
+
+// Every CSS class of the highlighted code starts with this prefix (hl-code, hl-keyword...)
+const CLASS_STYLE: ClassStyle = ClassStyle::SpacedPrefixed { prefix: "hl-" };
+
+/**
+ * Highlights code blocks with CSS classes. The colors come from the stylesheet of the theme.
+ */
+pub struct Highlighter {
+    syntaxes: SyntaxSet,
+    css: String,
+}
+
+impl Highlighter {
+    /**
+     * Loads the syntax definitions and the stylesheet of `theme`, one of the syntect
+     * default themes (base16-ocean.dark, InspiredGitHub, Solarized (light)...)
+     */
+    pub fn new(theme: &str) -> Self {
+        let mut themes = ThemeSet::load_defaults().themes;
+        let theme = match themes.remove(theme) {
+            Some(t) => t,
+            None => {
+                error!("Unknown highlight theme {}, using {}", theme, DEFAULT_THEME);
+                themes.remove(DEFAULT_THEME).expect("Default highlight theme is missing")
+            }
+        };
+        let css = css_for_theme_with_class_style(&theme, CLASS_STYLE).unwrap_or_else(|e| {
+            error!("Unable to generate the highlight stylesheet: {}", e);
+            String::new()
+        });
+        Self {
+            syntaxes: SyntaxSet::load_defaults_newlines(),
+            css,
+        }
+    }
+
+    pub fn css(&self) -> &str {
+        &self.css
+    }
+
+    /**
+     * HTML of a code block. `lang` is the info string of the fence (rust, py, sh...),
+     * unknown languages are shown as plain text.
+     */
+    pub fn code_block(&self, code: &str, lang: &str) -> String {
+        let syntax = self.syntaxes.find_syntax_by_token(lang)
+            .unwrap_or_else(|| self.syntaxes.find_syntax_plain_text());
+        let mut generator = ClassedHTMLGenerator::new_with_class_style(syntax, &self.syntaxes, CLASS_STYLE);
+        for line in LinesWithEndings::from(code) {
+            if let Err(e) = generator.parse_html_for_line_which_includes_newline(line) {
+                error!("Unable to highlight {} code: {}", lang, e);
+                return format!("<pre class=\"hl-code\"><code>{}</code></pre>\n", escape_html(code));
+            }
+        }
+        let lang_class = if lang.is_empty() {
+            String::new()
+        } else {
+            format!(" class=\"language-{}\"", escape_html(lang))
+        };
+        format!("<pre class=\"hl-code\"><code{}>{}</code></pre>\n", lang_class, generator.finalize())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_code_block() {
+        let highlighter = Highlighter::new(DEFAULT_THEME);
+        let html = highlighter.code_block("fn main() {}\n", "rust");
+        assert!(html.starts_with("<pre class=\"hl-code\"><code class=\"language-rust\">"));
+        assert!(html.contains("<span class=\"hl-storage hl-type hl-function hl-rust\">fn</span>"));
+        let html = highlighter.code_block("<b>&</b>\n", "unknown\"lang");
+        assert!(html.contains("class=\"language-unknown&quot;lang\""));
+        assert!(html.contains("&lt;b&gt;&amp;&lt;/b&gt;"));
+    }
+
+    #[test]
+    fn test_theme() {
+        assert!(Highlighter::new(DEFAULT_THEME).css().contains(".hl-code"));
+        assert_eq!(Highlighter::new("no such theme").css(), Highlighter::new(DEFAULT_THEME).css());
+    }
+}
@@ -2,9 +2,17 @@ mod constants;
 mod routes;
 mod data;
 mod entity;
+mod auth;
+mod error;
+mod markdown;
+mod feed;
+mod sitemap;
+mod highlight;
+mod sanitize;
 
 use std::sync::Arc;
 use std::net::SocketAddr;
+use std::time::Duration;
 
 extern crate log;
 extern crate handlebars;
@@ -12,9 +20,9 @@ extern crate handlebars;
 use log::{info, error};
 
 use axum::{
-    routing::{get, post},
+    routing::{delete, get, post, put},
     Router,
-    extract::Extension,
+    extract::{DefaultBodyLimit, Extension},
 };
 use tokio::net::TcpListener;
 use tower_http::{
@@ -33,24 +41,96 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         .init();
 
   let state = data::Datasources::new();
+
+  // `xmithd_backend sync <dir> [--delete] [--author <username>]` imports markdown notes and exits
+  let args: Vec<String> = std::env::args().collect();
+  match args.get(1).map(String::as_str) {
+      Some("sync") => return sync(state, &args[2..]),
+      // `xmithd_backend passwd <username>` sets the password read from the standard input
+      Some("passwd") => return passwd(state, &args[2..]),
+      _ => {}
+  }
+
   let addr_str = format!("{}:{}", state.conf().host, state.conf().port);
   let addr: SocketAddr = addr_str.parse()?;
 
   info!("Starting http server at http://{}", &addr_str);
   let static_files_path = String::from(&state.conf().static_files);
+  let media_path = String::from(&state.conf().media_dir);
+  std::fs::create_dir_all(&media_path)?;
+  let media_max_size = state.conf().media_max_size;
   let datasources_arc = Arc::new(state);
 
+  // Related posts are computed in the background after posts change, never while serving a page
+  let related_ds = datasources_arc.clone();
+  tokio::spawn(async move {
+      let mut interval = tokio::time::interval(Duration::from_secs(constants::RELATED_REFRESH_SECONDS));
+      loop {
+          interval.tick().await;
+          if !related_ds.db().related_stale() {
+              continue;
+          }
+          let ds = related_ds.clone();
+          match tokio::task::spawn_blocking(move || ds.db().refresh_related()).await {
+              Ok(Ok(())) => {},
+              Ok(Err(e)) => error!("Failed to compute the related posts: {}", e),
+              Err(e) => error!("Related posts task failed: {}", e),
+          }
+      }
+  });
+
+  // Management endpoints: every handler of this router takes an AdminUser
+  let admin_api = Router::new()
+      .route("/users", get(routes::api::list_users))
+      .route("/users/{id}/role", put(routes::api::set_user_role))
+      .route("/cache", get(routes::api::cache_stats));
+
   let app = Router::new()
       .route("/", get(routes::home))
       .route("/apps", get(routes::apps))
       .route("/about", get(routes::about))
       .route("/contact", get(routes::contact))
       .route("/notes", get(routes::notes))
-      .route("/notes/post/{id}", get(routes::post_raw))
-      .route("/users", get(routes::user_list))
+      .route("/notes/post/{id}", get(routes::post_by_id))
+      .route("/notes/tags", get(routes::tags))
+      .route("/notes/archive", get(routes::archive))
+      .route("/notes/archive/{year}/{month}", get(routes::archive_month))
+      .route("/notes/search", get(routes::search))
+      .route("/notes/search.json", get(routes::search_json))
+      .route("/notes/feed.atom", get(routes::feed_atom))
+      .route("/notes/feed.rss", get(routes::feed_rss))
+      .route("/notes/feed.json", get(routes::feed_json))
+      .route("/notes/tag/{name}", get(routes::notes_by_tag))
+      .route("/notes/series/{slug}", get(routes::series))
+      .route("/notes/preview/{token}", get(routes::post_preview))
+      .route("/notes/{slug}", get(routes::post_raw))
+      .route("/authors/{username}", get(routes::author))
+      .route("/sitemap.xml", get(routes::sitemap))
+      .route("/robots.txt", get(routes::robots))
+      .route("/highlight.css", get(routes::highlight_css))
+      .route("/login", get(routes::account::login_form).post(routes::account::login))
+      .route("/logout", get(routes::account::logout_form).post(routes::account::logout))
       .route("/utils/whatsmyip", get(routes::whatsmyip))
       .route("/api/inventory/solve", post(routes::solve))
+      .route("/api/posts", post(routes::api::create_post))
+      .route("/api/posts/{id}", put(routes::api::update_post).delete(routes::api::delete_post))
+      .route("/api/posts/{id}/revisions", get(routes::api::list_revisions))
+      .route("/api/posts/{id}/revisions/{revision_id}/restore", post(routes::api::restore_revision))
+      .route("/api/posts/{id}/diff", get(routes::api::diff_revisions))
+      .route("/api/series", get(routes::api::list_series).post(routes::api::create_series))
+      .route("/api/series/{id}", put(routes::api::update_series).delete(routes::api::delete_series))
+      .route("/api/media", get(routes::api::list_media)
+          .post(routes::api::upload_media)
+          // Room for the multipart framing around the file
+          .layer(DefaultBodyLimit::max(media_max_size + constants::MULTIPART_OVERHEAD)))
+      .route("/api/media/{id}", delete(routes::api::delete_media))
+      .route("/api/media/{id}/variants", post(routes::api::resize_media))
+      .route("/api/profile", get(routes::api::get_profile).put(routes::api::update_profile))
+      .route("/api/tokens", get(routes::api::list_tokens).post(routes::api::create_token))
+      .route("/api/tokens/{id}", delete(routes::api::delete_token))
+      .nest("/api/admin", admin_api)
       .nest_service("/public", ServeDir::new(&static_files_path))
+      .nest_service(constants::MEDIA_URL, ServeDir::new(&media_path))
       .fallback_service(ServeDir::new(constants::PUBLIC_FOLDER))
       .layer(Extension(datasources_arc.clone()))
       .layer(
@@ -82,3 +162,47 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
 
   close_result // Return the result from the match
 }
+
+fn sync(state: data::Datasources, args: &[String]) -> Result<(), Box<dyn std::error::Error>> {
+  let mut dir = None;
+  let mut options = data::sync::SyncOptions { delete_missing: false, author: None };
+  let mut args = args.iter();
+  while let Some(arg) = args.next() {
+      match arg.as_str() {
+          "--delete" => options.delete_missing = true,
+          "--author" => options.author = Some(args.next().ok_or("--author needs a username")?.clone()),
+          _ if dir.is_none() => dir = Some(std::path::PathBuf::from(arg)),
+          _ => return Err(format!("Unexpected argument {}", arg).into()),
+      }
+  }
+  let dir = dir.ok_or("Usage: xmithd_backend sync <dir> [--delete] [--author <username>]")?;
+
+  let report = data::sync::sync_directory(state.db(), &dir, &options)?;
+  info!("Sync done: {} created, {} updated, {} unchanged, {} deleted, {} failed",
+      report.created, report.updated, report.unchanged, report.deleted, report.failed);
+  state.close_db()?;
+  if report.failed > 0 {
+      return Err(format!("{} file(s) could not be imported", report.failed).into());
+  }
+  Ok(())
+}
+
+fn passwd(state: data::Datasources, args: &[String]) -> Result<(), Box<dyn std::error::Error>> {
+  let username = match args {
+      [username] => username,
+      _ => return Err("Usage: xmithd_backend passwd <username> (the password is read from the standard input)".into()),
+  };
+  let mut password = String::new();
+  std::io::stdin().read_line(&mut password)?;
+  let password = password.trim_end_matches(['\r', '\n']);
+  if password.is_empty() {
+      return Err("The password must not be empty".into());
+  }
+  let hash = auth::hash_password(password).map_err(|e| format!("Failed to hash the password: {}", e))?;
+  if !state.db().set_password_hash(username, &hash)? {
+      return Err(format!("No user named {}", username).into());
+  }
+  info!("Password of {} changed, their sessions were ended", username);
+  state.close_db()?;
+  Ok(())
+}
@@ -0,0 +1,489 @@
+use std::collections::HashSet;
+
+use log::error;
+use pulldown_cmark::{CodeBlockKind, CowStr, Event, Parser, Options, Tag, TagEnd, html};
+
+use super::constants;
+use super::data::slug;
+use super::entity::Media;
+use super::highlight::Highlighter;
+use super::sanitize::{Sanitizer, Trust};
+
+// Paragraph replaced by the table of contents
+const TOC_MARKER: &str = "[TOC]";
+
+// Width of the images in the notes column (at most 56em wide), for their sizes attribute
+const IMAGE_SIZES: &str = "(max-width: 56em) 100vw, 56em";
+
+// Names of the pulldown-cmark extensions in the markdown_extensions setting
+const EXTENSIONS: &[(&str, Options)] = &[
+    ("tables", Options::ENABLE_TABLES),
+    ("footnotes", Options::ENABLE_FOOTNOTES),
+    ("strikethrough", Options::ENABLE_STRIKETHROUGH),
+    ("tasklists", Options::ENABLE_TASKLISTS),
+    ("smart_punctuation", Options::ENABLE_SMART_PUNCTUATION),
+    ("heading_attributes", Options::ENABLE_HEADING_ATTRIBUTES),
+    ("definition_lists", Options::ENABLE_DEFINITION_LIST),
+    ("gfm", Options::ENABLE_GFM),
+    ("math", Options::ENABLE_MATH),
+    ("superscript", Options::ENABLE_SUPERSCRIPT),
+    ("subscript", Options::ENABLE_SUBSCRIPT),
+];
+// Site-specific blocks, see expand_blocks
+const ADMONITIONS_EXTENSION: &str = "admonitions";
+const FIGURES_EXTENSION: &str = "figures";
+
+pub const DEFAULT_EXTENSIONS: &[&str] = &[
+    "tables", "footnotes", "strikethrough", "tasklists", "smart_punctuation", ADMONITIONS_EXTENSION, FIGURES_EXTENSION,
+];
+
+// Kinds of admonitions (:::note) with their default title
+const ADMONITIONS: &[(&str, &str)] = &[("note", "Note"), ("warning", "Warning"), ("tip", "Tip")];
+
+pub fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
+
+/**
+ * Renders the markdown of the posts with the extensions enabled in the config
+ */
+pub struct Renderer {
+    options: Options,
+    admonitions: bool,
+    figures: bool,
+    highlighter: Highlighter,
+    sanitizer: Sanitizer,
+}
+
+impl Renderer {
+    /**
+     * `extensions` are names from EXTENSIONS, "admonitions" or "figures". Unknown names are ignored.
+     */
+    pub fn new(extensions: &[String], highlighter: Highlighter, sanitizer: Sanitizer) -> Self {
+        let mut options = Options::empty();
+        let mut admonitions = false;
+        let mut figures = false;
+        for name in extensions {
+            match EXTENSIONS.iter().find(|(n, _)| n == name) {
+                Some((_, option)) => options.insert(*option),
+                None if name == ADMONITIONS_EXTENSION => admonitions = true,
+                None if name == FIGURES_EXTENSION => figures = true,
+                None => error!("Unknown markdown extension {}", name),
+            }
+        }
+        Self {
+            options,
+            admonitions,
+            figures,
+            highlighter,
+            sanitizer,
+        }
+    }
+
+    pub fn highlighter(&self) -> &Highlighter {
+        &self.highlighter
+    }
+
+    /**
+     * Render the markdown content of a post to HTML, keeping the tags allowed for `trust`
+     */
+    pub fn to_html(&self, content: &str, trust: Trust) -> String {
+        let content = self.expand_blocks(content);
+        let mut html_output = String::new();
+        html::push_html(&mut html_output, Parser::new_ext(&content, self.options));
+        self.sanitizer.clean(&html_output, trust)
+    }
+
+    /**
+     * Render the markdown content of a post for its page: code blocks are highlighted and
+     * headings get an id and an anchor link. A paragraph containing only [TOC] is replaced by
+     * the table of contents. Only the tags allowed for `trust` are kept.
+     */
+    pub fn render_post(&self, content: &str, trust: Trust) -> RenderedPost {
+        let content = self.expand_blocks(content);
+        let mut events = highlight_code_blocks(Parser::new_ext(&content, self.options), &self.highlighter);
+        let headings = add_heading_anchors(&mut events);
+        let toc = toc_html(&headings);
+        let toc_in_content = replace_toc_marker(&mut events, &toc);
+        let mut html_output = String::new();
+        html::push_html(&mut html_output, events.into_iter());
+        RenderedPost {
+            html: self.sanitizer.clean(&html_output, trust),
+            // Its links get the prefix of the ids
+            toc: self.sanitizer.clean(&toc, trust),
+            headings: headings.len(),
+            toc_in_content,
+        }
+    }
+
+    // Inline HTML of a single line of markdown (without the paragraph)
+    fn inline_html(&self, text: &str) -> String {
+        let mut html_output = String::new();
+        html::push_html(&mut html_output, Parser::new_ext(text, self.options));
+        html_output.trim().trim_start_matches("<p>").trim_end_matches("</p>").to_string()
+    }
+
+    /**
+     * Turns the site-specific blocks into HTML blocks around their markdown content:
+     *
+     *   :::note Optional title          :::figure Optional *caption*
+     *   Some **markdown**               ![alt text](/public/image.png)
+     *   :::                             :::
+     *
+     * Admonitions are `note`, `warning` and `tip`. Blocks can be nested, lines inside code
+     * blocks are left alone.
+     */
+    fn expand_blocks(&self, content: &str) -> String {
+        if !self.admonitions && !self.figures {
+            return content.to_string();
+        }
+        let mut out = String::with_capacity(content.len());
+        // Closing HTML of the blocks currently open
+        let mut open: Vec<String> = Vec::new();
+        // Fence of the code block being read
+        let mut fence: Option<String> = None;
+        for line in content.split_inclusive('\n') {
+            let trimmed = line.trim();
+            let indented = line.len() - line.trim_start().len() >= 4;
+            if let Some(f) = &fence {
+                if trimmed.starts_with(f.as_str()) && trimmed.chars().all(|c| f.starts_with(c)) {
+                    fence = None;
+                }
+                out.push_str(line);
+                continue;
+            }
+            if !indented && (trimmed.starts_with("```") || trimmed.starts_with("~~~")) {
+                let c = trimmed.chars().next().unwrap_or('`');
+                fence = Some(trimmed.chars().take_while(|&x| x == c).collect());
+                out.push_str(line);
+                continue;
+            }
+            let Some(block) = trimmed.strip_prefix(":::").filter(|_| !indented) else {
+                out.push_str(line);
+                continue;
+            };
+            let (name, title) = block.split_once(char::is_whitespace).unwrap_or((block, ""));
+            let title = title.trim();
+            if name.is_empty() && title.is_empty() {
+                match open.pop() {
+                    Some(close) => out.push_str(&close),
+                    None => out.push_str(line),
+                }
+            } else if let Some((kind, default_title)) = ADMONITIONS.iter().find(|(k, _)| *k == name).filter(|_| self.admonitions) {
+                let title = if title.is_empty() { escape_html(default_title) } else { self.inline_html(title) };
+                out.push_str(&format!("\n<aside class=\"admonition admonition-{}\">\n<p class=\"admonition-title\">{}</p>\n\n",
+                                      kind, title));
+                open.push("\n</aside>\n\n".to_string());
+            } else if name == "figure" && self.figures {
+                out.push_str("\n<figure>\n\n");
+                let caption = if title.is_empty() {
+                    String::new()
+                } else {
+                    format!("<figcaption>{}</figcaption>\n", self.inline_html(title))
+                };
+                open.push(format!("\n{}</figure>\n\n", caption));
+            } else {
+                out.push_str(line);
+            }
+        }
+        // Blocks left open at the end of the post
+        while let Some(close) = open.pop() {
+            out.push_str(&close);
+        }
+        out
+    }
+}
+
+// A heading of the post, as listed in the table of contents
+struct Heading {
+    level: u8,
+    id: String,
+    text: String,
+}
+
+// A post rendered for its page
+pub struct RenderedPost {
+    pub html: String,
+    // Nested lists of links to the headings (empty when there are none)
+    pub toc: String,
+    pub headings: usize,
+    // Whether the table of contents was inserted in the html by a [TOC] marker
+    pub toc_in_content: bool,
+}
+
+fn highlight_code_blocks<'a>(parser: Parser<'a>, highlighter: &Highlighter) -> Vec<Event<'a>> {
+    let mut events = Vec::new();
+    // Language and text of the code block being read
+    let mut code_block: Option<(String, String)> = None;
+    for event in parser {
+        match (event, code_block.as_mut()) {
+            (Event::Start(Tag::CodeBlock(kind)), _) => {
+                let lang = match kind {
+                    // The info string may contain more than the language (```rust ignore)
+                    CodeBlockKind::Fenced(info) => info.split_whitespace().next().unwrap_or("").to_string(),
+                    CodeBlockKind::Indented => String::new(),
+                };
+                code_block = Some((lang, String::new()));
+            },
+            (Event::Text(text), Some((_, code))) => code.push_str(&text),
+            (Event::End(TagEnd::CodeBlock), Some((lang, code))) => {
+                events.push(Event::Html(CowStr::from(highlighter.code_block(code, lang))));
+                code_block = None;
+            },
+            (event, _) => events.push(event),
+        }
+    }
+    events
+}
+
+// Gives every heading an id made from its text (unique in the post) and a link to itself
+fn add_heading_anchors(events: &mut Vec<Event>) -> Vec<Heading> {
+    let mut headings = Vec::new();
+    let mut used_ids = HashSet::new();
+    let mut i = 0;
+    while i < events.len() {
+        let Event::Start(Tag::Heading { level, id: explicit_id, .. }) = &events[i] else {
+            i += 1;
+            continue;
+        };
+        let level = *level as u8;
+        let explicit_id = explicit_id.as_ref().map(|id| id.to_string());
+        let end = events[i..].iter().position(|e| matches!(e, Event::End(TagEnd::Heading(_))))
+            .map_or(events.len(), |p| i + p);
+        let text: String = events[i + 1..end].iter().filter_map(|e| match e {
+            Event::Text(t) | Event::Code(t) => Some(t.as_ref()),
+            _ => None,
+        }).collect();
+        let base = match explicit_id {
+            // {#id} with the heading_attributes extension
+            Some(id) => id,
+            None if text.chars().any(char::is_alphanumeric) => slug::slugify(&text),
+            None => "section".to_string(),
+        };
+        let mut id = base.clone();
+        let mut n = 1;
+        while !used_ids.insert(id.clone()) {
+            n += 1;
+            id = format!("{}-{}", base, n);
+        }
+        if let Event::Start(Tag::Heading { id: heading_id, .. }) = &mut events[i] {
+            *heading_id = Some(CowStr::from(id.clone()));
+        }
+        let anchor = format!(" <a class=\"anchor\" href=\"#{}\" aria-label=\"Link to this section\">#</a>", escape_html(&id));
+        events.insert(end, Event::InlineHtml(CowStr::from(anchor)));
+        headings.push(Heading { level, id, text: text.trim().to_string() });
+        i = end + 2;
+    }
+    headings
+}
+
+fn toc_html(headings: &[Heading]) -> String {
+    let mut out = String::new();
+    // Levels of the lists currently open
+    let mut levels: Vec<u8> = Vec::new();
+    for heading in headings {
+        while levels.last().is_some_and(|&l| l > heading.level) {
+            out.push_str("</li></ul>");
+            levels.pop();
+        }
+        if levels.last() == Some(&heading.level) {
+            out.push_str("</li>");
+        } else {
+            out.push_str("<ul>");
+            levels.push(heading.level);
+        }
+        out.push_str(&format!("<li><a href=\"#{}\">{}</a>", escape_html(&heading.id), escape_html(&heading.text)));
+    }
+    for _ in levels {
+        out.push_str("</li></ul>");
+    }
+    out
+}
+
+fn replace_toc_marker(events: &mut Vec<Event>, toc: &str) -> bool {
+    let mut replaced = false;
+    let mut i = 0;
+    while i < events.len() {
+        if matches!(events[i], Event::Start(Tag::Paragraph)) {
+            let end = events[i..].iter().position(|e| matches!(e, Event::End(TagEnd::Paragraph)))
+                .map_or(events.len(), |p| i + p);
+            let mut text = String::new();
+            let only_text = events[i + 1..end].iter().all(|e| match e {
+                Event::Text(t) => { text.push_str(t); true },
+                _ => false,
+            });
+            if only_text && text.trim() == TOC_MARKER {
+                let nav = format!("<nav class=\"toc\">{}</nav>\n", toc);
+                events.splice(i..(end + 1).min(events.len()), [Event::Html(CowStr::from(nav))]);
+                replaced = true;
+            }
+        }
+        i += 1;
+    }
+    replaced
+}
+
+/**
+ * Gives the uploaded images of the html (`<img src="/media/...">`) their size, so browsers keep
+ * their space while loading, and a srcset of their resized copies. `lookup` finds an upload by
+ * file name. Images with a width or srcset written by the author are left alone.
+ */
+pub fn responsive_images(html: &str, lookup: impl Fn(&str) -> Option<Media>) -> String {
+    let prefix = format!("{}/", constants::MEDIA_URL);
+    let mut out = String::with_capacity(html.len());
+    let mut rest = html;
+    while let Some(start) = rest.find("<img ") {
+        let Some(len) = rest[start..].find('>') else {
+            break;
+        };
+        let tag = &rest[start..start + len];
+        out.push_str(&rest[..start]);
+        out.push_str(tag);
+        let upload = tag.split_once(" src=\"")
+            .and_then(|(_, src)| src.split('"').next())
+            .and_then(|src| src.strip_prefix(prefix.as_str()))
+            .filter(|_| !tag.contains(" width=") && !tag.contains(" srcset="))
+            .and_then(&lookup);
+        if let Some(Media { width: Some(width), height: Some(height), url, variants, .. }) = upload {
+            out.push_str(&format!(" width=\"{}\" height=\"{}\"", width, height));
+            if !variants.is_empty() {
+                let srcset: Vec<String> = variants.iter()
+                    .map(|v| format!("{} {}w", v.url, v.width))
+                    .chain([format!("{} {}w", url, width)])
+                    .collect();
+                out.push_str(&format!(" srcset=\"{}\" sizes=\"{}\"", srcset.join(", "), IMAGE_SIZES));
+            }
+            out.push_str(" loading=\"lazy\"");
+        }
+        rest = &rest[start + len..];
+    }
+    out.push_str(rest);
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::entity::ImageVariant;
+    use super::super::highlight::DEFAULT_THEME;
+
+    fn helper_renderer_with(extensions: &[&str]) -> Renderer {
+        let extensions: Vec<String> = extensions.iter().map(|e| e.to_string()).collect();
+        Renderer::new(&extensions, Highlighter::new(DEFAULT_THEME), Sanitizer::new(&["iframe".to_string()], &[]))
+    }
+
+    fn helper_renderer() -> Renderer {
+        helper_renderer_with(DEFAULT_EXTENSIONS)
+    }
+
+    #[test]
+    fn test_highlighted_html() {
+        let renderer = helper_renderer();
+        let html = renderer.render_post("Some `code`:\n\n```rust ignore\nlet a = 1;\n```\n\n    indented <b>\n", Trust::Admin).html;
+        assert!(html.starts_with("<p>Some <code>code</code>:</p>\n<pre class=\"hl-code\"><code class=\"language-rust\">"));
+        assert!(html.contains("<span class=\"hl-storage hl-type hl-rust\">let</span>"));
+        assert!(html.contains("<pre class=\"hl-code\"><code><span class=\"hl-text hl-plain\">indented &lt;b&gt;\n</span></code></pre>"));
+        assert_eq!(renderer.to_html("```rust\nlet a = 1;\n```\n", Trust::Admin), "<pre><code class=\"language-rust\">let a = 1;\n</code></pre>\n");
+    }
+
+    #[test]
+    fn test_heading_anchors() {
+        let post = helper_renderer().render_post("# Intro\n\n## Use `Vec<T>`\n\n## Intro\n\n## ???\n", Trust::Admin);
+        assert_eq!(post.headings, 4);
+        assert!(post.html.contains("<h1 id=\"note-intro\">Intro <a class=\"anchor\" href=\"#note-intro\""));
+        assert!(post.html.contains("<h2 id=\"note-use-vec-t\">Use <code>Vec&lt;T&gt;</code> <a class=\"anchor\" href=\"#note-use-vec-t\""));
+        assert!(post.html.contains("<h2 id=\"note-intro-2\">"));
+        assert!(post.html.contains("<h2 id=\"note-section\">"));
+        assert!(!post.toc_in_content);
+        let renderer = helper_renderer_with(&["heading_attributes"]);
+        assert!(renderer.render_post("## Title {#custom}\n", Trust::Admin).html.starts_with("<h2 id=\"note-custom\">Title <a class=\"anchor\" href=\"#note-custom\""));
+    }
+
+    #[test]
+    fn test_toc() {
+        let renderer = helper_renderer();
+        let post = renderer.render_post("[TOC]\n\n## A\n\n### A.1\n\n### A.2\n\n## B & C\n\n# Top\n", Trust::Admin);
+        assert_eq!(post.toc, "<ul><li><a href=\"#note-a\">A</a><ul><li><a href=\"#note-a-1\">A.1</a></li><li><a href=\"#note-a-2\">A.2</a></li></ul>\
+                              </li><li><a href=\"#note-b-c\">B &amp; C</a></li></ul><ul><li><a href=\"#note-top\">Top</a></li></ul>");
+        assert!(post.toc_in_content);
+        assert!(post.html.starts_with(&format!("<nav class=\"toc\">{}</nav>\n<h2 id=\"note-a\">", post.toc)));
+        assert_eq!(renderer.render_post("No headings", Trust::Admin).toc, "");
+        assert!(renderer.render_post("Not a [TOC] marker", Trust::Admin).html.contains("Not a [TOC] marker"));
+    }
+
+    #[test]
+    fn test_extensions() {
+        let renderer = helper_renderer();
+        let html = renderer.to_html("| a | b |\n|---|---|\n| 1 | 2 |\n\n- [x] done\n\n\"quoted\" ~~old~~[^1]\n\n[^1]: A note\n", Trust::Admin);
+        assert!(html.contains("<table>"));
+        assert!(html.contains("<input disabled=\"\" type=\"checkbox\" checked=\"\">"));
+        assert!(html.contains("\u{201c}quoted\u{201d} <del>old</del>"));
+        assert!(html.contains("class=\"footnote-definition\""));
+        let plain = helper_renderer_with(&["unknown"]);
+        assert!(plain.to_html("| a | b |\n|---|---|\n", Trust::Admin).contains("<p>| a | b |"));
+        assert_eq!(plain.to_html(":::note\nx\n:::\n", Trust::Admin), "<p>:::note\nx\n:::</p>\n");
+    }
+
+    #[test]
+    fn test_admonitions() {
+        let renderer = helper_renderer();
+        let html = renderer.to_html("Intro\n:::warning Be *careful*\nSome **text**\n\n:::tip\nNested\n:::\n:::\nAfter\n", Trust::Admin);
+        assert_eq!(html, "<p>Intro</p>\n<aside class=\"admonition admonition-warning\">\n<p class=\"admonition-title\">Be <em>careful</em></p>\n\
+                          <p>Some <strong>text</strong></p>\n<aside class=\"admonition admonition-tip\">\n<p class=\"admonition-title\">Tip</p>\n\
+                          <p>Nested</p>\n</aside>\n</aside>\n<p>After</p>\n");
+        // Not blocks
+        let html = renderer.to_html("```\n:::note\n```\n\n:::unknown\n\n:::\n", Trust::Admin);
+        assert!(html.contains("<code>:::note\n</code>"));
+        assert!(html.contains("<p>:::unknown</p>\n<p>:::</p>"));
+        // Unclosed
+        assert!(renderer.to_html(":::note\nText", Trust::Admin).ends_with("<p>Text</p>\n</aside>\n"));
+    }
+
+    #[test]
+    fn test_sanitized() {
+        let renderer = helper_renderer();
+        let content = "Hi <script>alert(1)</script><b onclick=\"alert(1)\">there</b>\n\n<iframe src=\"https://example.com/embed\"></iframe>\n";
+        assert_eq!(renderer.to_html(content, Trust::Author), "<p>Hi <b>there</b></p>\n\n");
+        assert!(renderer.to_html(content, Trust::Admin).ends_with("<iframe src=\"https://example.com/embed\"></iframe>\n"));
+        assert!(!renderer.render_post(content, Trust::Author).html.contains("script"));
+    }
+
+    #[test]
+    fn test_figures() {
+        let html = helper_renderer().to_html(":::figure A [cat](https://example.com)\n![Cat](/public/cat.png)\n:::\n", Trust::Admin);
+        assert_eq!(html, "<figure>\n<p><img src=\"/public/cat.png\" alt=\"Cat\"></p>\n\
+                          <figcaption>A <a href=\"https://example.com\">cat</a></figcaption>\n</figure>\n");
+    }
+
+    #[test]
+    fn test_responsive_images() {
+        let lookup = |name: &str| {
+            (name == "abc.jpg").then(|| Media {
+                id: 1,
+                file_name: name.to_string(),
+                url: "/media/abc.jpg".to_string(),
+                original_name: None,
+                mime_type: "image/jpeg".to_string(),
+                size: 100,
+                post_ids: Vec::new(),
+                created: 0,
+                width: Some(1600),
+                height: Some(900),
+                variants: vec![ImageVariant {
+                    width: 480,
+                    height: 270,
+                    file_name: "abc-480.jpg".to_string(),
+                    url: "/media/abc-480.jpg".to_string(),
+                    size: 10,
+                }],
+            })
+        };
+        let html = helper_renderer().to_html("![A](/media/abc.jpg) ![B](/media/other.png) ![C](/public/c.png)", Trust::Author);
+        assert_eq!(responsive_images(&html, lookup),
+                   "<p><img src=\"/media/abc.jpg\" alt=\"A\" width=\"1600\" height=\"900\" \
+                    srcset=\"/media/abc-480.jpg 480w, /media/abc.jpg 1600w\" sizes=\"(max-width: 56em) 100vw, 56em\" loading=\"lazy\"> \
+                    <img src=\"/media/other.png\" alt=\"B\"> <img src=\"/public/c.png\" alt=\"C\"></p>\n");
+        let sized = "<img src=\"/media/abc.jpg\" width=\"100\">";
+        assert_eq!(responsive_images(sized, lookup), sized);
+    }
+}
@@ -0,0 +1,136 @@
+use axum::{
+    extract::{Extension, Form, Query},
+    http::{header, HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+};
+use std::sync::Arc;
+
+use log::{error, info};
+use serde::Deserialize;
+use serde_json::json;
+
+use super::html_content;
+use super::super::auth::{self, CurrentUser};
+use super::super::constants;
+use super::super::data::Datasources;
+use super::super::entity::User;
+
+type PageResult = Result<Response, (StatusCode, String)>;
+
+#[derive(Deserialize)]
+pub struct LoginParams {
+    next: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct LoginForm {
+    username: String,
+    password: String,
+    #[serde(default)]
+    next: String,
+}
+
+// Where to go after logging in: a path of this site, the home page otherwise
+fn local_path(next: &str) -> &str {
+    // "//host" and "/\host" are other sites for browsers
+    if next.starts_with('/') && !next.starts_with("//") && !next.starts_with("/\\") {
+        next
+    } else {
+        "/"
+    }
+}
+
+fn render(ds: &Datasources, template: &str, data: &serde_json::Value) -> Result<String, (StatusCode, String)> {
+    ds.handlebars().render(template, data).map_err(|e| {
+        error!("Handlebars render error ({}): {}", template, e);
+        (StatusCode::INTERNAL_SERVER_ERROR, format!("Template error: {}", e))
+    })
+}
+
+fn login_page(ds: &Datasources, status: StatusCode, user: Option<&User>, username: &str, next: &str, error: Option<&str>) -> PageResult {
+    let body = render(ds, "login", &json!({
+        "user": user,
+        "username": username,
+        "next": local_path(next),
+        "error": error,
+    }))?;
+    Ok((status, [(header::CACHE_CONTROL, "no-store")], html_content(body)).into_response())
+}
+
+// See other: the browser follows with a GET
+fn redirect(location: &str, cookie: String) -> Response {
+    (StatusCode::SEE_OTHER, [(header::LOCATION, location.to_string()), (header::SET_COOKIE, cookie)]).into_response()
+}
+
+pub async fn login_form(Extension(ds): Extension<Arc<Datasources>>,
+                        user: Option<CurrentUser>,
+                        Query(params): Query<LoginParams>) -> PageResult {
+    let next = params.next.unwrap_or_default();
+    login_page(&ds, StatusCode::OK, user.as_ref().map(|u| &u.0), "", &next, None)
+}
+
+pub async fn login(Extension(ds): Extension<Arc<Datasources>>, Form(form): Form<LoginForm>) -> PageResult {
+    let credentials = ds.db().get_user_credentials(&form.username).map_err(|e| {
+        error!("Failed to get the credentials of {}: {}", form.username, e);
+        (StatusCode::INTERNAL_SERVER_ERROR, "Login failed".to_string())
+    })?;
+    let (user, password_hash) = match credentials {
+        Some((user, hash)) => (Some(user), hash),
+        None => (None, None),
+    };
+    // Hashing takes a while on purpose, keep it off the async threads
+    let password = form.password;
+    let valid = tokio::task::spawn_blocking(move || auth::verify_password(&password, password_hash.as_deref()))
+        .await
+        .unwrap_or(false);
+    let user = match user {
+        Some(user) if valid => user,
+        _ => {
+            info!("Failed login for {}", form.username);
+            return login_page(&ds, StatusCode::UNAUTHORIZED, None, &form.username, &form.next,
+                              Some("Wrong username or password."));
+        }
+    };
+
+    let token = auth::random_token();
+    ds.db().create_session(&auth::hash_api_key(&token), user.id, constants::SESSION_DAYS).map_err(|e| {
+        error!("Failed to create a session for {}: {}", user.name, e);
+        (StatusCode::INTERNAL_SERVER_ERROR, "Login failed".to_string())
+    })?;
+    info!("User {} logged in", user.name);
+    let cookie = auth::session_cookie(&auth::sign_session(ds.session_key(), &token),
+                                      constants::SESSION_DAYS as u64 * 24 * 60 * 60,
+                                      ds.conf().secure_cookies);
+    Ok(redirect(local_path(&form.next), cookie))
+}
+
+pub async fn logout_form(Extension(ds): Extension<Arc<Datasources>>, user: Option<CurrentUser>) -> PageResult {
+    let body = render(&ds, "logout", &json!({ "user": user.map(|u| u.0) }))?;
+    Ok(([(header::CACHE_CONTROL, "no-store")], html_content(body)).into_response())
+}
+
+// A POST, so that links and images from other sites cannot log anyone out
+pub async fn logout(Extension(ds): Extension<Arc<Datasources>>, headers: HeaderMap) -> PageResult {
+    let token = auth::session_cookie_value(&headers).and_then(|value| auth::verify_session(ds.session_key(), value));
+    if let Some(token) = token {
+        ds.db().delete_session(&auth::hash_api_key(token)).map_err(|e| {
+            error!("Failed to delete a session: {}", e);
+            (StatusCode::INTERNAL_SERVER_ERROR, "Logout failed".to_string())
+        })?;
+    }
+    Ok(redirect("/", auth::session_cookie("", 0, ds.conf().secure_cookies)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_local_path() {
+        assert_eq!(local_path("/notes?page=2"), "/notes?page=2");
+        assert_eq!(local_path(""), "/");
+        assert_eq!(local_path("https://example.com/"), "/");
+        assert_eq!(local_path("//example.com/"), "/");
+        assert_eq!(local_path("/\\example.com/"), "/");
+    }
+}
@@ -0,0 +1,569 @@
+use axum::{
+    extract::{Extension, Path, Query, Json, Multipart},
+    http::StatusCode,
+};
+use std::sync::Arc;
+
+use log::{debug, error, info};
+use serde::Deserialize;
+use serde_json::json;
+use similar::{ChangeTag, TextDiff};
+
+use super::{json_content, JsonApiResult};
+use super::super::auth::{self, AdminUser, CurrentUser};
+use super::super::constants;
+use super::super::data::Datasources;
+use super::super::data::slug;
+use super::super::data::{images, media};
+use super::super::entity::{ApiToken, ApiTokenInput, NewApiToken, CacheStats, ImageVariant, Media, MediaUpload, Post, PostInput, Permission, PostRevision, Profile, ProfileInput, RoleInput, Series, SeriesInput, User};
+use super::super::error::ApiError;
+
+// Forbidden unless the role of the user has the permission
+fn require(ds: &Datasources, user: &User, permission: Permission) -> Result<(), ApiError> {
+    if ds.can(user, permission) {
+        Ok(())
+    } else {
+        debug!("User {} is not allowed to {:?}", user.name, permission);
+        Err(ApiError::Forbidden)
+    }
+}
+
+// Forbidden unless the user may edit the post
+fn require_post(ds: &Datasources, user: &User, post_id: u32) -> Result<(), ApiError> {
+    if ds.can_edit_post(user, post_id)? {
+        Ok(())
+    } else {
+        debug!("User {} is not allowed to edit post {}", user.name, post_id);
+        Err(ApiError::Forbidden)
+    }
+}
+
+// Checks the input for a new post, or for the edit of post `id`
+fn validate(ds: &Datasources, input: &PostInput, id: Option<u32>) -> Result<(), ApiError> {
+    if input.title.trim().is_empty() {
+        return Err(ApiError::BadRequest("Title must not be empty".to_string()));
+    }
+    if let Some(s) = &input.slug {
+        let post_slug = slug::slugify(s);
+        if slug::is_reserved(&post_slug) {
+            return Err(ApiError::BadRequest(format!("Slug {} is reserved", s)));
+        }
+        // Only generated slugs get a suffix, a slug that was asked for is refused
+        if ds.db().is_slug_taken(&post_slug, id)? {
+            return Err(ApiError::Conflict(format!("Slug {} is already used by another post", post_slug)));
+        }
+    }
+    Ok(())
+}
+
+pub async fn create_post(Extension(ds): Extension<Arc<Datasources>>,
+                         CurrentUser(user): CurrentUser,
+                         Json(input): Json<PostInput>) -> Result<JsonApiResult<Post>, ApiError> {
+    require(&ds, &user, Permission::EditOwnPosts)?;
+    validate(&ds, &input, None)?;
+    let post = ds.db().create_post(user.id, &input, ds.can(&user, Permission::EmbedHtml))?;
+    info!("User {} created post {}", user.name, post.ident.id);
+    Ok(json_content(StatusCode::CREATED, post))
+}
+
+pub async fn update_post(Extension(ds): Extension<Arc<Datasources>>,
+                         CurrentUser(user): CurrentUser,
+                         Path(id): Path<u32>,
+                         Json(input): Json<PostInput>) -> Result<JsonApiResult<Post>, ApiError> {
+    require_post(&ds, &user, id)?;
+    validate(&ds, &input, Some(id))?;
+    match ds.db().update_post(id, &input, ds.can(&user, Permission::EmbedHtml))? {
+        Some(post) => {
+            info!("User {} updated post {}", user.name, id);
+            Ok(json_content(StatusCode::OK, post))
+        },
+        None => Err(ApiError::NotFound(format!("Post {} not found", id))),
+    }
+}
+
+pub async fn delete_post(Extension(ds): Extension<Arc<Datasources>>,
+                         CurrentUser(user): CurrentUser,
+                         Path(id): Path<u32>) -> Result<StatusCode, ApiError> {
+    require_post(&ds, &user, id)?;
+    if ds.db().delete_post(id)? {
+        info!("User {} deleted post {}", user.name, id);
+        Ok(StatusCode::NO_CONTENT)
+    } else {
+        Err(ApiError::NotFound(format!("Post {} not found", id)))
+    }
+}
+
+pub async fn list_revisions(Extension(ds): Extension<Arc<Datasources>>,
+                            CurrentUser(user): CurrentUser,
+                            Path(id): Path<u32>) -> Result<JsonApiResult<Vec<PostRevision>>, ApiError> {
+    require_post(&ds, &user, id)?;
+    if ds.db().get_post_by_id(id).is_none() {
+        return Err(ApiError::NotFound(format!("Post {} not found", id)));
+    }
+    Ok(json_content(StatusCode::OK, ds.db().get_revisions(id)?))
+}
+
+#[derive(Deserialize)]
+pub struct DiffParams {
+    // Revision id
+    from: u32,
+    // Revision id, the current version of the post when missing
+    to: Option<u32>,
+}
+
+// Title and content of a revision, or of the post itself
+fn version_of(ds: &Datasources, post_id: u32, revision_id: Option<u32>) -> Result<(String, String), ApiError> {
+    match revision_id {
+        Some(rev) => ds.db().get_revision(post_id, rev)?
+            .map(|r| (r.title, r.content))
+            .ok_or_else(|| ApiError::NotFound(format!("Revision {} of post {} not found", rev, post_id))),
+        None => ds.db().get_post_by_id(post_id)
+            .map(|p| (p.ident.title, p.content))
+            .ok_or_else(|| ApiError::NotFound(format!("Post {} not found", post_id))),
+    }
+}
+
+// Line diff of the content between two versions of a post
+pub async fn diff_revisions(Extension(ds): Extension<Arc<Datasources>>,
+                            CurrentUser(user): CurrentUser,
+                            Path(id): Path<u32>,
+                            Query(params): Query<DiffParams>) -> Result<JsonApiResult<serde_json::Value>, ApiError> {
+    require_post(&ds, &user, id)?;
+    let (from_title, mut from_content) = version_of(&ds, id, Some(params.from))?;
+    let (to_title, mut to_content) = version_of(&ds, id, params.to)?;
+    // So that the last lines compare equal whether or not they end with a newline
+    for content in [&mut from_content, &mut to_content] {
+        if !content.is_empty() && !content.ends_with('\n') {
+            content.push('\n');
+        }
+    }
+    let diff = TextDiff::from_lines(&from_content, &to_content);
+    let changes: Vec<serde_json::Value> = diff.iter_all_changes().map(|change| {
+        let op = match change.tag() {
+            ChangeTag::Equal => "equal",
+            ChangeTag::Delete => "delete",
+            ChangeTag::Insert => "insert",
+        };
+        json!({
+            "op": op,
+            "old_line": change.old_index().map(|i| i + 1),
+            "new_line": change.new_index().map(|i| i + 1),
+            "text": change.to_string_lossy().trim_end_matches('\n'),
+        })
+    }).collect();
+    let from_name = format!("revision {}", params.from);
+    let to_name = params.to.map_or("current".to_string(), |rev| format!("revision {}", rev));
+    let unified = diff.unified_diff().header(&from_name, &to_name).to_string();
+    Ok(json_content(StatusCode::OK, json!({
+        "from": params.from,
+        "to": params.to,
+        "title": { "from": from_title, "to": to_title },
+        "changes": changes,
+        "unified": unified,
+    })))
+}
+
+pub async fn restore_revision(Extension(ds): Extension<Arc<Datasources>>,
+                              CurrentUser(user): CurrentUser,
+                              Path((id, revision_id)): Path<(u32, u32)>) -> Result<JsonApiResult<Post>, ApiError> {
+    require_post(&ds, &user, id)?;
+    match ds.db().restore_revision(id, revision_id, ds.can(&user, Permission::EmbedHtml))? {
+        Some(post) => {
+            info!("User {} restored revision {} of post {}", user.name, revision_id, id);
+            Ok(json_content(StatusCode::OK, post))
+        },
+        None => Err(ApiError::NotFound(format!("Revision {} of post {} not found", revision_id, id))),
+    }
+}
+
+fn validate_series(ds: &Datasources, input: &SeriesInput) -> Result<(), ApiError> {
+    if input.title.trim().is_empty() {
+        return Err(ApiError::BadRequest("Title must not be empty".to_string()));
+    }
+    for (i, id) in input.posts.iter().enumerate() {
+        if input.posts[..i].contains(id) {
+            return Err(ApiError::BadRequest(format!("Post {} is listed twice", id)));
+        }
+        if ds.db().get_post_by_id(*id).is_none() {
+            return Err(ApiError::BadRequest(format!("Post {} not found", id)));
+        }
+    }
+    Ok(())
+}
+
+pub async fn list_series(Extension(ds): Extension<Arc<Datasources>>,
+                         CurrentUser(user): CurrentUser) -> Result<JsonApiResult<Vec<Series>>, ApiError> {
+    require(&ds, &user, Permission::ManageSeries)?;
+    Ok(json_content(StatusCode::OK, ds.db().get_series_list()?))
+}
+
+pub async fn create_series(Extension(ds): Extension<Arc<Datasources>>,
+                           CurrentUser(user): CurrentUser,
+                           Json(input): Json<SeriesInput>) -> Result<JsonApiResult<Series>, ApiError> {
+    require(&ds, &user, Permission::ManageSeries)?;
+    validate_series(&ds, &input)?;
+    let series = ds.db().create_series(&input)?;
+    info!("User {} created series {}", user.name, series.id);
+    Ok(json_content(StatusCode::CREATED, series))
+}
+
+pub async fn update_series(Extension(ds): Extension<Arc<Datasources>>,
+                           CurrentUser(user): CurrentUser,
+                           Path(id): Path<u32>,
+                           Json(input): Json<SeriesInput>) -> Result<JsonApiResult<Series>, ApiError> {
+    require(&ds, &user, Permission::ManageSeries)?;
+    validate_series(&ds, &input)?;
+    match ds.db().update_series(id, &input)? {
+        Some(series) => {
+            info!("User {} updated series {}", user.name, id);
+            Ok(json_content(StatusCode::OK, series))
+        },
+        None => Err(ApiError::NotFound(format!("Series {} not found", id))),
+    }
+}
+
+pub async fn delete_series(Extension(ds): Extension<Arc<Datasources>>,
+                           CurrentUser(user): CurrentUser,
+                           Path(id): Path<u32>) -> Result<StatusCode, ApiError> {
+    require(&ds, &user, Permission::ManageSeries)?;
+    if ds.db().delete_series(id)? {
+        info!("User {} deleted series {}", user.name, id);
+        Ok(StatusCode::NO_CONTENT)
+    } else {
+        Err(ApiError::NotFound(format!("Series {} not found", id)))
+    }
+}
+
+/**
+ * Store a file sent as multipart/form-data: the `file` field, and optionally the `post_id`
+ * of the post it is for. A file already uploaded is not stored again, the existing one is returned.
+ */
+pub async fn upload_media(Extension(ds): Extension<Arc<Datasources>>,
+                          CurrentUser(user): CurrentUser,
+                          mut multipart: Multipart) -> Result<JsonApiResult<Media>, ApiError> {
+    require(&ds, &user, Permission::UploadMedia)?;
+    let max_size = ds.conf().media_max_size;
+    let mut file: Option<(Option<String>, Vec<u8>)> = None;
+    let mut post_id = None;
+    while let Some(mut field) = multipart.next_field().await? {
+        match field.name() {
+            Some("file") => {
+                let original_name = field.file_name().map(str::to_string);
+                let mut content = Vec::new();
+                while let Some(chunk) = field.chunk().await? {
+                    if content.len() + chunk.len() > max_size {
+                        return Err(ApiError::PayloadTooLarge(format!("Files are limited to {} bytes", max_size)));
+                    }
+                    content.extend_from_slice(&chunk);
+                }
+                file = Some((original_name, content));
+            },
+            Some("post_id") => {
+                let value = field.text().await?;
+                post_id = Some(value.trim().parse::<u32>()
+                    .map_err(|_| ApiError::BadRequest(format!("Invalid post_id {}", value)))?);
+            },
+            _ => {},
+        }
+    }
+    let (original_name, mut content) = file.ok_or_else(|| ApiError::BadRequest("Missing file field".to_string()))?;
+    let (mime_type, extension) = media::sniff(&content)
+        .ok_or_else(|| ApiError::UnsupportedMediaType("Unknown file type".to_string()))?;
+    if !ds.conf().media_types.iter().any(|t| t == mime_type) {
+        return Err(ApiError::UnsupportedMediaType(format!("{} files are not allowed", mime_type)));
+    }
+    if let Some(id) = post_id {
+        if ds.db().get_post_by_id(id).is_none() {
+            return Err(ApiError::BadRequest(format!("Post {} not found", id)));
+        }
+        require_post(&ds, &user, id)?;
+    }
+
+    if images::strip_location(&mut content, mime_type) {
+        info!("Removed the location from {}", original_name.as_deref().unwrap_or("the upload"));
+    }
+
+    let hash = media::content_hash(&content);
+    if let Some(existing) = ds.db().get_media_by_hash(&hash)? {
+        return Ok(json_content(StatusCode::OK, link_media(&ds, existing, post_id)?));
+    }
+    // Before storing anything, as this is where broken images are found
+    let resized = resize_image(&ds, content.clone(), mime_type).await?;
+    let file_name = media::file_name(&hash, extension);
+    media::store(ds.conf().media_path(), &file_name, &content)
+        .map_err(|e| ApiError::Internal(format!("Failed to store {}: {}", file_name, e)))?;
+    let upload = MediaUpload {
+        hash,
+        file_name,
+        original_name,
+        mime_type: mime_type.to_string(),
+        size: content.len() as u64,
+        post_id,
+    };
+    let Some(mut stored) = ds.db().create_media(user.id, &upload)? else {
+        // Uploaded at the same time by another request, which stores the resized copies
+        let existing = ds.db().get_media_by_hash(&upload.hash)?
+            .ok_or_else(|| ApiError::Internal(format!("Media {} vanished", upload.file_name)))?;
+        return Ok(json_content(StatusCode::OK, link_media(&ds, existing, post_id)?));
+    };
+    if let Some(resized) = resized {
+        stored = store_variants(&ds, &stored, resized)?;
+    }
+    info!("User {} uploaded media {} ({})", user.name, stored.id, stored.file_name);
+    Ok(json_content(StatusCode::CREATED, stored))
+}
+
+// Uploading a file again for another post links it to that post as well
+fn link_media(ds: &Datasources, existing: Media, post_id: Option<u32>) -> Result<Media, ApiError> {
+    Ok(match post_id {
+        Some(post_id) => ds.db().add_media_post(existing.id, post_id)?.unwrap_or(existing),
+        None => existing,
+    })
+}
+
+// Resized copies of an image, made on a blocking thread. None for files that get no copies.
+async fn resize_image(ds: &Datasources, content: Vec<u8>, mime_type: &str) -> Result<Option<(images::Resized, &'static str)>, ApiError> {
+    let Some(extension) = images::variant_extension(mime_type) else {
+        return Ok(None);
+    };
+    let widths = ds.conf().image_widths.clone();
+    let quality = ds.conf().image_quality;
+    let resized = tokio::task::spawn_blocking(move || images::resize(&content, &widths, extension, quality))
+        .await
+        .map_err(|e| ApiError::Internal(format!("Image resizing failed: {}", e)))?
+        .map_err(|e| ApiError::BadRequest(format!("Unreadable image: {}", e)))?;
+    Ok(Some((resized, extension)))
+}
+
+// Writes the resized copies of an image in place of the previous ones, and returns the updated media
+fn store_variants(ds: &Datasources, stored: &Media, (resized, extension): (images::Resized, &str)) -> Result<Media, ApiError> {
+    let dir = ds.conf().media_path();
+    let hash = stored.file_name.split_once('.').map_or(stored.file_name.as_str(), |(hash, _)| hash);
+    let mut variants = Vec::new();
+    for variant in resized.variants {
+        let file_name = media::variant_file_name(hash, variant.width, extension);
+        media::store(dir, &file_name, &variant.content)
+            .map_err(|e| ApiError::Internal(format!("Failed to store {}: {}", file_name, e)))?;
+        variants.push(ImageVariant {
+            width: variant.width,
+            height: variant.height,
+            url: String::new(),
+            file_name,
+            size: variant.content.len() as u64,
+        });
+    }
+    ds.db().set_media_variants(stored.id, resized.width, resized.height, &variants)?;
+    for old in stored.variants.iter().filter(|old| !variants.iter().any(|v| v.file_name == old.file_name)) {
+        if let Err(e) = media::remove(dir, &old.file_name) {
+            error!("Failed to delete {}: {}", old.file_name, e);
+        }
+    }
+    ds.db().get_media(stored.id)?.ok_or_else(|| ApiError::NotFound(format!("Media {} not found", stored.id)))
+}
+
+/**
+ * Make the resized copies of an uploaded image again, for files uploaded before they existed
+ * or after `image_widths` changed
+ */
+pub async fn resize_media(Extension(ds): Extension<Arc<Datasources>>,
+                          CurrentUser(user): CurrentUser,
+                          Path(id): Path<u32>) -> Result<JsonApiResult<Media>, ApiError> {
+    require(&ds, &user, Permission::ManageMedia)?;
+    let stored = ds.db().get_media(id)?.ok_or_else(|| ApiError::NotFound(format!("Media {} not found", id)))?;
+    let content = std::fs::read(ds.conf().media_path().join(&stored.file_name))
+        .map_err(|e| ApiError::Internal(format!("Failed to read {}: {}", stored.file_name, e)))?;
+    let resized = resize_image(&ds, content, &stored.mime_type).await?
+        .ok_or_else(|| ApiError::BadRequest(format!("{} files have no resized copies", stored.mime_type)))?;
+    let updated = store_variants(&ds, &stored, resized)?;
+    info!("User {} resized media {}", user.name, id);
+    Ok(json_content(StatusCode::OK, updated))
+}
+
+#[derive(Deserialize)]
+pub struct MediaParams {
+    // Only the files of this post
+    post_id: Option<u32>,
+}
+
+pub async fn list_media(Extension(ds): Extension<Arc<Datasources>>,
+                        CurrentUser(user): CurrentUser,
+                        Query(params): Query<MediaParams>) -> Result<JsonApiResult<Vec<Media>>, ApiError> {
+    require(&ds, &user, Permission::UploadMedia)?;
+    Ok(json_content(StatusCode::OK, ds.db().get_media_list(params.post_id)?))
+}
+
+pub async fn delete_media(Extension(ds): Extension<Arc<Datasources>>,
+                          CurrentUser(user): CurrentUser,
+                          Path(id): Path<u32>) -> Result<StatusCode, ApiError> {
+    require(&ds, &user, Permission::ManageMedia)?;
+    let stored = ds.db().get_media(id)?.ok_or_else(|| ApiError::NotFound(format!("Media {} not found", id)))?;
+    if ds.db().delete_media(id)? {
+        // The record is gone, a file left behind is only wasted space
+        let dir = ds.conf().media_path();
+        for file_name in stored.variants.iter().map(|v| &v.file_name).chain([&stored.file_name]) {
+            if let Err(e) = media::remove(dir, file_name) {
+                error!("Failed to delete {}: {}", file_name, e);
+            }
+        }
+        info!("User {} deleted media {}", user.name, id);
+    }
+    Ok(StatusCode::NO_CONTENT)
+}
+
+// Hit and miss counters of the render cache
+pub async fn cache_stats(Extension(ds): Extension<Arc<Datasources>>,
+                         _: AdminUser) -> Result<JsonApiResult<CacheStats>, ApiError> {
+    Ok(json_content(StatusCode::OK, ds.cache().stats()))
+}
+
+// Everyone with an account, for the admins
+pub async fn list_users(Extension(ds): Extension<Arc<Datasources>>,
+                        _: AdminUser) -> Result<JsonApiResult<Vec<User>>, ApiError> {
+    Ok(json_content(StatusCode::OK, ds.db().get_users()?))
+}
+
+fn validate_token(ds: &Datasources, user: &User, input: &ApiTokenInput) -> Result<(), ApiError> {
+    if input.name.trim().is_empty() {
+        return Err(ApiError::BadRequest("Name must not be empty".to_string()));
+    }
+    if input.scopes.is_empty() {
+        return Err(ApiError::BadRequest(format!("Scopes must not be empty (among {})", auth::TOKEN_SCOPES.join(", "))));
+    }
+    if let Some(scope) = input.scopes.iter().find(|s| !auth::TOKEN_SCOPES.contains(&s.as_str())) {
+        return Err(ApiError::BadRequest(format!("Unknown scope {} (among {})", scope, auth::TOKEN_SCOPES.join(", "))));
+    }
+    if !auth::can_manage(ds, user) && input.scopes.iter().any(|s| s == "admin") {
+        return Err(ApiError::Forbidden);
+    }
+    if input.expires_at.is_some_and(|at| at <= chrono::Utc::now().timestamp_millis()) {
+        return Err(ApiError::BadRequest("Expiry must be in the future".to_string()));
+    }
+    Ok(())
+}
+
+// Personal API tokens of the caller
+pub async fn list_tokens(Extension(ds): Extension<Arc<Datasources>>,
+                         CurrentUser(user): CurrentUser) -> Result<JsonApiResult<Vec<ApiToken>>, ApiError> {
+    Ok(json_content(StatusCode::OK, ds.db().get_api_tokens(user.id)?))
+}
+
+pub async fn create_token(Extension(ds): Extension<Arc<Datasources>>,
+                          CurrentUser(user): CurrentUser,
+                          Json(mut input): Json<ApiTokenInput>) -> Result<JsonApiResult<NewApiToken>, ApiError> {
+    validate_token(&ds, &user, &input)?;
+    input.scopes.sort_unstable();
+    input.scopes.dedup();
+    let token = format!("{}{}", auth::API_TOKEN_PREFIX, auth::random_token());
+    let info = ds.db().create_api_token(user.id, input.name.trim(), &auth::hash_api_key(&token), &input.scopes, input.expires_at)?;
+    info!("User {} created API token {} ({})", user.name, info.id, info.scopes.join(" "));
+    Ok(json_content(StatusCode::CREATED, NewApiToken { token, info }))
+}
+
+pub async fn delete_token(Extension(ds): Extension<Arc<Datasources>>,
+                          CurrentUser(user): CurrentUser,
+                          Path(id): Path<u32>) -> Result<StatusCode, ApiError> {
+    if !ds.db().delete_api_token(user.id, id)? {
+        return Err(ApiError::NotFound(format!("Token {} not found", id)));
+    }
+    info!("User {} revoked API token {}", user.name, id);
+    Ok(StatusCode::NO_CONTENT)
+}
+
+// Give a user another role. Admins cannot change their own, so there is always one left.
+pub async fn set_user_role(Extension(ds): Extension<Arc<Datasources>>,
+                           AdminUser(admin): AdminUser,
+                           Path(id): Path<u32>,
+                           Json(input): Json<RoleInput>) -> Result<JsonApiResult<User>, ApiError> {
+    if id == admin.id {
+        return Err(ApiError::BadRequest("You cannot change your own role".to_string()));
+    }
+    match ds.db().set_user_role(id, input.role)? {
+        Some(user) => {
+            info!("User {} made {} {}", admin.name, user.name, input.role.as_stored());
+            Ok(json_content(StatusCode::OK, user))
+        },
+        None => Err(ApiError::NotFound(format!("User {} not found", id))),
+    }
+}
+
+fn validate_profile(input: &ProfileInput) -> Result<(), ApiError> {
+    if input.display_name.as_ref().is_some_and(|name| name.chars().count() > constants::PROFILE_NAME_LENGTH) {
+        return Err(ApiError::BadRequest(format!("Display name is limited to {} characters", constants::PROFILE_NAME_LENGTH)));
+    }
+    if input.bio.chars().count() > constants::PROFILE_BIO_LENGTH {
+        return Err(ApiError::BadRequest(format!("Bio is limited to {} characters", constants::PROFILE_BIO_LENGTH)));
+    }
+    if input.links.len() > constants::PROFILE_LINKS {
+        return Err(ApiError::BadRequest(format!("Profiles are limited to {} links", constants::PROFILE_LINKS)));
+    }
+    for link in &input.links {
+        if link.title.trim().is_empty() {
+            return Err(ApiError::BadRequest("Link title must not be empty".to_string()));
+        }
+        if !(link.url.starts_with("https://") || link.url.starts_with("http://")) {
+            return Err(ApiError::BadRequest(format!("Link {} must be an http(s) URL", link.url)));
+        }
+    }
+    Ok(())
+}
+
+// Profile of the caller, shown on their author page
+pub async fn get_profile(Extension(ds): Extension<Arc<Datasources>>,
+                         CurrentUser(user): CurrentUser) -> Result<JsonApiResult<Profile>, ApiError> {
+    let profile = ds.db().get_profile_by_id(user.id)?
+        .ok_or_else(|| ApiError::NotFound(format!("User {} not found", user.id)))?;
+    Ok(json_content(StatusCode::OK, profile))
+}
+
+pub async fn update_profile(Extension(ds): Extension<Arc<Datasources>>,
+                            CurrentUser(user): CurrentUser,
+                            Json(input): Json<ProfileInput>) -> Result<JsonApiResult<Profile>, ApiError> {
+    validate_profile(&input)?;
+    let profile = ds.db().set_profile(user.id, &input)?
+        .ok_or_else(|| ApiError::NotFound(format!("User {} not found", user.id)))?;
+    info!("User {} updated their profile", user.name);
+    Ok(json_content(StatusCode::OK, profile))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::response::IntoResponse;
+
+    fn helper_input(title: &str, slug: Option<&str>) -> PostInput {
+        PostInput {
+            title: title.to_string(),
+            content: String::new(),
+            tags: None,
+            slug: slug.map(str::to_string),
+            status: None,
+            published_at: None,
+        }
+    }
+
+    // The admin of Datasources::for_tests
+    fn helper_admin(ds: &Datasources) -> CurrentUser {
+        CurrentUser(ds.db().get_users().unwrap().remove(0))
+    }
+
+    #[tokio::test]
+    async fn test_taken_slug() {
+        let ds = Arc::new(Datasources::for_tests());
+        let (status, _, first) = create_post(Extension(ds.clone()), helper_admin(&ds), Json(helper_input("First", Some("Mine"))))
+            .await.unwrap();
+        assert_eq!((status, first.0.ident.slug.as_str()), (StatusCode::CREATED, "mine"));
+
+        let taken = create_post(Extension(ds.clone()), helper_admin(&ds), Json(helper_input("Second", Some("mine")))).await;
+        assert_eq!(taken.err().unwrap().into_response().status(), StatusCode::CONFLICT);
+        let (_, _, second) = create_post(Extension(ds.clone()), helper_admin(&ds), Json(helper_input("Second", None)))
+            .await.unwrap();
+        let taken = update_post(Extension(ds.clone()), helper_admin(&ds), Path(second.0.ident.id),
+                                Json(helper_input("Second", Some("mine")))).await;
+        assert_eq!(taken.err().unwrap().into_response().status(), StatusCode::CONFLICT);
+
+        // A post keeps its own slug
+        let (status, _, _) = update_post(Extension(ds.clone()), helper_admin(&ds), Path(first.0.ident.id),
+                                         Json(helper_input("First", Some("mine")))).await.unwrap();
+        assert_eq!(status, StatusCode::OK);
+    }
+}
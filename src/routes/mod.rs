@@ -0,0 +1,623 @@
+use axum::{
+    extract::{Extension, Path, Query, Json, ConnectInfo},
+    response::{Html, IntoResponse, Json as JsonResponse},
+    http::{header, HeaderName, StatusCode, HeaderMap, HeaderValue},
+};
+use std::sync::Arc; // For shared state
+use std::net::SocketAddr; // For ConnectInfo
+
+pub mod api;
+pub mod account;
+
+use super::constants;
+use super::feed;
+use super::markdown;
+use super::sitemap;
+use super::sanitize::Trust;
+use super::data::Datasources;
+use super::data::solver::compute;
+use super::data::slug;
+use super::entity::{Post, PostIdent, PostStatus, SearchResult, CategoryResult, Category};
+
+use log::debug;
+use serde::Deserialize;
+use serde_json::json;
+
+
+// Define a helper type for Axum responses with HTML content type
+type HtmlResponse = (HeaderMap, Html<String>);
+
+fn html_content(body: String) -> HtmlResponse {
+    let mut headers = HeaderMap::new();
+    headers.insert(axum::http::header::CONTENT_TYPE, HeaderValue::from_static(constants::HTML_CONTENT_TYPE));
+    (headers, Html(body))
+}
+
+// Define a helper type for Axum responses with JSON content type
+type JsonApiResult<T> = (StatusCode, HeaderMap, JsonResponse<T>);
+
+fn json_content<T: serde::Serialize>(status: StatusCode, data: T) -> JsonApiResult<T> {
+    let mut headers = HeaderMap::new();
+    headers.insert(axum::http::header::CONTENT_TYPE, HeaderValue::from_static(constants::JSON_CONTENT_TYPE));
+    (status, headers, JsonResponse(data))
+}
+
+// Page from the render cache, rendered and stored on a miss (errors are not stored)
+fn cached_page<E>(ds: &Datasources, key: String, render: impl FnOnce() -> Result<String, E>) -> Result<String, E> {
+    let version = match ds.db().content_version() {
+        Ok(version) => version,
+        Err(e) => {
+            debug!("Failed to get the content version: {}", e);
+            return render();
+        }
+    };
+    if let Some(body) = ds.cache().get(&key, version) {
+        return Ok(body);
+    }
+    let body = render()?;
+    ds.cache().insert(key, version, body.clone());
+    Ok(body)
+}
+
+// TODO add proper error handling using Axum's IntoResponse for custom error types
+
+// Removed #[get("/")] macro
+pub async fn home(Extension(ds): Extension<Arc<Datasources>>) -> HtmlResponse {
+    // Clone config data to ensure correct lifetimes for json! macro
+    let site_name = &ds.conf().site_domain;
+    let data = json!({
+        "site_name": site_name,
+    });
+    // Render first, then create response
+    match ds.handlebars().render("home", &data) {
+        Ok(body) => html_content(body),
+        Err(e) => {
+            // Log error, return error message in response
+            log::error!("Handlebars render error (home): {}", e);
+            html_content(format!("Template error: {}", e))
+        }
+    }
+}
+
+// Removed #[get("/apps")] macro
+pub async fn apps(Extension(ds): Extension<Arc<Datasources>>) -> HtmlResponse {
+    // Clone config data
+    let github_handle = ds.conf().author_github_name.clone();
+    let data = json!({
+        "apps": {
+            "chatapp": "/chatapp" // Assuming this is still relevant
+        },
+        "github_handle": github_handle
+    });
+    // Render first, then create response
+    match ds.handlebars().render("apps", &data) {
+        Ok(body) => html_content(body),
+        Err(e) => {
+            log::error!("Handlebars render error (apps): {}", e);
+            html_content(format!("Template error: {}", e))
+        }
+    }
+}
+
+// Removed #[get("/about")] macro
+pub async fn about(Extension(ds): Extension<Arc<Datasources>>) -> HtmlResponse {
+    let full_name = &ds.conf().site_author;
+    let data = json!({
+        "full_name": full_name
+    });
+    // Render first, then create response
+    match ds.handlebars().render("about", &data) {
+        Ok(body) => html_content(body),
+        Err(e) => {
+            log::error!("Handlebars render error (about): {}", e);
+            html_content(format!("Template error: {}", e))
+        }
+    }
+}
+
+// Removed #[get("/contact")] macro
+pub async fn contact(Extension(ds): Extension<Arc<Datasources>>) -> HtmlResponse {
+    // Clone config data
+    let email = &ds.conf().author_email;
+    let twitter_handle = &ds.conf().author_twitter;
+    let data = json!({
+        "email": email,
+        "twitter_handle": twitter_handle
+    });
+    // Render first, then create response
+    match ds.handlebars().render("contact", &data) {
+        Ok(body) => html_content(body),
+        Err(e) => {
+            log::error!("Handlebars render error (contact): {}", e);
+            html_content(format!("Template error: {}", e))
+        }
+    }
+}
+
+fn render_post(ds: &Datasources, post_data: &Post, preview: bool) -> Result<String, (StatusCode, String)> {
+    let rendered = ds.markdown().render_post(&post_data.content, Trust::of_post(post_data.trusted_html));
+    // Long posts without a [TOC] marker show the table of contents beside the content
+    let toc_sidebar = !rendered.toc_in_content && rendered.headings >= constants::TOC_MIN_HEADINGS;
+    // Suggestions are not worth failing the page for
+    let related = ds.db().get_related_posts(post_data.ident.id, constants::RELATED_POSTS)
+        .unwrap_or_else(|e| {
+            log::error!("Failed to get the related posts of {}: {}", post_data.ident.id, e);
+            Vec::new()
+        });
+    let series = ds.db().get_post_series(post_data.ident.id).unwrap_or_else(|e| {
+        log::error!("Failed to get the series of {}: {}", post_data.ident.id, e);
+        None
+    });
+    let html = markdown::responsive_images(&rendered.html, |file_name| {
+        ds.db().get_media_by_file_name(file_name).unwrap_or_else(|e| {
+            log::error!("Failed to get media {}: {}", file_name, e);
+            None
+        })
+    });
+    let template_data = json!({
+      "raw_post": html,
+      "toc": rendered.toc,
+      "toc_sidebar": toc_sidebar,
+      "title": &post_data.ident.title,
+      "author": post_data.author.as_ref().map(|author| json!({
+          "name": author.name,
+          "url": format!("/authors/{}", slug::encode(&author.username)),
+      })),
+      "created": post_data.ident.created,
+      "published": post_data.ident.published,
+      "updated": post_data.updated,
+      "tags": &post_data.ident.tags,
+      "reading_minutes": post_data.ident.summary.reading_minutes,
+      "related": related,
+      "series": series,
+      "preview": preview,
+      "status": post_data.status
+    });
+    // Render first, then create response
+    match ds.handlebars().render("single_post", &template_data) {
+        Ok(body) => Ok(body),
+        Err(e) => {
+            log::error!("Handlebars render error (single_post): {}", e);
+            // Return error status + message
+            Err((StatusCode::INTERNAL_SERVER_ERROR, format!("Template error: {}", e)))
+        }
+    }
+}
+
+pub async fn post_raw(Extension(ds): Extension<Arc<Datasources>>, Path(post_slug): Path<String>) -> impl IntoResponse {
+    // The post is only loaded on a cache miss
+    cached_page(&ds, format!("/notes/{}", post_slug), || {
+        match ds.db().get_post_by_slug(&post_slug) {
+            Some(post_data) => render_post(&ds, &post_data, false),
+            // Use tuple for simple status + body response
+            None => Err((StatusCode::NOT_FOUND, "Post Not Found".to_string())),
+        }
+    }).map(html_content)
+}
+
+// Drafts and scheduled posts can be read by whoever has the secret preview URL
+pub async fn post_preview(Extension(ds): Extension<Arc<Datasources>>, Path(token): Path<String>) -> impl IntoResponse {
+    match ds.db().get_post_by_preview_token(&token) {
+        Some(post_data) => {
+            let page = html_content(render_post(&ds, &post_data, true)?);
+            // Keep previews out of search engines
+            Ok(([(HeaderName::from_static("x-robots-tag"), "noindex, nofollow")], page))
+        },
+        None => Err((StatusCode::NOT_FOUND, "Post Not Found".to_string())),
+    }
+}
+
+// Old numeric URLs permanently redirect to the slug URL
+pub async fn post_by_id(Extension(ds): Extension<Arc<Datasources>>, Path(id): Path<u32>) -> impl IntoResponse {
+    match ds.db().get_post_by_id(id) {
+        Some(post_data) if post_data.status == PostStatus::Published => {
+            let location = format!("/notes/{}", slug::encode(&post_data.ident.slug));
+            Ok((StatusCode::MOVED_PERMANENTLY, [(header::LOCATION, location)]))
+        },
+        _ => Err((StatusCode::NOT_FOUND, "Post Not Found".to_string())),
+    }
+}
+
+#[derive(Deserialize)]
+pub struct PageParams {
+    page: Option<u32>,
+}
+
+// One page of a listing, numbered from 1
+struct Page {
+    number: u32,
+    count: u32,
+}
+
+impl Page {
+    // None when the requested page is past the end (an empty listing still has page 1)
+    fn new(requested: Option<u32>, total: u32) -> Option<Page> {
+        let number = requested.unwrap_or(1).max(1);
+        let count = total.div_ceil(constants::NOTES_PAGE_SIZE).max(1);
+        if number > count {
+            None
+        } else {
+            Some(Page { number, count })
+        }
+    }
+
+    fn limit(&self) -> i32 {
+        constants::NOTES_PAGE_SIZE as i32
+    }
+
+    fn offset(&self) -> i32 {
+        ((self.number - 1) * constants::NOTES_PAGE_SIZE) as i32
+    }
+
+    fn url(base: &str, number: u32) -> String {
+        if number == 1 {
+            base.to_string()
+        } else {
+            format!("{}?page={}", base, number)
+        }
+    }
+
+    // Template data for the pagination partial
+    fn to_json(&self, base: &str) -> serde_json::Value {
+        json!({
+            "current": self.number,
+            "count": self.count,
+            "multiple": self.count > 1,
+            "prev": if self.number > 1 { Some(Self::url(base, self.number - 1)) } else { None },
+            "next": if self.number < self.count { Some(Self::url(base, self.number + 1)) } else { None },
+        })
+    }
+}
+
+pub async fn notes(Extension(ds): Extension<Arc<Datasources>>, Query(params): Query<PageParams>) -> impl IntoResponse {
+    let key = format!("/notes?page={}", params.page.unwrap_or(1));
+    cached_page(&ds, key, || notes_page(&ds, &params)).map(html_content)
+}
+
+fn notes_page(ds: &Datasources, params: &PageParams) -> Result<String, (StatusCode, String)> {
+    // Keeping sync DB call for now
+    let total = ds.db().count_posts().unwrap_or_else(|e| {
+        debug!("Failed to count posts: {}", e);
+        0
+    });
+    let page = Page::new(params.page, total).ok_or((StatusCode::NOT_FOUND, "Page Not Found".to_string()))?;
+    let posts: Vec<PostIdent> = ds.db().get_posts(page.limit(), page.offset()).unwrap_or_else(|e| {
+        debug!("Failed to get posts: {}", e);
+        Vec::new()
+    });
+    let data = json!({
+        "posts": &posts, // Reference is okay here as `posts` lives long enough
+        "pagination": page.to_json("/notes")
+    });
+    // Render first, then create response
+    match ds.handlebars().render("notes", &data) {
+        Ok(body) => Ok(body),
+        Err(e) => {
+            log::error!("Handlebars render error (notes): {}", e);
+            Err((StatusCode::INTERNAL_SERVER_ERROR, format!("Template error: {}", e)))
+        }
+    }
+}
+
+pub async fn notes_by_tag(Extension(ds): Extension<Arc<Datasources>>,
+                          Path(tag): Path<String>,
+                          Query(params): Query<PageParams>) -> impl IntoResponse {
+    let key = format!("/notes/tag/{}?page={}", tag, params.page.unwrap_or(1));
+    cached_page(&ds, key, || tag_page(&ds, &tag, &params)).map(html_content)
+}
+
+fn tag_page(ds: &Datasources, tag: &str, params: &PageParams) -> Result<String, (StatusCode, String)> {
+    let total = ds.db().count_posts_by_tag(tag).unwrap_or_else(|e| {
+        debug!("Failed to count posts for tag {}: {}", tag, e);
+        0
+    });
+    if total == 0 {
+        return Err((StatusCode::NOT_FOUND, "Tag Not Found".to_string()));
+    }
+    let page = Page::new(params.page, total).ok_or((StatusCode::NOT_FOUND, "Page Not Found".to_string()))?;
+    let posts: Vec<PostIdent> = ds.db().get_posts_by_tag(tag, page.limit(), page.offset()).unwrap_or_else(|e| {
+        debug!("Failed to get posts for tag {}: {}", tag, e);
+        Vec::new()
+    });
+    let data = json!({
+        "tag": tag,
+        "posts": &posts,
+        "pagination": page.to_json(&format!("/notes/tag/{}", slug::encode(tag)))
+    });
+    match ds.handlebars().render("tag", &data) {
+        Ok(body) => Ok(body),
+        Err(e) => {
+            log::error!("Handlebars render error (tag): {}", e);
+            Err((StatusCode::INTERNAL_SERVER_ERROR, format!("Template error: {}", e)))
+        }
+    }
+}
+
+pub async fn author(Extension(ds): Extension<Arc<Datasources>>,
+                    Path(username): Path<String>,
+                    Query(params): Query<PageParams>) -> impl IntoResponse {
+    let key = format!("/authors/{}?page={}", username, params.page.unwrap_or(1));
+    cached_page(&ds, key, || author_page(&ds, &username, &params)).map(html_content)
+}
+
+// Only users with published notes have a page, the others are not listed anywhere
+fn author_page(ds: &Datasources, username: &str, params: &PageParams) -> Result<String, (StatusCode, String)> {
+    let not_found = || (StatusCode::NOT_FOUND, "Author Not Found".to_string());
+    let profile = ds.db().get_profile(username).unwrap_or_else(|e| {
+        debug!("Failed to get the profile of {}: {}", username, e);
+        None
+    }).ok_or_else(not_found)?;
+    let total = ds.db().count_posts_by_author(profile.id).unwrap_or_else(|e| {
+        debug!("Failed to count posts of {}: {}", username, e);
+        0
+    });
+    if total == 0 {
+        return Err(not_found());
+    }
+    let page = Page::new(params.page, total).ok_or((StatusCode::NOT_FOUND, "Page Not Found".to_string()))?;
+    let posts: Vec<PostIdent> = ds.db().get_posts_by_author(profile.id, page.limit(), page.offset()).unwrap_or_else(|e| {
+        debug!("Failed to get posts of {}: {}", username, e);
+        Vec::new()
+    });
+    let data = json!({
+        "name": profile.name,
+        "username": profile.username,
+        "bio": ds.markdown().to_html(&profile.bio, Trust::Author),
+        "links": profile.links,
+        "posts": &posts,
+        "pagination": page.to_json(&format!("/authors/{}", slug::encode(username)))
+    });
+    match ds.handlebars().render("author", &data) {
+        Ok(body) => Ok(body),
+        Err(e) => {
+            log::error!("Handlebars render error (author): {}", e);
+            Err((StatusCode::INTERNAL_SERVER_ERROR, format!("Template error: {}", e)))
+        }
+    }
+}
+
+pub async fn series(Extension(ds): Extension<Arc<Datasources>>, Path(series_slug): Path<String>) -> impl IntoResponse {
+    cached_page(&ds, format!("/notes/series/{}", series_slug), || series_page(&ds, &series_slug)).map(html_content)
+}
+
+fn series_page(ds: &Datasources, series_slug: &str) -> Result<String, (StatusCode, String)> {
+    let not_found = || (StatusCode::NOT_FOUND, "Series Not Found".to_string());
+    let series = ds.db().get_series_by_slug(series_slug).unwrap_or_else(|e| {
+        debug!("Failed to get series {}: {}", series_slug, e);
+        None
+    }).ok_or_else(not_found)?;
+    let parts = ds.db().get_series_parts(series.id).unwrap_or_else(|e| {
+        debug!("Failed to get the parts of series {}: {}", series_slug, e);
+        Vec::new()
+    });
+    // Nothing to read yet
+    if parts.is_empty() {
+        return Err(not_found());
+    }
+    let data = json!({
+        "title": series.title,
+        "description": series.description,
+        "parts": parts
+    });
+    match ds.handlebars().render("series", &data) {
+        Ok(body) => Ok(body),
+        Err(e) => {
+            log::error!("Handlebars render error (series): {}", e);
+            Err((StatusCode::INTERNAL_SERVER_ERROR, format!("Template error: {}", e)))
+        }
+    }
+}
+
+fn month_name(month: u32) -> &'static str {
+    u8::try_from(month).ok()
+        .and_then(|m| chrono::Month::try_from(m).ok())
+        .map_or("", |m| m.name())
+}
+
+pub async fn archive(Extension(ds): Extension<Arc<Datasources>>) -> HtmlResponse {
+    let page = cached_page(&ds, "/notes/archive".to_string(), || archive_page(&ds));
+    html_content(page.unwrap_or_else(|e| e))
+}
+
+// Errors are the message shown in the page
+fn archive_page(ds: &Datasources) -> Result<String, String> {
+    let periods = ds.db().get_archive().unwrap_or_else(|e| {
+        debug!("Failed to get archive: {}", e);
+        Vec::new()
+    });
+    // Group the months by year, keeping the newest first order
+    let mut years: Vec<serde_json::Value> = Vec::new();
+    for (year, months) in periods.chunk_by(|a, b| a.year == b.year).map(|c| (c[0].year, c)) {
+        years.push(json!({
+            "year": year,
+            "count": months.iter().map(|m| m.count).sum::<u32>(),
+            "months": months.iter().map(|m| json!({
+                "name": month_name(m.month),
+                "count": m.count,
+                "url": format!("/notes/archive/{}/{:02}", m.year, m.month),
+            })).collect::<Vec<serde_json::Value>>(),
+        }));
+    }
+    let data = json!({
+        "years": years
+    });
+    match ds.handlebars().render("archive", &data) {
+        Ok(body) => Ok(body),
+        Err(e) => {
+            log::error!("Handlebars render error (archive): {}", e);
+            Err(format!("Template error: {}", e))
+        }
+    }
+}
+
+pub async fn archive_month(Extension(ds): Extension<Arc<Datasources>>,
+                           Path((year, month)): Path<(i32, u32)>,
+                           Query(params): Query<PageParams>) -> impl IntoResponse {
+    let key = format!("/notes/archive/{}/{:02}?page={}", year, month, params.page.unwrap_or(1));
+    cached_page(&ds, key, || archive_month_page(&ds, year, month, &params)).map(html_content)
+}
+
+fn archive_month_page(ds: &Datasources, year: i32, month: u32, params: &PageParams) -> Result<String, (StatusCode, String)> {
+    let total = ds.db().get_archive().unwrap_or_else(|e| {
+        debug!("Failed to get archive: {}", e);
+        Vec::new()
+    }).iter().find(|p| p.year == year && p.month == month).map_or(0, |p| p.count);
+    if total == 0 {
+        return Err((StatusCode::NOT_FOUND, "No Notes For This Month".to_string()));
+    }
+    let page = Page::new(params.page, total).ok_or((StatusCode::NOT_FOUND, "Page Not Found".to_string()))?;
+    let posts: Vec<PostIdent> = ds.db().get_posts_by_month(year, month, page.limit(), page.offset()).unwrap_or_else(|e| {
+        debug!("Failed to get posts for {}-{}: {}", year, month, e);
+        Vec::new()
+    });
+    let data = json!({
+        "period": format!("{} {}", month_name(month), year),
+        "posts": &posts,
+        "pagination": page.to_json(&format!("/notes/archive/{}/{:02}", year, month))
+    });
+    match ds.handlebars().render("archive_month", &data) {
+        Ok(body) => Ok(body),
+        Err(e) => {
+            log::error!("Handlebars render error (archive_month): {}", e);
+            Err((StatusCode::INTERNAL_SERVER_ERROR, format!("Template error: {}", e)))
+        }
+    }
+}
+
+// Tag cloud weight from 1 (least used) to 5 (most used)
+fn tag_weight(count: u32, max_count: u32) -> u32 {
+    if max_count <= 1 {
+        1
+    } else {
+        1 + (count.max(1) - 1) * 4 / (max_count - 1)
+    }
+}
+
+pub async fn tags(Extension(ds): Extension<Arc<Datasources>>) -> HtmlResponse {
+    let page = cached_page(&ds, "/notes/tags".to_string(), || tags_page(&ds));
+    html_content(page.unwrap_or_else(|e| e))
+}
+
+// Errors are the message shown in the page
+fn tags_page(ds: &Datasources) -> Result<String, String> {
+    let tags = ds.db().get_tags().unwrap_or_else(|e| {
+        debug!("Failed to get tags: {}", e);
+        Vec::new()
+    });
+    // Scale the counts to a weight from 1 to 5 for the tag cloud
+    let max_count = tags.iter().map(|t| t.count).max().unwrap_or(1);
+    let cloud: Vec<serde_json::Value> = tags.iter().map(|t| json!({
+        "name": &t.name,
+        "count": t.count,
+        "weight": tag_weight(t.count, max_count),
+    })).collect();
+    let data = json!({
+        "tags": cloud
+    });
+    match ds.handlebars().render("tags", &data) {
+        Ok(body) => Ok(body),
+        Err(e) => {
+            log::error!("Handlebars render error (tags): {}", e);
+            Err(format!("Template error: {}", e))
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub struct SearchParams {
+    q: Option<String>,
+}
+
+fn search_results(ds: &Datasources, query: &str) -> Vec<SearchResult> {
+    ds.db().search_posts(query, constants::SEARCH_LIMIT).unwrap_or_else(|e| {
+        debug!("Failed to search posts for {}: {}", query, e);
+        Vec::new()
+    })
+}
+
+pub async fn search(Extension(ds): Extension<Arc<Datasources>>, Query(params): Query<SearchParams>) -> HtmlResponse {
+    let query = params.q.unwrap_or_default();
+    let results = search_results(&ds, &query);
+    let data = json!({
+        "query": query.trim(),
+        "results": &results
+    });
+    match ds.handlebars().render("search", &data) {
+        Ok(body) => html_content(body),
+        Err(e) => {
+            log::error!("Handlebars render error (search): {}", e);
+            html_content(format!("Template error: {}", e))
+        }
+    }
+}
+
+pub async fn search_json(Extension(ds): Extension<Arc<Datasources>>, Query(params): Query<SearchParams>) -> JsonApiResult<Vec<SearchResult>> {
+    let query = params.q.unwrap_or_default();
+    json_content(StatusCode::OK, search_results(&ds, &query))
+}
+
+// Same as cached_page for the documents that can't fail
+fn cached(ds: &Datasources, key: &str, render: impl FnOnce() -> String) -> String {
+    cached_page(ds, key.to_string(), || Ok::<String, ()>(render())).unwrap_or_default()
+}
+
+fn feed_posts(ds: &Datasources) -> Vec<Post> {
+    ds.db().get_recent_posts(feed::FEED_SIZE).unwrap_or_else(|e| {
+        debug!("Failed to get posts for feed: {}", e);
+        Vec::new()
+    })
+}
+
+pub async fn feed_atom(Extension(ds): Extension<Arc<Datasources>>) -> impl IntoResponse {
+    let body = cached(&ds, "/notes/feed.atom", || feed::atom(ds.conf(), ds.markdown(), &feed_posts(&ds)));
+    ([(header::CONTENT_TYPE, constants::ATOM_CONTENT_TYPE)], body)
+}
+
+pub async fn feed_rss(Extension(ds): Extension<Arc<Datasources>>) -> impl IntoResponse {
+    let body = cached(&ds, "/notes/feed.rss", || feed::rss(ds.conf(), ds.markdown(), &feed_posts(&ds)));
+    ([(header::CONTENT_TYPE, constants::RSS_CONTENT_TYPE)], body)
+}
+
+pub async fn feed_json(Extension(ds): Extension<Arc<Datasources>>) -> impl IntoResponse {
+    let body = cached(&ds, "/notes/feed.json", || feed::json_feed(ds.conf(), ds.markdown(), &feed_posts(&ds)).to_string());
+    ([(header::CONTENT_TYPE, constants::JSON_FEED_CONTENT_TYPE)], body)
+}
+
+pub async fn sitemap(Extension(ds): Extension<Arc<Datasources>>) -> impl IntoResponse {
+    let body = cached(&ds, "/sitemap.xml", || {
+        let posts = ds.db().get_post_updates().unwrap_or_else(|e| {
+            debug!("Failed to get posts for sitemap: {}", e);
+            Vec::new()
+        });
+        sitemap::sitemap(ds.conf(), &posts)
+    });
+    ([(header::CONTENT_TYPE, constants::XML_CONTENT_TYPE)], body)
+}
+
+pub async fn highlight_css(Extension(ds): Extension<Arc<Datasources>>) -> impl IntoResponse {
+    ([(header::CONTENT_TYPE, constants::CSS_CONTENT_TYPE)], ds.markdown().highlighter().css().to_string())
+}
+
+pub async fn robots(Extension(ds): Extension<Arc<Datasources>>) -> impl IntoResponse {
+    ([(header::CONTENT_TYPE, constants::TEXT_CONTENT_TYPE)], sitemap::robots(ds.conf()))
+}
+
+// Use ConnectInfo extractor for client address and HeaderMap
+pub async fn whatsmyip(ConnectInfo(addr): ConnectInfo<SocketAddr>, headers: HeaderMap) -> impl IntoResponse {
+    // Axum provides the client socket address directly via ConnectInfo
+    // Check for X-Real-IP header first
+    let ip_to_return = headers
+        .get("X-Real-IP")
+        .and_then(|hv| hv.to_str().ok())
+        .map(|s| s.to_string()) // Convert valid header to String
+        .unwrap_or_else(|| addr.ip().to_string()); // Fallback to peer IP
+
+    (StatusCode::OK, ip_to_return)
+}
+
+// Updated signature: Json extractor, returns Json response with Vec<Category>
+pub async fn solve(Json(payload): Json<Vec<CategoryResult>>) -> JsonResponse<Vec<Category>> {
+    // Assuming compute is a sync function for now
+    let res = compute(payload);
+    JsonResponse(res)
+}
@@ -0,0 +1,159 @@
+use std::borrow::Cow;
+use std::collections::HashSet;
+
+use ammonia::Builder;
+
+// How much the HTML of a post is trusted, from who last wrote it
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Trust {
+    Admin,
+    Author,
+}
+
+impl Trust {
+    pub fn of_post(trusted_html: bool) -> Self {
+        if trusted_html { Trust::Admin } else { Trust::Author }
+    }
+}
+
+// Tags produced by the markdown extensions, added to ammonia's defaults
+const MARKDOWN_TAGS: &[&str] = &["aside", "figure", "figcaption", "nav", "input"];
+
+// Classes set by the renderer (headings, table of contents, admonitions and footnotes).
+// Posts can't use the other classes of the site to look like its pages.
+const CLASSES: &[&str] = &["anchor", "toc", "admonition", "footnote-reference", "footnote-definition", "footnote-definition-label"];
+// Highlighted code, code block languages and admonition kinds
+const CLASS_PREFIXES: &[&str] = &["hl-", "language-", "admonition-"];
+
+// Prepended to every id of a post (and the links to them), so it can't clash with the ids of
+// the page or shadow the globals of its scripts
+const ID_PREFIX: &str = "note-";
+
+// Attributes kept on the tags that can be allowed from the config
+const EMBED_ATTRIBUTES: &[(&str, &[&str])] = &[
+    ("iframe", &["src", "width", "height", "title", "allow", "allowfullscreen", "frameborder", "loading"]),
+    ("video", &["src", "width", "height", "controls", "poster", "loop", "muted"]),
+    ("audio", &["src", "controls", "loop"]),
+    ("source", &["src", "type"]),
+];
+
+/**
+ * Allow-list of the HTML of the posts. Everything else (scripts, event handlers, styles...)
+ * is removed. Each trust level may allow more tags.
+ */
+pub struct Sanitizer {
+    admin_tags: Vec<String>,
+    author_tags: Vec<String>,
+}
+
+impl Sanitizer {
+    pub fn new(admin_tags: &[String], author_tags: &[String]) -> Self {
+        Self {
+            admin_tags: admin_tags.to_vec(),
+            author_tags: author_tags.to_vec(),
+        }
+    }
+
+    fn builder(&self, trust: Trust) -> Builder<'_> {
+        let extra_tags = match trust {
+            Trust::Admin => &self.admin_tags,
+            Trust::Author => &self.author_tags,
+        };
+        let mut builder = Builder::default();
+        builder
+            .add_tags(MARKDOWN_TAGS)
+            .add_tags(extra_tags)
+            // Headings, footnotes, highlighted code and the blocks of the extensions
+            .add_generic_attributes(&["id", "class"])
+            .attribute_filter(filter_attribute)
+            .add_tag_attributes("a", &["aria-label"])
+            .add_tag_attributes("input", &["type", "checked", "disabled"])
+            // Alignment of the table columns
+            .add_tag_attributes("th", &["style"])
+            .add_tag_attributes("td", &["style"])
+            .filter_style_properties(HashSet::from(["text-align"]))
+            // Links can't open new windows (target is not allowed), so rel is not needed
+            .link_rel(None);
+        for (tag, attributes) in EMBED_ATTRIBUTES {
+            if extra_tags.iter().any(|t| t == tag) {
+                builder.add_tag_attributes(tag, *attributes);
+            }
+        }
+        builder
+    }
+
+    pub fn clean(&self, html: &str, trust: Trust) -> String {
+        self.builder(trust).clean(html).to_string()
+    }
+}
+
+fn allowed_class(class: &str) -> bool {
+    CLASSES.contains(&class) || CLASS_PREFIXES.iter().any(|prefix| class.starts_with(prefix))
+}
+
+fn filter_attribute<'u>(element: &str, attribute: &str, value: &'u str) -> Option<Cow<'u, str>> {
+    match attribute {
+        "class" => {
+            let classes: Vec<&str> = value.split_ascii_whitespace().filter(|c| allowed_class(c)).collect();
+            if classes.is_empty() { None } else { Some(Cow::Owned(classes.join(" "))) }
+        },
+        "id" => Some(Cow::Owned(format!("{}{}", ID_PREFIX, value))),
+        "href" if element == "a" => match value.strip_prefix('#') {
+            Some(fragment) if !fragment.is_empty() => Some(Cow::Owned(format!("#{}{}", ID_PREFIX, fragment))),
+            _ => Some(Cow::Borrowed(value)),
+        },
+        _ => Some(Cow::Borrowed(value)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn helper_sanitizer() -> Sanitizer {
+        Sanitizer::new(&["iframe".to_string()], &[])
+    }
+
+    #[test]
+    fn test_scripts_removed() {
+        let sanitizer = helper_sanitizer();
+        for trust in [Trust::Admin, Trust::Author] {
+            assert_eq!(sanitizer.clean("<p onclick=\"alert(1)\">Hi<script>alert(1)</script></p>", trust), "<p>Hi</p>");
+            assert_eq!(sanitizer.clean("<a href=\"javascript:alert(1)\">x</a>", trust), "<a>x</a>");
+            assert_eq!(sanitizer.clean("<img src=\"x\" onerror=\"alert(1)\">", trust), "<img src=\"x\">");
+        }
+    }
+
+    #[test]
+    fn test_markdown_html_kept() {
+        let html = "<h2 id=\"note-a\">A <a class=\"anchor\" href=\"#note-a\" aria-label=\"Link to this section\">#</a></h2>\
+                    <aside class=\"admonition admonition-note\"><p class=\"admonition-title\">Note</p></aside>\
+                    <figure><img src=\"/public/a.png\" alt=\"A\"><figcaption>A</figcaption></figure>\
+                    <ul><li><input disabled=\"\" type=\"checkbox\" checked=\"\">done</li></ul>\
+                    <table><thead><tr><th style=\"text-align:center\">a</th></tr></thead></table>\
+                    <pre class=\"hl-code\"><code class=\"language-rust\"><span class=\"hl-source\">x</span></code></pre>";
+        assert_eq!(helper_sanitizer().clean(&html.replace("note-a", "a"), Trust::Author), html);
+        assert_eq!(helper_sanitizer().clean("<table><tr><td style=\"color: red; text-align: right\">1</td></tr></table>", Trust::Author),
+                   "<table><tbody><tr><td style=\"text-align:right\">1</td></tr></tbody></table>");
+    }
+
+    #[test]
+    fn test_iframes() {
+        let sanitizer = helper_sanitizer();
+        let iframe = "<iframe src=\"https://www.youtube.com/embed/x\" width=\"560\" allowfullscreen=\"\"></iframe>";
+        assert_eq!(sanitizer.clean(iframe, Trust::Admin), iframe);
+        assert_eq!(sanitizer.clean(iframe, Trust::Author), "");
+        assert_eq!(sanitizer.clean("<iframe src=\"javascript:alert(1)\" onload=\"alert(1)\"></iframe>", Trust::Admin), "<iframe></iframe>");
+    }
+
+    #[test]
+    fn test_classes_and_ids() {
+        let sanitizer = helper_sanitizer();
+        for trust in [Trust::Admin, Trust::Author] {
+            assert_eq!(sanitizer.clean("<div class=\"site-header admonition\" id=\"debug\">x</div>", trust),
+                       "<div class=\"admonition\" id=\"note-debug\">x</div>");
+            assert_eq!(sanitizer.clean("<p class=\"login\"><a href=\"#debug\">x</a> <a href=\"/notes#a\">y</a></p>", trust),
+                       "<p><a href=\"#note-debug\">x</a> <a href=\"/notes#a\">y</a></p>");
+        }
+    }
+}
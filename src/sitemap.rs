@@ -0,0 +1,97 @@
+use super::data::Config;
+use super::data::slug;
+use super::feed::{escape_xml, rfc3339, site_url};
+
+// Pages of the router that do not depend on the database
+pub const PAGES: &[&str] = &["/", "/apps", "/about", "/contact", "/notes", "/notes/tags", "/notes/archive"];
+
+// Pages whose content changes with every post
+const LISTINGS: &[&str] = &["/notes", "/notes/tags", "/notes/archive"];
+
+fn url_entry(out: &mut String, loc: &str, lastmod: Option<i64>) {
+    out.push_str("  <url>\n");
+    out.push_str(&format!("    <loc>{}</loc>\n", escape_xml(loc)));
+    if let Some(ms) = lastmod {
+        out.push_str(&format!("    <lastmod>{}</lastmod>\n", rfc3339(ms)));
+    }
+    out.push_str("  </url>\n");
+}
+
+/**
+ * Sitemap (https://www.sitemaps.org/protocol.html) of the static pages and the given posts,
+ * as (slug, last modification in ms) pairs
+ */
+pub fn sitemap(conf: &Config, posts: &[(String, i64)]) -> String {
+    let site = site_url(conf);
+    let last_updated = posts.iter().map(|(_, updated)| *updated).max();
+    let mut out = String::new();
+    out.push_str("<?xml version=\"1.0\" encoding=\"utf-8\"?>\n");
+    out.push_str("<urlset xmlns=\"http://www.sitemaps.org/schemas/sitemap/0.9\">\n");
+    for page in PAGES {
+        let lastmod = if LISTINGS.contains(page) { last_updated } else { None };
+        url_entry(&mut out, &format!("{}{}", site, page), lastmod);
+    }
+    for (post_slug, updated) in posts {
+        url_entry(&mut out, &format!("{}/notes/{}", site, slug::encode(post_slug)), Some(*updated));
+    }
+    out.push_str("</urlset>\n");
+    out
+}
+
+/**
+ * robots.txt pointing to the sitemap
+ */
+pub fn robots(conf: &Config) -> String {
+    let mut out = String::from("User-agent: *\n");
+    for path in &conf.robots_disallow {
+        out.push_str(&format!("Disallow: {}\n", path));
+    }
+    if conf.robots_disallow.is_empty() {
+        out.push_str("Disallow:\n");
+    }
+    out.push_str(&format!("\nSitemap: {}/sitemap.xml\n", site_url(conf)));
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn helper_conf() -> Config {
+        serde_json::from_value(json!({
+            "site_domain": "example.com",
+            "site_author": "John Titor",
+            "author_twitter": "john_titor",
+            "author_email": "john_titor@example.com",
+            "author_github_name": "john_titor",
+            "port": 3001,
+            "host": "0.0.0.0",
+            "db_file": ":memory:",
+            "static_files": "./external_files"
+        })).unwrap()
+    }
+
+    #[test]
+    fn test_sitemap() {
+        let posts = vec![("日本語".to_string(), 1_700_000_060_000), ("a&b".to_string(), 1_600_000_000_000)];
+        let doc = sitemap(&helper_conf(), &posts);
+        assert!(doc.contains("<loc>https://example.com/</loc>\n  </url>"));
+        assert!(doc.contains("<loc>https://example.com/notes</loc>\n    <lastmod>2023-11-14T22:14:20Z</lastmod>"));
+        assert!(doc.contains("<loc>https://example.com/notes/%E6%97%A5%E6%9C%AC%E8%AA%9E</loc>\n    <lastmod>2023-11-14T22:14:20Z</lastmod>"));
+        assert!(doc.contains("<loc>https://example.com/notes/a%26b</loc>\n    <lastmod>2020-09-13T12:26:40Z</lastmod>"));
+        assert_eq!(doc.matches("<url>").count(), PAGES.len() + 2);
+    }
+
+    #[test]
+    fn test_robots() {
+        let mut conf = helper_conf();
+        let doc = robots(&conf);
+        assert!(doc.contains("Disallow: /api/\n"));
+        assert!(doc.contains("Disallow: /notes/preview/\n"));
+        assert!(!doc.contains("/users"));
+        assert!(doc.ends_with("Sitemap: https://example.com/sitemap.xml\n"));
+        conf.robots_disallow.clear();
+        assert_eq!(robots(&conf), "User-agent: *\nDisallow:\n\nSitemap: https://example.com/sitemap.xml\n");
+    }
+}